@@ -0,0 +1,133 @@
+use std::{fs::File, io::BufReader, process::ExitCode};
+
+use quallocator::{
+    bump::BumpHeap,
+    mmap::MmapHeap,
+    placement::PlacementPolicy,
+    source::{mmap::MmapSource, sbrk::SbrkSource, virtual_break::VirtualBreakSource},
+    trace::{
+        read_trace,
+        replay::{ReplayHeap, ReplayReport, replay},
+    },
+};
+
+const USAGE: &str = "usage: qualloc-replay <trace> [--backend bump|virtual-bump|mmap] \
+[--policy first-fit|best-fit] [--stats-every <events>]";
+
+/*
+ * Options given in the command line
+ */
+struct Options {
+    trace: String,
+    backend: String,
+    policy: PlacementPolicy,
+    stats_every: usize,
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        trace: String::new(),
+        backend: "mmap".to_string(),
+        policy: PlacementPolicy::FirstFit,
+        stats_every: 1000,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+
+        match arg.as_str() {
+            "--backend" => options.backend = value()?,
+            "--policy" => {
+                options.policy = match value()?.as_str() {
+                    "first-fit" => PlacementPolicy::FirstFit,
+                    "best-fit" => PlacementPolicy::BestFit,
+                    policy => return Err(format!("unknown placement policy {policy}")),
+                }
+            }
+            "--stats-every" => {
+                options.stats_every = value()?
+                    .parse()
+                    .map_err(|_| "--stats-every needs a number".to_string())?
+            }
+            _ if options.trace.is_empty() && !arg.starts_with("--") => options.trace = arg,
+            _ => return Err(format!("unknown argument {arg}")),
+        }
+    }
+
+    if options.trace.is_empty() {
+        return Err("the trace file is missing".to_string());
+    }
+
+    Ok(options)
+}
+
+/*
+ * Gets the peak resident set size of this process in bytes
+ */
+fn peak_rss() -> usize {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+
+    /*
+     * Linux gives ru_maxrss in KiB and macOS in bytes
+     */
+    if cfg!(target_vendor = "apple") {
+        usage.ru_maxrss as usize
+    } else {
+        usage.ru_maxrss as usize * 1024
+    }
+}
+
+fn run_replay(heap: &impl ReplayHeap, options: &Options) -> Result<ReplayReport, String> {
+    let file = File::open(&options.trace).map_err(|err| format!("{}: {err}", options.trace))?;
+    let events =
+        read_trace(BufReader::new(file)).map_err(|err| format!("{}: {err}", options.trace))?;
+
+    heap.set_placement_policy(options.policy);
+
+    Ok(replay(&events, heap, options.stats_every))
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let report = match options.backend.as_str() {
+        "bump" => run_replay(&BumpHeap::new(SbrkSource {}), &options),
+        "virtual-bump" => run_replay(&BumpHeap::new(VirtualBreakSource::default()), &options),
+        "mmap" => run_replay(&MmapHeap::new(MmapSource {}), &options),
+        backend => Err(format!("unknown backend {backend}\n{USAGE}")),
+    };
+
+    let report = match report {
+        Ok(report) => report,
+        Err(message) => {
+            eprintln!("{message}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("backend: {} ({:?})", options.backend, options.policy);
+    println!(
+        "operations: {} allocations, {} frees, {} failed allocations",
+        report.allocations, report.frees, report.failed_allocations
+    );
+    println!("time: {:?}", report.elapsed);
+    println!("peak rss: {} bytes", peak_rss());
+    println!(
+        "heap: {} bytes at the end, {} bytes at the peak",
+        report.final_stats.heap_size, report.final_stats.peak_heap_size
+    );
+    println!(
+        "fragmentation: {:.3} at the end, {:.3} at most",
+        report.final_stats.fragmentation, report.max_fragmentation
+    );
+
+    ExitCode::SUCCESS
+}
@@ -1,46 +1,188 @@
-use std::sync::atomic::{AtomicPtr, Ordering};
+use std::{
+    io,
+    path::Path,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::{
+    call_site::{AllocatorScope, CallSiteStats},
+    canary::{OverflowHandler, OverflowReport, canary_block_size},
+    error::AllocError,
+    fork::register_fork_handlers,
+    free_check::{InvalidFree, InvalidFreeAction, InvalidFreeHandler, InvalidFreeKind},
+    hooks::{EventHook, EventKind, HeapEvent},
+    limits::AutoTuning,
+    oom::OomHandler,
+    placement::PlacementPolicy,
+    pressure::PressureHandler,
+    profile::{HeapProfile, ProfileFormat},
+    quarantine::UseAfterFreeHandler,
+    quota::QuotaKind,
+    source::{
+        MemorySource, sbrk::SbrkSource, system::SystemBreakSource,
+        virtual_break::VirtualBreakSource,
+    },
+    stats::Stats,
+    tags::{AllocTag, TagScope, TagStats, current_tag},
+    trace::{TraceOp, recorder::TraceRecorder},
+    tunables::{Tunable, load_env_options},
+    utils::check_alignment,
+    verify::HeapError,
+    walk::HeapEntry,
+};
 
 use super::{
+    BumpHeap, BumpHeapState, BumpMemoryBlockHeader,
     globals::bump_memory,
+    walk::{BumpHeapWalker, block_entry},
     utils::{allocate_block, deallocate_block, merge_adjacent_free_blocks},
 };
 
-pub struct BumpAllocator {}
-
-impl BumpAllocator {
+impl<S: MemorySource> BumpHeap<S> {
     /**
-     * Allocate memory on the heap using the bump allocator.
+     * Allocate memory on this heap using the bump allocator.
      *
      * @param size The size of the memory to allocate.
-     * @return A pointer to the allocated memory.
+     * @return A pointer to the allocated memory, or the reason why it can't be allocated.
      *
      * @note This function is thread-safe.
-     * @warning This function may return None if the system runs out of memory.
+     * @note When the heap runs out of memory, the emergency reserve is released and the OOM handler can
+     * ask for the allocation to be tried again.
      * @warning A generic type must be provided to ensure proper alignment
      * if the type isn't provided, the qualloc function will assume the type is ()
      */
-    pub fn qualloc<T>(size: i32) -> Option<*mut T> {
-        let mut memory_guard = bump_memory.lock().unwrap();
+    pub fn qualloc<T>(&self, size: i32) -> Result<*mut T, AllocError> {
+        let _allocator_scope = AllocatorScope::enter();
+        let mut attempt = 0;
+
+        loop {
+            match self.try_qualloc::<T>(size) {
+                Err(error) if error.is_out_of_memory() => {
+                    attempt += 1;
+
+                    if !self.handle_oom(error, size as usize, attempt) {
+                        return Err(error);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /**
+     * Allocate memory on this heap counted in the given tag instead of the tag of the current thread.
+     *
+     * @param tag The tag stored in the header of the block.
+     * @param size The size of the memory to allocate.
+     * @return A pointer to the allocated memory, or the reason why it can't be allocated.
+     */
+    pub fn qualloc_tagged<T>(&self, tag: AllocTag, size: i32) -> Result<*mut T, AllocError> {
+        let _allocator_scope = AllocatorScope::enter();
+        let _tag_scope = TagScope::enter(tag);
+
+        self.qualloc::<T>(size)
+    }
+
+    /**
+     * Allocates once, without calling the OOM handler when the heap runs out of memory
+     */
+    pub(super) fn try_qualloc<T>(&self, size: i32) -> Result<*mut T, AllocError> {
+        check_alignment::<T>()?;
+
+        let has_canaries = self.canaries.is_enabled();
+        let block_size = usize::try_from(size)
+            .ok()
+            .and_then(|size| canary_block_size(size, has_canaries))
+            .and_then(|block_size| i32::try_from(block_size).ok())
+            .ok_or(AllocError::SizeOverflow {
+                size: size as usize,
+            })?;
+        let tag = current_tag();
+
+        let usr_pointer = {
+            let mut memory_guard = self.memory.lock().unwrap();
+
+            self.verify_before_allocation(&memory_guard)?;
+            self.quota.check_user_bytes(size as usize)?;
+            self.tags.check(tag, size as usize)?;
+
+            let block = self.take_block::<T>(&mut memory_guard, block_size)?;
+            let usr_pointer = unsafe { block.add(1) as *mut T };
+
+            unsafe {
+                self.arm_canaries(block, size, has_canaries);
+                (*block).tag = tag;
+            }
+
+            self.counters.allocations.fetch_add(1, Ordering::Relaxed);
+            self.quota.record_allocation(size as usize);
+            self.tags.record_allocation(tag, size as usize);
+            self.free_checks.set_torn_down(false);
+            self.free_checks.record_allocation(usr_pointer as usize);
+            self.call_sites.record_allocation(usr_pointer as usize, size as usize);
+            self.profiler.record_allocation(usr_pointer as usize, size as usize);
+            self.verify_after_operation(memory_guard);
+            usr_pointer
+        };
 
         /*
-         * If memory isn't initialized, allocate a new block of memory and assign it to the memory guard
+         * The events and the watermarks are checked without the heap lock, so the hook and the pressure
+         * handler can use the heap
          */
-        if memory_guard.is_none() {
-            unsafe {
-                let old_break = allocate_block::<T>(size)?;
+        self.hooks.flush();
+        self.hooks.dispatch(HeapEvent {
+            kind: EventKind::Alloc,
+            address: usr_pointer as usize,
+            size: size as usize,
+        });
+        self.check_pressure();
+
+        Ok(usr_pointer)
+    }
 
-                *memory_guard = Some(AtomicPtr::new(old_break));
+    /**
+     * Looks for a free block that can store the given size, if there isn't one, then a new block is
+     * allocated at the end of the heap
+     *
+     * @return The header of the block, it's already marked as not free.
+     */
+    fn take_block<T>(
+        &self,
+        memory_guard: &mut BumpHeapState<S>,
+        size: i32,
+    ) -> Result<*mut BumpMemoryBlockHeader, AllocError> {
+        /*
+         * If memory isn't initialized, allocate a new block of memory and assign it to the memory guard
+         */
+        if memory_guard.head.is_none() {
+            let old_break = allocate_block::<T, S>(
+                &mut memory_guard.source,
+                &self.counters,
+                &self.quota,
+                &self.hooks,
+                size,
+            )?;
+
+            memory_guard.head = Some(AtomicPtr::new(old_break));
+
+            return Ok(old_break);
+        }
 
-                let user_ptr = old_break.add(1);
+        if self.placement.policy() == PlacementPolicy::BestFit
+            && let Some(node) = self.find_best_fit_block(memory_guard, size)
+        {
+            unsafe { (*node).is_free = false };
 
-                return Some(user_ptr as *mut T);
-            }
+            return Ok(node);
         }
 
         /*
          * If memory is initialized, search for a free block of memory that is large enough to allocate the requested memory
          */
-        let mut current_node = memory_guard.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+        let mut current_node = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
         let mut last_node = current_node;
 
         while let Some(node) = current_node {
@@ -48,9 +190,9 @@ impl BumpAllocator {
                 last_node = Some(node);
 
                 /*
-                 * If this node isn't free, then we must continue
+                 * If this node isn't free or it's in the quarantine, then we must continue
                  */
-                if !(*node).is_free {
+                if !(*node).is_available() {
                     current_node = (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
                     continue;
                 }
@@ -88,16 +230,21 @@ impl BumpAllocator {
                  * and return to the user the pointer
                  */
                 (*node).is_free = false;
-                let user_ptr = node.add(1);
 
-                return Some(user_ptr as *mut T);
+                return Ok(node);
             }
         }
 
         /*
          * If no free block of memory is found, allocate a new block of memory
          */
-        let old_break = allocate_block::<T>(size)?;
+        let old_break = allocate_block::<T, S>(
+            &mut memory_guard.source,
+            &self.counters,
+            &self.quota,
+            &self.hooks,
+            size,
+        )?;
 
         /*
          * Make new BumpMemoryBlockHeader to point the last_node as the previous
@@ -110,88 +257,721 @@ impl BumpAllocator {
             }
         }
 
-        unsafe {
-            let user_ptr = old_break.add(1);
+        Ok(old_break)
+    }
+
+    /**
+     * Looks for the smallest available block that can store the given size, adjacent free blocks aren't
+     * merged, so if there isn't one the first fit search is done
+     */
+    fn find_best_fit_block(
+        &self,
+        memory_guard: &BumpHeapState<S>,
+        size: i32,
+    ) -> Option<*mut BumpMemoryBlockHeader> {
+        let mut best_block: Option<*mut BumpMemoryBlockHeader> = None;
+        let mut current_node = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
 
-            return Some(user_ptr as *mut T);
+        while let Some(node) = current_node {
+            unsafe {
+                if (*node).is_available()
+                    && (*node).size >= size
+                    && best_block.is_none_or(|best_block| (*node).size < (*best_block).size)
+                {
+                    best_block = Some(node);
+                }
+
+                current_node = (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+            }
         }
+
+        best_block
     }
 
     /**
-     * Deallocate memory on the heap using the bump allocator.
+     * Deallocate memory on this heap using the bump allocator.
      *
      * @param usr_data The pointer to the memory to deallocate.
      *
      * @note This function is thread-safe.
+     * @note Double frees, frees of interior or foreign pointers and frees after teardown are reported with
+     * the action configured in free_checks.
      */
-    pub fn qudelloc<T>(usr_data: *const T) {
-        let mut memory_guard = bump_memory.lock().unwrap();
+    pub fn qudelloc<T>(&self, usr_data: *const T) {
+        let (result, use_after_free_reports) = {
+            let mut memory_guard = self.memory.lock().unwrap();
+            let result = self.release_block(&mut memory_guard, usr_data as *const u8);
+
+            if result.is_ok() {
+                self.counters.frees.fetch_add(1, Ordering::Relaxed);
+                self.free_checks.record_free(usr_data as usize);
+                self.call_sites.record_free(usr_data as usize);
+                self.profiler.record_free(usr_data as usize);
+                self.leak_suppressions.forget(usr_data as usize);
+            }
 
-        /*
-         * If memory isn't initialized, do nothing
-         */
-        if memory_guard.is_none() {
-            return;
-        }
+            let use_after_free_reports = self.evict_quarantine(&mut memory_guard, false);
 
-        let mut current_node = memory_guard.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+            self.verify_after_operation(memory_guard);
+            (result, use_after_free_reports)
+        };
 
         /*
-         * Check if deallocated node is head node
+         * The reports are done without the heap lock, so the report handlers can use the heap
          */
-        if let Some(node) = current_node {
-            unsafe {
-                let usr_data_ptr = node.add(1) as *const T;
-
-                if usr_data_ptr == usr_data {
-                    (*node).is_free = true;
+        match result {
+            Ok((usr_size, overflow_report)) => {
+                self.canaries.report(overflow_report);
+                self.hooks.dispatch(HeapEvent {
+                    kind: EventKind::Free,
+                    address: usr_data as usize,
+                    size: usr_size,
+                });
+            }
+            Err(invalid_free) => self.free_checks.report(usr_data as usize, invalid_free),
+        }
 
-                    /*
-                     * If deallocated node is head node and it's last node, we must decrease
-                     * heap size, otherwise, just return after set it free
-                     */
-                    if (*node).next.is_none() {
-                        (*memory_guard) = None;
-                        deallocate_block((*node).size);
-                        return;
-                    }
+        self.quarantine.report(use_after_free_reports);
+        self.hooks.flush();
+        self.check_pressure();
+    }
 
-                    return;
-                }
-            }
+    /**
+     * Looks for the block of the given user pointer and sets it free, if it's the last block of the heap,
+     * then its memory is given back to the memory source
+     *
+     * @return The user size and the report of the canaries of the block, or the reason why the block can't
+     * be deallocated.
+     */
+    fn release_block(
+        &self,
+        memory_guard: &mut BumpHeapState<S>,
+        usr_data: *const u8,
+    ) -> Result<(usize, Option<OverflowReport>), InvalidFree> {
+        if self.free_checks.is_torn_down() {
+            return Err(InvalidFree {
+                kind: InvalidFreeKind::AfterTeardown,
+                block: None,
+            });
         }
 
+        let mut current_node = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
         while let Some(node) = current_node {
             unsafe {
                 /*
                  * If we found node in list, then we must set it to be free, otherwise, we must
                  * continue iterations on next node
                  */
-                let usr_data_ptr = node.add(1) as *const T;
+                let usr_data_ptr = node.add(1) as *const u8;
 
                 if usr_data_ptr != usr_data {
                     current_node = (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
                     continue;
                 }
 
-                (*node).is_free = true;
+                if (*node).is_free {
+                    return Err(InvalidFree {
+                        kind: InvalidFreeKind::DoubleFree,
+                        block: Some(block_entry(node)),
+                    });
+                }
+
+                let overflow_report = self.check_canaries(node);
+
+                let usr_size = (*node).usr_size as usize;
+
+                self.quota.record_free(usr_size);
+                self.tags.record_free((*node).tag, usr_size);
 
                 /*
-                 * If this is already the last node, we must give to Operative System the memory of the block
+                 * When the quarantine is enabled, the block is freed for real when it leaves the quarantine
                  */
-                if (*node).next.is_none() {
-                    if let Some(prev_ptr) = &(*node).prev {
-                        let prev = prev_ptr.load(Ordering::SeqCst);
+                if self.quarantine.is_enabled() {
+                    (*node).is_free = true;
+                    (*node).in_quarantine = true;
+                    self.quarantine.push(node as usize, node.add(1) as usize, (*node).size as usize);
 
-                        (*prev).next = None;
-                    }
+                    return Ok((usr_size, overflow_report));
+                }
+
+                self.free_block(memory_guard, node);
+
+                return Ok((usr_size, overflow_report));
+            }
+        }
+
+        Err(self.find_invalid_free(memory_guard, usr_data))
+    }
 
-                    deallocate_block((*node).size);
+    /**
+     * Sets a block free, if this is the last block of the heap, then its memory is given back to the memory source
+     *
+     * @note If the memory source can't shrink or the block is smaller than the trim threshold, then the block
+     * stays at the end of the heap as a free block.
+     */
+    pub(super) unsafe fn free_block(
+        &self,
+        memory_guard: &mut BumpHeapState<S>,
+        node: *mut BumpMemoryBlockHeader,
+    ) {
+        unsafe {
+            (*node).is_free = true;
+            (*node).in_quarantine = false;
+
+            /*
+             * If this is already the last node, we must give to Operative System the memory of the block,
+             * if it's also the head node, then the heap becomes empty
+             */
+            let block_size = ((*node).size + BumpMemoryBlockHeader::size()) as usize;
+
+            if (*node).next.is_none() && block_size >= self.trim_threshold.load(Ordering::Relaxed) {
+                /*
+                 * The header can't be read after giving back its memory, so the prev is taken before
+                 */
+                let prev = (*node).prev.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+                let size = (*node).size;
+
+                if deallocate_block(
+                    &mut memory_guard.source,
+                    &self.counters,
+                    &self.hooks,
+                    node as usize,
+                    size,
+                )
+                .is_err()
+                {
                     return;
                 }
 
-                current_node = (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+                match prev {
+                    Some(prev) => (*prev).next = None,
+                    None => memory_guard.head = None,
+                }
+            }
+        }
+    }
+
+    /**
+     * Finds why a pointer that isn't the user pointer of any block can't be deallocated
+     */
+    fn find_invalid_free(&self, memory_guard: &BumpHeapState<S>, usr_data: *const u8) -> InvalidFree {
+        let mut current_node = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        while let Some(node) = current_node {
+            let block = block_entry(node);
+
+            if (block.address..block.usr_address + block.size).contains(&(usr_data as usize)) {
+                return InvalidFree {
+                    kind: InvalidFreeKind::InteriorPointer,
+                    block: Some(block),
+                };
+            }
+
+            current_node = unsafe { (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst)) };
+        }
+
+        /*
+         * Blocks given back to the memory source aren't in the list, but their pointers are known
+         * when the backtraces are enabled
+         */
+        let kind = if self.free_checks.was_freed(usr_data as usize) {
+            InvalidFreeKind::DoubleFree
+        } else {
+            InvalidFreeKind::ForeignPointer
+        };
+
+        InvalidFree { kind, block: None }
+    }
+
+    /**
+     * Gives all the blocks of this heap back to the memory source, after it the deallocations of the
+     * pointers of this heap are reported as frees after teardown.
+     *
+     * @note The heap can be used again, the next allocation starts a new heap.
+     * @note If the memory source can't shrink, then the blocks that weren't given back stay in the heap
+     * as free blocks.
+     * @note The emergency reserve is given back with the rest of the heap, it must be set again.
+     */
+    pub fn teardown(&self) {
+        let mut memory_guard = self.memory.lock().unwrap();
+
+        let mut last_node = None;
+        let mut current_node = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        while let Some(node) = current_node {
+            last_node = Some(node);
+            current_node = unsafe { (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst)) };
+        }
+
+        /*
+         * Blocks are given back from the last one, because the break can only shrink from its end
+         */
+        while let Some(node) = last_node {
+            unsafe {
+                let prev = (*node).prev.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+                let size = (*node).size;
+
+                if deallocate_block(
+                    &mut memory_guard.source,
+                    &self.counters,
+                    &self.hooks,
+                    node as usize,
+                    size,
+                )
+                .is_err()
+                {
+                    break;
+                }
+
+                last_node = prev;
             }
         }
+
+        /*
+         * The blocks that are left are the ones from the head to the block that couldn't be given back
+         */
+        match last_node {
+            Some(node) => unsafe {
+                (*node).next = None;
+                current_node = memory_guard
+                    .head
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst));
+
+                while let Some(node) = current_node {
+                    (*node).is_free = true;
+                    (*node).in_quarantine = false;
+                    current_node = (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+                }
+            },
+            None => memory_guard.head = None,
+        }
+
+        self.quarantine.clear();
+        self.quota.clear();
+        self.tags.clear();
+        self.oom.take_reserve();
+        self.call_sites.clear();
+        self.profiler.clear();
+        self.free_checks.set_torn_down(true);
+
+        drop(memory_guard);
+        self.hooks.flush();
+    }
+}
+
+pub struct BumpAllocator {}
+
+impl BumpAllocator {
+    /**
+     * Allocate memory on the heap using the bump allocator.
+     *
+     * @param size The size of the memory to allocate.
+     * @return A pointer to the allocated memory, or the reason why it can't be allocated.
+     *
+     * @note This function is thread-safe, the memory is taken from the program break with sbrk. Small sizes
+     * aren't served by the lock-free small allocator, it's only used by MmapAllocator, so every allocation
+     * takes the bump_memory lock.
+     * @note The first allocation applies the settings of the QUALLOC_OPTIONS environment variable and
+     * registers the fork handlers, while they can't be registered the allocations fail with the error of
+     * pthread_atfork.
+     */
+    pub fn qualloc<T>(size: i32) -> Result<*mut T, AllocError> {
+        let _allocator_scope = AllocatorScope::enter();
+        register_fork_handlers()?;
+        load_env_options();
+
+        let usr_pointer = bump_memory.qualloc::<T>(size)?;
+
+        TraceRecorder::record_allocation(
+            TraceOp::BumpAlloc,
+            usr_pointer as usize,
+            size as usize,
+            align_of::<T>(),
+        );
+
+        Ok(usr_pointer)
+    }
+
+    /**
+     * Allocate memory counted in the given tag instead of the tag of the current thread, it's the same as
+     * allocating inside a TagScope.
+     *
+     * @param tag The tag stored in the header of the block.
+     * @param size The size of the memory to allocate.
+     * @return A pointer to the allocated memory, or the reason why it can't be allocated.
+     */
+    pub fn qualloc_tagged<T>(tag: AllocTag, size: i32) -> Result<*mut T, AllocError> {
+        let _allocator_scope = AllocatorScope::enter();
+        let _tag_scope = TagScope::enter(tag);
+
+        Self::qualloc::<T>(size)
+    }
+
+    /**
+     * Deallocate memory on the heap using the bump allocator.
+     *
+     * @param usr_data The pointer to the memory to deallocate.
+     *
+     * @note This function is thread-safe.
+     */
+    pub fn qudelloc<T>(usr_data: *const T) {
+        TraceRecorder::record_free(TraceOp::BumpFree, usr_data as usize);
+
+        bump_memory.qudelloc(usr_data)
+    }
+
+    /**
+     * Walks over the blocks of the bump allocator heap.
+     *
+     * @return An iterator that holds the heap lock until it's dropped.
+     *
+     * @warning Allocating or deallocating with the bump allocator while the walker is alive deadlocks.
+     */
+    pub fn walk() -> BumpHeapWalker<'static, SystemBreakSource> {
+        bump_memory.walk()
+    }
+
+    /**
+     * Takes a consistent copy of all the blocks of the bump allocator heap.
+     */
+    pub fn snapshot() -> Vec<HeapEntry> {
+        bump_memory.snapshot()
+    }
+
+    /**
+     * Takes the statistics of the bump allocator heap.
+     */
+    pub fn stats() -> Stats {
+        bump_memory.stats()
+    }
+
+    /**
+     * Checks the invariants of the bump allocator heap.
+     *
+     * @return The first violation found.
+     */
+    pub fn verify() -> Result<(), HeapError> {
+        bump_memory.verify()
+    }
+
+    /**
+     * Enables or disables running verify after every operation of the bump allocator, only in debug builds.
+     */
+    pub fn set_verify_after_operations(enabled: bool) {
+        bump_memory.set_verify_after_operations(enabled)
+    }
+
+    /**
+     * Sets how the bump allocator chooses the free space for new allocations.
+     */
+    pub fn set_placement_policy(policy: PlacementPolicy) {
+        bump_memory.placement.set_policy(policy)
+    }
+
+    /**
+     * Sets the byte budget of the bump allocator, allocations that would go over it fail with
+     * AllocError::QuotaExceeded.
+     *
+     * @param limit The maximum number of bytes, None removes the quota.
+     * @param kind Counts the bytes asked by the user or the bytes taken from the program break.
+     */
+    pub fn set_quota(limit: Option<usize>, kind: QuotaKind) {
+        bump_memory.quota.set_limit(limit, kind)
+    }
+
+    /**
+     * Sets the soft limits of the bump allocator, the pressure handler is called once when the usage goes
+     * over the high watermark and once when it goes back under the low watermark.
+     *
+     * @param high The usage that starts the pressure, None removes the watermarks.
+     * @param low The usage that ends the pressure.
+     * @param kind Counts the bytes asked by the user or the bytes taken from the program break.
+     */
+    pub fn set_watermarks(high: Option<usize>, low: usize, kind: QuotaKind) {
+        bump_memory.watermarks.set(high, low, kind)
+    }
+
+    /**
+     * Sets the function that receives the crossings of the watermarks of the bump allocator, None prints
+     * them to stderr.
+     *
+     * @note The handler is called without the heap lock, so it can free memory of the bump allocator.
+     */
+    pub fn set_pressure_handler(handler: Option<PressureHandler>) {
+        bump_memory.watermarks.set_handler(handler)
+    }
+
+    /**
+     * Enables or disables trimming the bump allocator after its high watermark is reported.
+     */
+    pub fn set_auto_trim(enabled: bool) {
+        bump_memory.watermarks.set_auto_trim(enabled)
+    }
+
+    /**
+     * Gives back the free memory of the bump allocator to the OS.
+     *
+     * @return The number of bytes given back.
+     */
+    pub fn trim() -> usize {
+        bump_memory.trim()
+    }
+
+    /**
+     * Sets the function called when the bump allocator runs out of memory, it can free memory and ask for
+     * the allocation to be tried again, None fails the allocations.
+     */
+    pub fn set_oom_handler(handler: Option<OomHandler>) {
+        bump_memory.oom.set_handler(handler)
+    }
+
+    /**
+     * Allocates the emergency reserve of the bump allocator, it's released on the first out of memory.
+     *
+     * @param size The size of the reserve, 0 removes the reserve.
+     * @return The reason why the reserve can't be allocated.
+     */
+    pub fn set_emergency_reserve(size: usize) -> Result<(), AllocError> {
+        bump_memory.set_emergency_reserve(size)
+    }
+
+    /**
+     * Reads the cgroup and rlimit memory limits of the process and sizes the bump allocator from them, the
+     * settings are reported by the stats.
+     *
+     * @return The settings that were applied.
+     */
+    pub fn auto_tune() -> AutoTuning {
+        let tuning = AutoTuning::probe();
+
+        bump_memory.apply_tuning(tuning);
+        tuning
+    }
+
+    /**
+     * Changes a setting of the bump allocator at runtime, like mallopt, the settings can also be given in the
+     * QUALLOC_OPTIONS environment variable, it's read at the first allocation.
+     *
+     * @return If the bump allocator haves the setting, MmapThreshold and MinRegionSize are only for the mmap
+     * allocator.
+     */
+    pub fn set_option(tunable: Tunable) -> bool {
+        load_env_options();
+        bump_memory.set_tunable(tunable)
+    }
+
+    /**
+     * Sets the function that receives the events of the bump allocator, it's called on every allocation and
+     * deallocation and every time the program break grows or shrinks, None stops the events.
+     */
+    pub fn set_event_hook(hook: Option<EventHook>) {
+        bump_memory.hooks.set_hook(hook)
+    }
+
+    /**
+     * Sets the limit of the live bytes of a tag, the allocations that would go over it fail with
+     * AllocError::TagLimitExceeded.
+     *
+     * @param limit The maximum number of bytes, None removes the limit.
+     */
+    pub fn set_tag_limit(tag: AllocTag, limit: Option<usize>) {
+        bump_memory.tags.set_limit(tag, limit)
+    }
+
+    /**
+     * Gets the live bytes, the live allocations and the peak of a tag
+     */
+    pub fn tag_stats(tag: AllocTag) -> TagStats {
+        bump_memory.tags.stats(tag)
+    }
+
+    /**
+     * Gets the stats of the tags that were used or have a limit
+     */
+    pub fn all_tag_stats() -> Vec<TagStats> {
+        bump_memory.tags.all_stats()
+    }
+
+    /**
+     * Sets what the bump allocator does when it finds a double free or an invalid free.
+     */
+    pub fn set_invalid_free_action(action: InvalidFreeAction) {
+        bump_memory.free_checks.set_action(action)
+    }
+
+    /**
+     * Sets the function that receives the invalid free reports of the bump allocator, None prints them
+     * to stderr.
+     */
+    pub fn set_invalid_free_handler(handler: Option<InvalidFreeHandler>) {
+        bump_memory.free_checks.set_handler(handler)
+    }
+
+    /**
+     * Enables or disables capturing the allocation and free backtraces used by the invalid free reports.
+     */
+    pub fn set_free_backtraces(enabled: bool) {
+        bump_memory.free_checks.set_backtraces(enabled)
+    }
+
+    /**
+     * Enables or disables recording the call site of every allocation of the bump allocator, disabling it
+     * forgets the recorded call sites.
+     */
+    pub fn set_call_sites(enabled: bool) {
+        bump_memory.call_sites.set_enabled(enabled)
+    }
+
+    /**
+     * Gets the call sites of the bump allocator with the most live bytes.
+     *
+     * @param count Maximum number of call sites returned.
+     */
+    pub fn top_call_sites_by_bytes(count: usize) -> Vec<CallSiteStats> {
+        bump_memory.call_sites.top_by_bytes(count)
+    }
+
+    /**
+     * Gets the call sites of the bump allocator with the most live allocations.
+     *
+     * @param count Maximum number of call sites returned.
+     */
+    pub fn top_call_sites_by_count(count: usize) -> Vec<CallSiteStats> {
+        bump_memory.call_sites.top_by_count(count)
+    }
+
+    /**
+     * Enables or disables the sampling heap profiler of the bump allocator.
+     *
+     * @param sample_interval Mean bytes allocated between two samples, SAMPLE_INTERVAL_DEFAULT is
+     * the default of tcmalloc.
+     */
+    pub fn set_heap_profiling(enabled: bool, sample_interval: usize) {
+        bump_memory.profiler.set_enabled(enabled, sample_interval)
+    }
+
+    /**
+     * Takes the live samples of the heap profiler of the bump allocator.
+     */
+    pub fn heap_profile() -> HeapProfile {
+        bump_memory.profiler.profile()
+    }
+
+    /**
+     * Writes the heap profile of the bump allocator to a file, for analyzing it with pprof.
+     */
+    pub fn dump_heap_profile(path: impl AsRef<Path>, format: ProfileFormat) -> io::Result<()> {
+        bump_memory.profiler.profile().dump(path, format)
+    }
+
+    /**
+     * Enables or disables the quarantine of the bump allocator, freed blocks are poisoned and they aren't
+     * reused until max_bytes of other blocks are freed after them.
+     */
+    pub fn set_quarantine(enabled: bool, max_bytes: usize) {
+        bump_memory.quarantine.set_enabled(enabled, max_bytes)
+    }
+
+    /**
+     * Sets the function that receives the write after free reports of the bump allocator, None prints them
+     * to stderr.
+     */
+    pub fn set_use_after_free_handler(handler: Option<UseAfterFreeHandler>) {
+        bump_memory.quarantine.set_handler(handler)
+    }
+
+    /**
+     * Takes all the blocks out of the quarantine of the bump allocator, checking their poison pattern.
+     */
+    pub fn flush_quarantine() {
+        bump_memory.flush_quarantine()
+    }
+
+    /**
+     * Enables or disables the canaries of the bump allocator, the blocks allocated while it's enabled get canaries
+     * before and after their user data, they are checked on free and by check_all.
+     */
+    pub fn set_canaries(enabled: bool) {
+        bump_memory.canaries.set_enabled(enabled)
+    }
+
+    /**
+     * Sets the function that receives the overflow reports found on free by the bump allocator, None prints
+     * them to stderr.
+     */
+    pub fn set_overflow_handler(handler: Option<OverflowHandler>) {
+        bump_memory.canaries.set_handler(handler)
+    }
+
+    /**
+     * Checks the canaries of all the blocks of the bump allocator that aren't free.
+     *
+     * @return The reports of the blocks whose canaries were changed.
+     */
+    pub fn check_all() -> Vec<OverflowReport> {
+        bump_memory.check_all()
+    }
+
+    /**
+     * Gives all the memory of the bump allocator back to the OS.
+     *
+     * @warning Every pointer given by the bump allocator becomes invalid.
+     */
+    pub fn teardown() {
+        bump_memory.teardown()
+    }
+
+    /**
+     * Moves the bump allocator to a private virtual break.
+     *
+     * @param reserve_size The size of the virtual range reserved for the heap.
+     * @return True if the break was changed.
+     *
+     * @note The program break is shared with the malloc of the C library, so if a C library is using malloc
+     * in the same process, then the bump allocator can deallocate memory that malloc is using. The virtual
+     * break is a range reserved with mmap that only the bump allocator uses.
+     * @warning The break can only be changed while the heap is empty, otherwise this function returns false.
+     */
+    pub fn use_virtual_break(reserve_size: usize) -> bool {
+        Self::set_break(SystemBreakSource::Virtual(VirtualBreakSource::new(
+            reserve_size,
+        )))
+    }
+
+    /**
+     * Moves the bump allocator back to the program break of the process.
+     *
+     * @return True if the break was changed.
+     *
+     * @warning The break can only be changed while the heap is empty, otherwise this function returns false.
+     */
+    pub fn use_program_break() -> bool {
+        Self::set_break(SystemBreakSource::Sbrk(SbrkSource {}))
+    }
+
+    fn set_break(source: SystemBreakSource) -> bool {
+        let mut memory_guard = bump_memory.memory.lock().unwrap();
+
+        if memory_guard.head.is_some() {
+            return false;
+        }
+
+        memory_guard.source = source;
+
+        true
     }
 }
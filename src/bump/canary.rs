@@ -0,0 +1,100 @@
+use std::sync::atomic::Ordering;
+
+use crate::{
+    canary::{
+        OverflowReport, canary_value, count_clobbered_bytes, read_rear_canary, write_rear_canary,
+    },
+    source::MemorySource,
+};
+
+use super::{BumpHeap, BumpMemoryBlockHeader, walk::block_entry};
+
+impl<S: MemorySource> BumpHeap<S> {
+    /**
+     * Stores the user size of a block that was just allocated and writes its canaries if it was sized for
+     * them, the heap lock must be taken by the caller
+     */
+    pub(super) unsafe fn arm_canaries(
+        &self,
+        node: *mut BumpMemoryBlockHeader,
+        usr_size: i32,
+        has_canaries: bool,
+    ) {
+        unsafe {
+            (*node).usr_size = usr_size;
+            (*node).has_canaries = has_canaries;
+
+            if !(*node).has_canaries {
+                return;
+            }
+
+            let canary = canary_value(node as usize);
+
+            (*node).front_canary = canary;
+            write_rear_canary(node.add(1) as usize, usr_size as usize, canary);
+        }
+    }
+
+    /**
+     * Checks the canaries of a block, the heap lock must be taken by the caller
+     *
+     * @return The report if any canary was changed.
+     */
+    pub(super) unsafe fn check_canaries(
+        &self,
+        node: *mut BumpMemoryBlockHeader,
+    ) -> Option<OverflowReport> {
+        unsafe {
+            if !(*node).has_canaries {
+                return None;
+            }
+
+            let canary = canary_value(node as usize);
+            let usr_size = (*node).usr_size as usize;
+            let front_clobbered = count_clobbered_bytes((*node).front_canary, canary);
+            let rear_clobbered =
+                count_clobbered_bytes(read_rear_canary(node.add(1) as usize, usr_size), canary);
+
+            if front_clobbered == 0 && rear_clobbered == 0 {
+                return None;
+            }
+
+            Some(OverflowReport {
+                block: block_entry(node),
+                usr_size,
+                front_clobbered,
+                rear_clobbered,
+            })
+        }
+    }
+
+    /**
+     * Checks the canaries of all the blocks of this heap that aren't free.
+     *
+     * @return The reports of the blocks whose canaries were changed.
+     *
+     * @note A block whose header was overwritten can break the list, verify can find it.
+     */
+    pub fn check_all(&self) -> Vec<OverflowReport> {
+        let memory_guard = self.memory.lock().unwrap();
+        let mut reports = Vec::new();
+        let mut current_node = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        while let Some(node) = current_node {
+            unsafe {
+                if !(*node).is_free
+                    && let Some(report) = self.check_canaries(node)
+                {
+                    reports.push(report);
+                }
+
+                current_node = (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+            }
+        }
+
+        reports
+    }
+}
@@ -1,8 +1,10 @@
 use lazy_static::lazy_static;
-use std::sync::{Mutex, atomic::AtomicPtr};
 
-use super::BumpMemoryBlockHeader;
+use crate::source::system::SystemBreakSource;
+
+use super::BumpHeap;
 
 lazy_static! {
-    pub static ref bump_memory: Mutex<Option<AtomicPtr<BumpMemoryBlockHeader>>> = Mutex::new(None);
+    pub static ref bump_memory: BumpHeap<SystemBreakSource> =
+        BumpHeap::new(SystemBreakSource::default());
 }
@@ -0,0 +1,28 @@
+use crate::{
+    leak::{LeakReport, LeakedBlock, find_leaked_blocks},
+    source::MemorySource,
+};
+
+use super::BumpHeap;
+
+impl<S: MemorySource> BumpHeap<S> {
+    /**
+     * Finds the blocks of this heap that are still allocated and aren't suppressed, the blocks in the
+     * quarantine are free, so they aren't leaks.
+     */
+    pub fn leaked_blocks(&self) -> Vec<LeakedBlock> {
+        find_leaked_blocks(
+            self.snapshot(),
+            &self.free_checks,
+            &self.call_sites,
+            &self.leak_suppressions,
+        )
+    }
+
+    /**
+     * Groups the leaked blocks of this heap by call site.
+     */
+    pub fn leaks(&self) -> LeakReport {
+        LeakReport::new(self.leaked_blocks())
+    }
+}
@@ -0,0 +1,27 @@
+use std::sync::atomic::Ordering;
+
+use crate::{limits::AutoTuning, quota::QuotaKind, source::MemorySource};
+
+use super::BumpHeap;
+
+impl<S: MemorySource> BumpHeap<S> {
+    /**
+     * Applies the settings derived from the memory limits, the soft limit and the low watermark become the
+     * watermarks of the heap taken from the memory source, the heap is trimmed when the soft limit is
+     * crossed and a free block at the end of the heap is kept until it reaches the trim threshold.
+     *
+     * @note The quarantine keeps its enabled state, only its size is changed. Without a pressure handler the
+     * crossings of the soft limit are printed to stderr.
+     */
+    pub fn apply_tuning(&self, tuning: AutoTuning) {
+        self.watermarks
+            .set(tuning.soft_limit, tuning.low_watermark, QuotaKind::OsBytes);
+        self.watermarks.set_auto_trim(tuning.soft_limit.is_some());
+        self.quarantine
+            .set_enabled(self.quarantine.is_enabled(), tuning.quarantine_size);
+        self.trim_threshold
+            .store(tuning.trim_threshold, Ordering::Relaxed);
+
+        *self.tuning.lock().unwrap() = Some(tuning);
+    }
+}
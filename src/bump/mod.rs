@@ -1,8 +1,27 @@
-use std::sync::atomic::AtomicPtr;
+use std::sync::{
+    Mutex,
+    atomic::{AtomicBool, AtomicPtr, AtomicUsize},
+};
+
+use crate::{
+    call_site::CallSites, canary::Canaries, free_check::FreeChecks, hooks::EventHooks,
+    leak::LeakSuppressions, limits::AutoTuning, oom::OomHooks, placement::Placement,
+    pressure::Watermarks, profile::HeapProfiler, quarantine::Quarantine, quota::Quota,
+    source::MemorySource, stats::HeapCounters, tags::{AllocTag, Tags, UNTAGGED},
+};
 
 pub mod globals;
 pub mod utils;
 pub mod allocator;
+pub mod canary;
+pub mod leak;
+pub mod limits;
+pub mod oom;
+pub mod pressure;
+pub mod quarantine;
+pub mod tunables;
+pub mod verify;
+pub mod walk;
 
 /**
  * Bump memory allocator is the classic type of dynamic memory management using sbrk
@@ -36,11 +55,23 @@ pub mod allocator;
  * size should be 58
  */
 
+/*
+ * The header is repr(C) because the front canary must be the last field, just before the user data
+ */
+#[repr(C)]
 pub struct BumpMemoryBlockHeader {
     pub size: i32,
+    // Size asked by the user, it's smaller than size when the block haves canaries or it was reused
+    pub usr_size: i32,
     pub is_free: bool,
+    // A freed block that is kept in the quarantine, it can't be reused until it leaves the quarantine
+    pub in_quarantine: bool,
+    pub has_canaries: bool,
+    // Tag of the allocation, it's counted in the tags of the heap until the user deallocates it
+    pub tag: AllocTag,
     pub next: Option<AtomicPtr<BumpMemoryBlockHeader>>,
     pub prev: Option<AtomicPtr<BumpMemoryBlockHeader>>,
+    pub front_canary: u64,
 }
 
 impl BumpMemoryBlockHeader {
@@ -53,12 +84,79 @@ impl BumpMemoryBlockHeader {
         Self {
             next,
             is_free,
+            in_quarantine: false,
+            has_canaries: false,
+            tag: UNTAGGED,
             prev,
             size,
+            usr_size: size,
+            front_canary: 0,
         }
     }
 
     pub fn size() -> i32 {
         size_of::<BumpMemoryBlockHeader>() as i32
     }
+
+    /**
+     * Checks if the block can be given to the user
+     */
+    pub fn is_available(&self) -> bool {
+        self.is_free && !self.in_quarantine
+    }
+}
+
+/**
+ * A bump heap is the list of blocks of the bump allocator together with the memory source where the blocks
+ * are taken from, BumpAllocator uses a global heap over sbrk but a heap can be created over any memory source
+ */
+pub struct BumpHeap<S: MemorySource> {
+    pub memory: Mutex<BumpHeapState<S>>,
+    pub counters: HeapCounters,
+    pub verify_after_operations: AtomicBool,
+    pub free_checks: FreeChecks,
+    pub quarantine: Quarantine,
+    pub canaries: Canaries,
+    pub call_sites: CallSites,
+    pub profiler: HeapProfiler,
+    pub leak_suppressions: LeakSuppressions,
+    pub placement: Placement,
+    pub quota: Quota,
+    pub watermarks: Watermarks,
+    pub oom: OomHooks,
+    pub hooks: EventHooks,
+    pub tags: Tags,
+    // Settings applied by apply_tuning, they are reported by the stats
+    pub tuning: Mutex<Option<AutoTuning>>,
+    // A free block at the end of the heap smaller than this isn't given back to the memory source
+    pub trim_threshold: AtomicUsize,
+}
+
+impl<S: MemorySource> BumpHeap<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            memory: Mutex::new(BumpHeapState { head: None, source }),
+            counters: HeapCounters::new(),
+            verify_after_operations: AtomicBool::new(false),
+            free_checks: FreeChecks::new(),
+            quarantine: Quarantine::new(),
+            canaries: Canaries::new(),
+            call_sites: CallSites::new(),
+            profiler: HeapProfiler::new(),
+            leak_suppressions: LeakSuppressions::new(),
+            placement: Placement::new(),
+            quota: Quota::new(),
+            watermarks: Watermarks::new(),
+            oom: OomHooks::new(),
+            hooks: EventHooks::new(),
+            tags: Tags::new(),
+            tuning: Mutex::new(None),
+            trim_threshold: AtomicUsize::new(0),
+        }
+    }
+}
+
+pub struct BumpHeapState<S: MemorySource> {
+    pub head: Option<AtomicPtr<BumpMemoryBlockHeader>>,
+    pub source: S,
 }
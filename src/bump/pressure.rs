@@ -0,0 +1,99 @@
+use std::sync::atomic::Ordering;
+
+use crate::{
+    pressure::{PressureLevel, PressureReport},
+    source::MemorySource,
+};
+
+use super::{BumpHeap, utils::deallocate_block};
+
+impl<S: MemorySource> BumpHeap<S> {
+    /**
+     * Gives back to the memory source the free memory at the end of the heap, the quarantine is flushed
+     * before, so its blocks can be given back too.
+     *
+     * @return The number of bytes given back.
+     *
+     * @note Only the free blocks at the end of the break can be given back, free blocks between used
+     * blocks stay in the heap.
+     */
+    pub fn trim(&self) -> usize {
+        let heap_size = self.counters.heap_size.load(Ordering::Relaxed);
+
+        let reports = {
+            let mut memory_guard = self.memory.lock().unwrap();
+            let reports = self.evict_quarantine(&mut memory_guard, true);
+
+            let mut last_node = memory_guard
+                .head
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst));
+
+            while let Some(next) = last_node.and_then(|node| unsafe {
+                (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst))
+            }) {
+                last_node = Some(next);
+            }
+
+            /*
+             * Blocks are given back from the last one until a used block is found
+             */
+            while let Some(node) = last_node.filter(|node| unsafe { (**node).is_available() }) {
+                unsafe {
+                    let prev = (*node).prev.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+                    let size = (*node).size;
+
+                    if deallocate_block(
+                        &mut memory_guard.source,
+                        &self.counters,
+                        &self.hooks,
+                        node as usize,
+                        size,
+                    )
+                    .is_err()
+                    {
+                        break;
+                    }
+
+                    match prev {
+                        Some(prev) => (*prev).next = None,
+                        None => memory_guard.head = None,
+                    }
+
+                    last_node = prev;
+                }
+            }
+
+            self.verify_after_operation(memory_guard);
+            reports
+        };
+
+        self.quarantine.report(reports);
+        self.hooks.flush();
+
+        heap_size.saturating_sub(self.counters.heap_size.load(Ordering::Relaxed))
+    }
+
+    /**
+     * Checks the watermarks after an operation and reports the crossing, it must be called without the
+     * heap lock
+     */
+    pub(super) fn check_pressure(&self) {
+        let Some(report) = self.watermarks.check(
+            self.quota.user_bytes(),
+            self.counters.heap_size.load(Ordering::Relaxed),
+        ) else {
+            return;
+        };
+
+        self.report_pressure(&report);
+    }
+
+    fn report_pressure(&self, report: &PressureReport) {
+        self.watermarks.report(report);
+
+        if report.level == PressureLevel::High && self.watermarks.is_auto_trim_enabled() {
+            self.trim();
+        }
+    }
+}
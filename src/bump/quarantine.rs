@@ -0,0 +1,56 @@
+use crate::{
+    quarantine::{Quarantine, UseAfterFreeReport},
+    source::MemorySource,
+};
+
+use super::{BumpHeap, BumpHeapState, BumpMemoryBlockHeader, walk::block_entry};
+
+impl<S: MemorySource> BumpHeap<S> {
+    /**
+     * Takes the blocks that must leave the quarantine, checks their poison pattern and frees them for real,
+     * the heap lock must be taken by the caller
+     *
+     * @param flush Takes all the blocks of the quarantine.
+     * @return The reports of the blocks that were written after free.
+     */
+    pub(super) fn evict_quarantine(
+        &self,
+        memory_guard: &mut BumpHeapState<S>,
+        flush: bool,
+    ) -> Vec<UseAfterFreeReport> {
+        let mut reports = Vec::new();
+
+        while let Some(block) = self.quarantine.pop(flush) {
+            let node = block.address as *mut BumpMemoryBlockHeader;
+            let entry = block_entry(node);
+
+            if block.poisoned
+                && let Some((offset, value)) =
+                    Quarantine::find_poison_change(entry.usr_address, block.size)
+            {
+                reports.push(UseAfterFreeReport {
+                    block: entry,
+                    offset,
+                    value,
+                });
+            }
+
+            unsafe { self.free_block(memory_guard, node) };
+        }
+
+        reports
+    }
+
+    /**
+     * Takes all the blocks out of the quarantine, checking their poison pattern, so they can be reused.
+     */
+    pub fn flush_quarantine(&self) {
+        let reports = {
+            let mut memory_guard = self.memory.lock().unwrap();
+
+            self.evict_quarantine(&mut memory_guard, true)
+        };
+
+        self.quarantine.report(reports);
+    }
+}
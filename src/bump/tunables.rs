@@ -0,0 +1,35 @@
+use std::sync::atomic::Ordering;
+
+use crate::{source::MemorySource, tunables::Tunable};
+
+use super::BumpHeap;
+
+impl<S: MemorySource> BumpHeap<S> {
+    /**
+     * Changes a setting of this heap, like mallopt.
+     *
+     * @return If the heap haves the setting, the bump heap doesn't have regions nor a small allocator, so
+     * MmapThreshold, MinRegionSize, SmallAllocator and SmallMaxSize aren't applied.
+     */
+    pub fn set_tunable(&self, tunable: Tunable) -> bool {
+        match tunable {
+            Tunable::TrimThreshold(size) => self.trim_threshold.store(size, Ordering::Relaxed),
+            Tunable::QuarantineSize(size) => self
+                .quarantine
+                .set_enabled(self.quarantine.is_enabled(), size),
+            Tunable::Placement(policy) => self.placement.set_policy(policy),
+            Tunable::Canaries(enabled) => self.canaries.set_enabled(enabled),
+            Tunable::Quarantine(enabled) => self
+                .quarantine
+                .set_enabled(enabled, self.quarantine.max_bytes()),
+            Tunable::Poisoning(enabled) => self.quarantine.set_poisoning(enabled),
+            Tunable::VerifyAfterOperations(enabled) => self.set_verify_after_operations(enabled),
+            Tunable::MmapThreshold(_)
+            | Tunable::MinRegionSize(_)
+            | Tunable::SmallAllocator(_)
+            | Tunable::SmallMaxSize(_) => return false,
+        }
+
+        true
+    }
+}
@@ -1,35 +1,69 @@
 use std::sync::atomic::{AtomicPtr, Ordering};
 
-use crate::utils::align_up;
+use crate::{
+    error::AllocError,
+    hooks::{EventHooks, EventKind, HeapEvent},
+    quota::Quota,
+    source::MemorySource,
+    stats::HeapCounters,
+    utils::checked_align_up,
+};
 
-use super::{BumpMemoryBlockHeader, globals::bump_memory};
+use super::{BumpHeap, BumpMemoryBlockHeader, globals::bump_memory};
 use libc::sbrk;
 
 /**
  * Allocate a new block of memory for the bump allocator and set the header
  * for the new block.
  *
+ * @param source The memory source of the heap.
+ * @param counters The counters of the heap.
+ * @param quota The quota of the heap, the growth is checked against it when it counts OS bytes.
+ * @param hooks The hooks of the heap, the growth is recorded for them.
  * @param size The size of the new block of memory to allocate.
  * @return The pointer to the new block of memory.
  *
  * @note This function is unsafe and should only be called by the bump allocator.
- * @warning This function may return NULL if the system runs out of memory.
+ * @warning This function returns an error if the size with the header doesn't fit in an i32, the growth
+ * goes over the quota or the memory source can't grow.
  */
-pub fn allocate_block<T>(size: i32) -> Option<*mut BumpMemoryBlockHeader> {
+pub fn allocate_block<T, S: MemorySource>(
+    source: &mut S,
+    counters: &HeapCounters,
+    quota: &Quota,
+    hooks: &EventHooks,
+    size: i32,
+) -> Result<*mut BumpMemoryBlockHeader, AllocError> {
     unsafe {
         // Add the size of the header to the size of the block
-        let aligned_user_data_size = align_up(size);
-        let allocated_size = BumpMemoryBlockHeader::size() + aligned_user_data_size;
+        let size_overflow = AllocError::SizeOverflow {
+            size: size as usize,
+        };
+        let aligned_user_data_size = checked_align_up(size).ok_or(size_overflow)?;
+        let allocated_size = BumpMemoryBlockHeader::size()
+            .checked_add(aligned_user_data_size)
+            .ok_or(size_overflow)?;
 
-        // Increase the heap size
-        let old_break = sbrk(allocated_size) as *mut BumpMemoryBlockHeader;
+        quota.check_os_bytes(
+            counters.heap_size.load(Ordering::Relaxed),
+            allocated_size as usize,
+        )?;
 
-        if old_break.is_null() {
-            return None;
-        }
+        // Increase the heap size
+        counters.sbrk_calls.fetch_add(1, Ordering::Relaxed);
+        let old_break = source
+            .grow(allocated_size as usize)
+            .inspect_err(|err| counters.record_os_failure(*err))?
+            as *mut BumpMemoryBlockHeader;
+        counters.record_heap_growth(allocated_size as usize);
+        hooks.record(HeapEvent {
+            kind: EventKind::BreakGrow,
+            address: old_break as usize,
+            size: allocated_size as usize,
+        });
 
         *old_break = BumpMemoryBlockHeader::new(aligned_user_data_size, false, None, None);
-        Some(old_break)
+        Ok(old_break)
     }
 }
 
@@ -43,16 +77,37 @@ pub fn get_usr_pointer<T>(header: *const BumpMemoryBlockHeader) -> *mut T {
 /**
  * Deallocate a block of memory for the bump allocator.
  *
+ * @param source The memory source of the heap.
+ * @param counters The counters of the heap.
+ * @param hooks The hooks of the heap, the release is recorded for them.
+ * @param address The address of the header of the block, it's the break after deallocating it.
  * @param size The size of the block of memory to deallocate.
+ * @return Nothing if the memory was given back, or the error of the memory source, then the break
+ * didn't move and the block must stay in the heap.
  *
  * @note This function is unsafe and should only be called by the bump allocator.
  */
-pub fn deallocate_block(size: i32) {
-    unsafe {
-        let deallocated_size = BumpMemoryBlockHeader::size() + size;
+pub fn deallocate_block<S: MemorySource>(
+    source: &mut S,
+    counters: &HeapCounters,
+    hooks: &EventHooks,
+    address: usize,
+    size: i32,
+) -> Result<(), AllocError> {
+    let deallocated_size = BumpMemoryBlockHeader::size() + size;
 
-        sbrk(-deallocated_size);
-    }
+    counters.sbrk_calls.fetch_add(1, Ordering::Relaxed);
+    source
+        .shrink(deallocated_size as usize)
+        .inspect_err(|err| counters.record_os_failure(*err))?;
+    counters.record_heap_release(deallocated_size as usize);
+    hooks.record(HeapEvent {
+        kind: EventKind::BreakShrink,
+        address,
+        size: deallocated_size as usize,
+    });
+
+    Ok(())
 }
 
 /**
@@ -133,11 +188,11 @@ pub fn merge_adjacent_free_blocks(
     let mut acumulated_size = 0;
 
     unsafe {
-        if (*current_block).is_free {
+        if (*current_block).is_available() {
             acumulated_size += (*current_block).size;
         }
 
-        while (*current_block).is_free {
+        while (*current_block).is_available() {
             last_scanned_block = Some(current_block);
 
             if acumulated_size >= stop_size {
@@ -159,15 +214,17 @@ pub fn merge_adjacent_free_blocks(
 
                 /*
                  * If block is adjacent, then we must add to acumulated size all the ocupped size
-                 * by the next pointer (header and size attribute)
+                 * by the next pointer (header and size attribute), otherwise the merge stops here
+                 * because the space between both blocks isn't ours
                  */
-                if (*next_block).is_free
-                    && (current_block_address + BumpMemoryBlockHeader::size() + current_block_size)
-                        == next_block_address
+                if !(*next_block).is_available()
+                    || (current_block_address + BumpMemoryBlockHeader::size() + current_block_size)
+                        != next_block_address
                 {
-                    acumulated_size += (*current_block).size + BumpMemoryBlockHeader::size();
+                    break;
                 }
 
+                acumulated_size += (*next_block).size + BumpMemoryBlockHeader::size();
                 current_block = next_block;
             } else {
                 break;
@@ -236,26 +293,27 @@ pub fn merge_adjacent_free_blocks(
  *  - free: <is pointer free>
  */
 pub fn scan_bump_memory() {
-    unsafe {
-        let memory_guard = bump_memory.lock().unwrap();
+    scan_bump_heap(&bump_memory);
+}
 
-        println!("Bump memory scanning results:");
-        if memory_guard.is_none() {
-            println!("Bump memory is empty");
-            return;
-        }
-        let mut current_node = memory_guard.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+/**
+ * Prints in console all the items into the given heap, using the same format as scan_bump_memory
+ */
+pub fn scan_bump_heap<S: MemorySource>(heap: &BumpHeap<S>) {
+    let blocks = heap.snapshot();
 
-        while let Some(node) = current_node {
-            println!(
-                "{:p}:\n\t- size: {} bytes\n\t- free: {}\n",
-                node,
-                (*node).size,
-                (*node).is_free
-            );
-            current_node = (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
-        }
+    println!("Bump memory scanning results:");
+    if blocks.is_empty() {
+        println!("Bump memory is empty");
+        return;
+    }
 
-        println!("Bump memory end");
+    for block in blocks {
+        println!(
+            "{:#x}:\n\t- size: {} bytes\n\t- free: {}\n",
+            block.address, block.size, block.is_free
+        );
     }
+
+    println!("Bump memory end");
 }
@@ -0,0 +1,120 @@
+use std::sync::{MutexGuard, atomic::Ordering};
+
+use crate::{error::AllocError, source::MemorySource, utils::align_up, verify::HeapError};
+
+use super::{BumpHeap, BumpHeapState, BumpMemoryBlockHeader};
+
+/**
+ * Checks the invariants of the blocks of a bump heap, the heap lock must be taken by the caller
+ *
+ * - The head doesn't have prev and the prev of every next block points back to its block
+ * - Blocks are address-ordered and they don't overlap, the break only grows, so every block is placed
+ *   after the previous one
+ * - Sizes are aligned
+ *
+ * @return The first violation found.
+ */
+pub fn verify_bump_heap<S: MemorySource>(memory: &BumpHeapState<S>) -> Result<(), HeapError> {
+    let mut current_node = memory.head.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+
+    if let Some(head) = current_node
+        && unsafe { (*head).prev.is_some() }
+    {
+        return Err(HeapError::HeadWithPrev {
+            address: head as usize,
+        });
+    }
+
+    while let Some(node) = current_node {
+        unsafe {
+            let size = (*node).size;
+
+            if size < 0 || align_up(size) != size {
+                return Err(HeapError::UnalignedSize {
+                    address: node as usize,
+                    size: size as usize,
+                });
+            }
+
+            current_node = (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+
+            let Some(next) = current_node else {
+                break;
+            };
+
+            if (*next).prev.as_ref().map(|ptr| ptr.load(Ordering::SeqCst)) != Some(node) {
+                return Err(HeapError::BrokenLink {
+                    address: node as usize,
+                    next: next as usize,
+                });
+            }
+
+            if (next as usize)
+                < node as usize + BumpMemoryBlockHeader::size() as usize + size as usize
+            {
+                return Err(HeapError::Overlap {
+                    address: next as usize,
+                    previous: node as usize,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl<S: MemorySource> BumpHeap<S> {
+    /**
+     * Checks the invariants of the blocks of this heap.
+     *
+     * @return The first violation found.
+     */
+    pub fn verify(&self) -> Result<(), HeapError> {
+        verify_bump_heap(&self.memory.lock().unwrap())
+    }
+
+    /**
+     * Enables or disables running verify after every allocation and deallocation of this heap, if a
+     * violation is found, then the operation panics with it.
+     *
+     * @note Allocations also verify the heap before taking memory and return AllocError::CorruptedHeap
+     * if it was corrupted since the last operation.
+     * @note The checks only run in debug builds, release builds ignore this option.
+     */
+    pub fn set_verify_after_operations(&self, enabled: bool) {
+        self.verify_after_operations
+            .store(enabled, Ordering::Relaxed);
+    }
+
+    /**
+     * Runs verify if it was enabled with set_verify_after_operations and releases the heap lock taken by the
+     * operation, a violation panics after releasing it, so the lock isn't poisoned for the next callers
+     */
+    pub(super) fn verify_after_operation(&self, memory_guard: MutexGuard<'_, BumpHeapState<S>>) {
+        if !cfg!(debug_assertions) || !self.verify_after_operations.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let result = verify_bump_heap(&memory_guard);
+        drop(memory_guard);
+
+        if let Err(err) = result {
+            panic!("bump heap is corrupted: {err}");
+        }
+    }
+
+    /**
+     * Runs verify before an allocation if it was enabled with set_verify_after_operations, so memory isn't
+     * given from a heap that was corrupted since the last operation, the heap lock must be taken by the caller
+     */
+    pub(super) fn verify_before_allocation(
+        &self,
+        memory: &BumpHeapState<S>,
+    ) -> Result<(), AllocError> {
+        if !cfg!(debug_assertions) || !self.verify_after_operations.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        verify_bump_heap(memory).map_err(AllocError::CorruptedHeap)
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::{MutexGuard, atomic::Ordering};
+
+use crate::{
+    source::MemorySource,
+    stats::Stats,
+    walk::{HeapEntry, HeapEntryKind},
+};
+
+use super::{BumpHeap, BumpHeapState, BumpMemoryBlockHeader};
+
+/**
+ * Iterator over the blocks of a bump heap, it holds the heap lock while it's alive, so the blocks can't change
+ * during the walk
+ *
+ * @warning Allocating or deallocating on the same heap while the walker is alive deadlocks.
+ */
+pub struct BumpHeapWalker<'a, S: MemorySource> {
+    _memory_guard: MutexGuard<'a, BumpHeapState<S>>,
+    current_node: Option<*mut BumpMemoryBlockHeader>,
+}
+
+impl<S: MemorySource> Iterator for BumpHeapWalker<'_, S> {
+    type Item = HeapEntry;
+
+    fn next(&mut self) -> Option<HeapEntry> {
+        let node = self.current_node?;
+
+        unsafe {
+            self.current_node = (*node).next.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+
+            Some(block_entry(node))
+        }
+    }
+}
+
+/**
+ * Describes a block of a bump heap, the heap lock must be taken by the caller
+ */
+pub(crate) fn block_entry(node: *const BumpMemoryBlockHeader) -> HeapEntry {
+    unsafe {
+        HeapEntry {
+            kind: HeapEntryKind::Block,
+            address: node as usize,
+            usr_address: node.add(1) as usize,
+            size: (*node).size as usize,
+            is_free: (*node).is_free,
+            region: None,
+            header_size: BumpMemoryBlockHeader::size() as usize,
+            tag: (*node).tag,
+        }
+    }
+}
+
+impl<S: MemorySource> BumpHeap<S> {
+    /**
+     * Walks over the blocks of this heap in list order.
+     *
+     * @return An iterator that holds the heap lock until it's dropped.
+     */
+    pub fn walk(&self) -> BumpHeapWalker<'_, S> {
+        let memory_guard = self.memory.lock().unwrap();
+        let current_node = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        BumpHeapWalker {
+            _memory_guard: memory_guard,
+            current_node,
+        }
+    }
+
+    /**
+     * Takes a consistent copy of all the blocks of this heap, the lock is released before returning.
+     */
+    pub fn snapshot(&self) -> Vec<HeapEntry> {
+        self.walk().collect()
+    }
+
+    /**
+     * Takes the statistics of this heap, the counters are read while the heap lock is taken, so they agree
+     * with the blocks found by the walk.
+     */
+    pub fn stats(&self) -> Stats {
+        let walker = self.walk();
+
+        self.counters
+            .load()
+            .add_entries(walker)
+            .add_quota(&self.quota)
+            .add_tuning(*self.tuning.lock().unwrap())
+    }
+}
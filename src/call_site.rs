@@ -0,0 +1,386 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    ffi::CStr,
+    fmt,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::fork::{ForkGuards, ForkLocks, lock_for_fork};
+
+/*
+ * Frames kept for every call site, the frames of the allocator aren't part of them
+ */
+pub const CALL_SITE_DEPTH: usize = 16;
+
+thread_local! {
+    /*
+     * Stack address inside the frame of the outermost allocator function called by the program, the frames
+     * under it belong to the allocator, it's 0 outside of the allocator
+     */
+    static ALLOCATOR_ENTRY: Cell<usize> = const { Cell::new(0) };
+}
+
+/**
+ * Marks the frame of an allocator function called by the program until it's dropped, so the backtraces
+ * captured inside it don't have the frames of the allocator. Only the outermost scope marks its frame, so
+ * the allocator functions can call each other
+ *
+ * @note The handlers and hooks called by an allocation run inside its scope, so their own allocations are
+ * recorded with the call site of the allocation that called them.
+ *
+ * For example:
+ *
+ * pub fn allocate<T>(size: usize) -> Result<*mut T, AllocError> {
+ *     let _allocator_scope = AllocatorScope::enter();
+ *     ...
+ * }
+ */
+pub struct AllocatorScope {
+    outermost: bool,
+}
+
+impl AllocatorScope {
+    /*
+     * It's inlined, so the marker is a local of the function that enters the scope
+     */
+    #[inline(always)]
+    pub fn enter() -> Self {
+        let marker = 0u8;
+        let outermost = ALLOCATOR_ENTRY.get() == 0;
+
+        if outermost {
+            ALLOCATOR_ENTRY.set(std::hint::black_box(&marker) as *const u8 as usize);
+        }
+
+        Self { outermost }
+    }
+}
+
+impl Drop for AllocatorScope {
+    fn drop(&mut self) {
+        if self.outermost {
+            ALLOCATOR_ENTRY.set(0);
+        }
+    }
+}
+
+#[cfg(all(
+    not(miri),
+    not(target_arch = "arm"),
+    any(all(target_os = "linux", target_env = "gnu"), target_vendor = "apple")
+))]
+mod unwind {
+    use std::ffi::{c_int, c_void};
+
+    pub const URC_NO_REASON: c_int = 0;
+    pub const URC_NORMAL_STOP: c_int = 4;
+
+    pub type TraceFn = extern "C" fn(context: *mut c_void, state: *mut c_void) -> c_int;
+
+    /*
+     * Unwinder of the C library runtime, it's the same one used by backtrace(3) and by the panics
+     */
+    unsafe extern "C" {
+        pub fn _Unwind_Backtrace(trace: TraceFn, state: *mut c_void) -> c_int;
+        pub fn _Unwind_GetIP(context: *mut c_void) -> usize;
+        pub fn _Unwind_GetCFA(context: *mut c_void) -> usize;
+    }
+}
+
+/**
+ * Backtrace of an allocation stored as the return addresses of its frames, it's captured without
+ * allocating, so it can be taken while the heap lock is held
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompactBacktrace {
+    frames: [usize; CALL_SITE_DEPTH],
+    len: usize,
+}
+
+impl CompactBacktrace {
+    /**
+     * Captures the backtrace of the current thread, it's empty on the platforms without an unwinder.
+     *
+     * Inside an AllocatorScope the frames under the scope are skipped, so the backtrace starts at the
+     * allocator function called by the program, followed by the frames of the program. Outside of it the
+     * backtrace starts at this function.
+     *
+     * @note glibc loads libgcc_s the first time the stack is unwound, it allocates with the system malloc,
+     * never with these allocators.
+     */
+    pub fn capture() -> Self {
+        let mut backtrace = Self {
+            frames: [0usize; CALL_SITE_DEPTH],
+            len: 0,
+        };
+
+        #[cfg(all(
+            not(miri),
+            not(target_arch = "arm"),
+            any(all(target_os = "linux", target_env = "gnu"), target_vendor = "apple")
+        ))]
+        {
+            let mut state = (&mut backtrace, ALLOCATOR_ENTRY.get());
+
+            unsafe {
+                unwind::_Unwind_Backtrace(
+                    Self::trace_frame,
+                    &mut state as *mut (&mut Self, usize) as *mut std::ffi::c_void,
+                )
+            };
+        }
+
+        backtrace
+    }
+
+    /*
+     * Keeps a frame of the unwinder, the canonical frame address of a frame is the stack pointer of its
+     * caller, so the frames of the allocator are the ones whose address isn't above the allocator entry
+     */
+    #[cfg(all(
+        not(miri),
+        not(target_arch = "arm"),
+        any(all(target_os = "linux", target_env = "gnu"), target_vendor = "apple")
+    ))]
+    extern "C" fn trace_frame(
+        context: *mut std::ffi::c_void,
+        state: *mut std::ffi::c_void,
+    ) -> std::ffi::c_int {
+        let (backtrace, allocator_entry) = unsafe { &mut *(state as *mut (&mut Self, usize)) };
+
+        if unsafe { unwind::_Unwind_GetCFA(context) } <= *allocator_entry {
+            return unwind::URC_NO_REASON;
+        }
+
+        backtrace.frames[backtrace.len] = unsafe { unwind::_Unwind_GetIP(context) };
+        backtrace.len += 1;
+
+        match backtrace.len == CALL_SITE_DEPTH {
+            true => unwind::URC_NORMAL_STOP,
+            false => unwind::URC_NO_REASON,
+        }
+    }
+
+    pub fn frames(&self) -> &[usize] {
+        &self.frames[..self.len]
+    }
+
+    /**
+     * Gets the FNV-1a hash of the frames, it identifies the call site
+     */
+    pub fn hash(&self) -> u64 {
+        self.frames()
+            .iter()
+            .flat_map(|frame| frame.to_ne_bytes())
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+}
+
+impl fmt::Display for CompactBacktrace {
+    /*
+     * Frames are resolved with dladdr, so only exported symbols have a name, the other frames are shown
+     * as an offset inside their object
+     */
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, frame) in self.frames().iter().enumerate() {
+            let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+            let found = unsafe { libc::dladdr(*frame as *const libc::c_void, &mut info) } != 0;
+
+            write!(formatter, "{index:4}: {frame:#x}")?;
+
+            if found && !info.dli_sname.is_null() {
+                let name = unsafe { CStr::from_ptr(info.dli_sname) };
+
+                write!(formatter, " in {}", name.to_string_lossy())?;
+            } else if found && !info.dli_fname.is_null() {
+                let object = unsafe { CStr::from_ptr(info.dli_fname) };
+
+                write!(
+                    formatter,
+                    " in {}+{:#x}",
+                    object.to_string_lossy(),
+                    frame - info.dli_fbase as usize
+                )?;
+            }
+
+            writeln!(formatter)?;
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Live allocations of a call site
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallSiteStats {
+    pub hash: u64,
+    pub backtrace: CompactBacktrace,
+    pub live_bytes: usize,
+    pub live_count: usize,
+}
+
+/*
+ * Call site of every live allocation by user pointer, and the call sites by hash
+ */
+#[derive(Default)]
+struct CallSiteTable {
+    live: HashMap<usize, (u64, usize)>,
+    sites: HashMap<u64, CallSiteStats>,
+}
+
+/**
+ * Optional side table of a heap that stores the call site of every live allocation, the allocations done
+ * before enabling it aren't recorded
+ *
+ * The table is updated while the heap lock is taken, its maps use the global allocator of the program,
+ * never the heap that is being recorded
+ */
+pub struct CallSites {
+    enabled: AtomicBool,
+    table: Mutex<Option<CallSiteTable>>,
+}
+
+impl Default for CallSites {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CallSites {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            table: Mutex::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Enables or disables recording call sites, disabling it forgets all the recorded call sites.
+     */
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+
+        if !enabled {
+            *self.table.lock().unwrap() = None;
+        }
+    }
+
+    /**
+     * Forgets all the recorded call sites, it's used when the memory of the heap is given back
+     */
+    pub fn clear(&self) {
+        *self.table.lock().unwrap() = None;
+    }
+
+    /**
+     * Records the call site of an allocation, the heap lock must be taken by the caller
+     */
+    pub fn record_allocation(&self, usr_address: usize, size: usize) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let backtrace = CompactBacktrace::capture();
+        let hash = backtrace.hash();
+        let mut table = self.table.lock().unwrap();
+        let table = table.get_or_insert_default();
+        let site = table.sites.entry(hash).or_insert(CallSiteStats {
+            hash,
+            backtrace,
+            live_bytes: 0,
+            live_count: 0,
+        });
+
+        site.live_bytes += size;
+        site.live_count += 1;
+        table.live.insert(usr_address, (hash, size));
+    }
+
+    /**
+     * Forgets the call site of a deallocation, the heap lock must be taken by the caller
+     */
+    pub fn record_free(&self, usr_address: usize) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut table = self.table.lock().unwrap();
+        let Some(table) = table.as_mut() else {
+            return;
+        };
+        let Some((hash, size)) = table.live.remove(&usr_address) else {
+            return;
+        };
+
+        if let Some(site) = table.sites.get_mut(&hash) {
+            site.live_bytes -= size;
+            site.live_count -= 1;
+
+            if site.live_count == 0 {
+                table.sites.remove(&hash);
+            }
+        }
+    }
+
+    /**
+     * Gets the call site of a live allocation
+     */
+    pub fn call_site(&self, usr_address: usize) -> Option<CompactBacktrace> {
+        let table = self.table.lock().unwrap();
+        let table = table.as_ref()?;
+        let (hash, _) = table.live.get(&usr_address)?;
+
+        table.sites.get(hash).map(|site| site.backtrace)
+    }
+
+    /**
+     * Gets the call sites with the most live bytes.
+     *
+     * @param count Maximum number of call sites returned.
+     */
+    pub fn top_by_bytes(&self, count: usize) -> Vec<CallSiteStats> {
+        self.top_by(count, |site| site.live_bytes)
+    }
+
+    /**
+     * Gets the call sites with the most live allocations.
+     *
+     * @param count Maximum number of call sites returned.
+     */
+    pub fn top_by_count(&self, count: usize) -> Vec<CallSiteStats> {
+        self.top_by(count, |site| site.live_count)
+    }
+
+    fn top_by(&self, count: usize, key: impl Fn(&CallSiteStats) -> usize) -> Vec<CallSiteStats> {
+        let mut sites: Vec<CallSiteStats> = self
+            .table
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|table| table.sites.values().copied().collect())
+            .unwrap_or_default();
+
+        sites.sort_by_key(|site| std::cmp::Reverse(key(site)));
+        sites.truncate(count);
+
+        sites
+    }
+}
+
+impl ForkLocks for CallSites {
+    fn lock_all(&'static self, guards: &mut ForkGuards) {
+        guards.push(lock_for_fork(&self.table));
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::{
+    fork::{ForkGuards, ForkLocks, lock_for_fork},
+    walk::HeapEntry,
+};
+
+/*
+ * Size of the canary placed after the user data, the canary placed before the user data is the
+ * front_canary field of the headers
+ */
+pub const CANARY_SIZE: usize = size_of::<u64>();
+
+/*
+ * Seed of the canaries, it's mixed with the header address so a copied header doesn't have a valid canary
+ */
+const CANARY_SEED: u64 = 0x5a17_c0de_feed_f00d;
+
+/**
+ * Gets the canary of the block that haves the header in the given address
+ */
+pub fn canary_value(header_address: usize) -> u64 {
+    CANARY_SEED ^ header_address as u64
+}
+
+/**
+ * Counts the bytes of a canary that were changed
+ */
+pub fn count_clobbered_bytes(found: u64, expected: u64) -> usize {
+    found
+        .to_ne_bytes()
+        .iter()
+        .zip(expected.to_ne_bytes())
+        .filter(|(found, expected)| **found != *expected)
+        .count()
+}
+
+/**
+ * Gets the size that must be taken from the heap for storing the user data and its rear canary, or None if it
+ * overflows
+ *
+ * @note The mode must be read once per allocation and the same value given to arm_canaries, if it's enabled
+ * between both the rear canary would be written after the end of the block.
+ */
+pub fn canary_block_size(usr_size: usize, has_canaries: bool) -> Option<usize> {
+    match has_canaries {
+        true => usr_size.checked_add(CANARY_SIZE),
+        false => Some(usr_size),
+    }
+}
+
+/**
+ * Writes the canary placed after the user data, it isn't aligned because the user size can be any size
+ */
+pub(crate) unsafe fn write_rear_canary(usr_address: usize, usr_size: usize, canary: u64) {
+    unsafe { ((usr_address + usr_size) as *mut u64).write_unaligned(canary) };
+}
+
+pub(crate) unsafe fn read_rear_canary(usr_address: usize, usr_size: usize) -> u64 {
+    unsafe { ((usr_address + usr_size) as *const u64).read_unaligned() }
+}
+
+/**
+ * Report of a block whose canaries were changed, the front canary is changed by writes before the user
+ * pointer and the rear canary by writes after the user size
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OverflowReport {
+    pub block: HeapEntry,
+    // Size asked by the user, the rear canary is just after it
+    pub usr_size: usize,
+    pub front_clobbered: usize,
+    pub rear_clobbered: usize,
+}
+
+impl fmt::Display for OverflowReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "buffer overflow in block {:#x}: user pointer {:#x}, size {} bytes, {} bytes clobbered before and {} bytes clobbered after the user data",
+            self.block.address,
+            self.block.usr_address,
+            self.usr_size,
+            self.front_clobbered,
+            self.rear_clobbered
+        )
+    }
+}
+
+/**
+ * Function that receives the overflow reports, it's an Arc because it's cloned out of its lock before being
+ * called, so it can use the heap or change the handler
+ */
+pub type OverflowHandler = Arc<dyn Fn(&OverflowReport) + Send + Sync>;
+
+/**
+ * Hardening mode of a heap, when it's enabled every new allocation gets canaries before and after its user data
+ *
+ * The mode can be changed at any time, blocks remember if they were allocated with canaries
+ */
+pub struct Canaries {
+    enabled: AtomicBool,
+    handler: Mutex<Option<OverflowHandler>>,
+}
+
+impl Default for Canaries {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Canaries {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            handler: Mutex::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /**
+     * Sets the function that receives the overflow reports found on free, None prints them to stderr
+     */
+    pub fn set_handler(&self, handler: Option<OverflowHandler>) {
+        *self.handler.lock().unwrap() = handler;
+    }
+
+    /**
+     * Gives the overflow report to the handler, it must be called after releasing the heap lock, so the
+     * handler can use the heap
+     */
+    pub fn report(&self, report: Option<OverflowReport>) {
+        let Some(report) = report else {
+            return;
+        };
+
+        let handler = self.handler.lock().unwrap().clone();
+
+        match handler {
+            Some(handler) => handler(&report),
+            None => eprintln!("{report}"),
+        }
+    }
+}
+
+impl ForkLocks for Canaries {
+    fn lock_all(&'static self, guards: &mut ForkGuards) {
+        guards.push(lock_for_fork(&self.handler));
+    }
+}
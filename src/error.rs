@@ -0,0 +1,135 @@
+use std::{fmt, io};
+
+use crate::{tags::AllocTag, verify::HeapError};
+
+/**
+ * Call to the OS that failed
+ *
+ * @note Sources that don't call the OS, like BufferSource or SimulatedSource, report the call they are
+ * emulating with ENOMEM when they are full.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OsCall {
+    Sbrk,
+    Mmap,
+    Mprotect,
+    Madvise,
+    Munmap,
+    PthreadAtfork,
+}
+
+impl fmt::Display for OsCall {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OsCall::Sbrk => "sbrk",
+            OsCall::Mmap => "mmap",
+            OsCall::Mprotect => "mprotect",
+            OsCall::Madvise => "madvise",
+            OsCall::Munmap => "munmap",
+            OsCall::PthreadAtfork => "pthread_atfork",
+        };
+
+        formatter.write_str(name)
+    }
+}
+
+/**
+ * Reason why an allocation failed
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocError {
+    // The OS or the memory source refused a call, errno is the one set by the call
+    OsRefused { call: OsCall, errno: i32 },
+    // The size plus the headers of the allocator doesn't fit in the size type of the allocator
+    SizeOverflow { size: usize },
+    // The alignment isn't a power of two or it's bigger than the alignment the allocator can give
+    InvalidAlignment { align: usize },
+    // The allocation would take the heap over its quota
+    QuotaExceeded { limit: usize, requested: usize },
+    // The allocation would take its tag over the limit of the tag
+    TagLimitExceeded { tag: AllocTag, limit: usize, requested: usize },
+    // The heap was found corrupted before allocating, so nothing was allocated from it
+    CorruptedHeap(HeapError),
+}
+
+impl AllocError {
+    /**
+     * Creates an OsRefused with the errno of the last call to the OS.
+     *
+     * @note It must be called right after the call that failed, before anything else can change errno.
+     */
+    pub fn last_os_error(call: OsCall) -> Self {
+        AllocError::OsRefused {
+            call,
+            errno: io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::ENOMEM),
+        }
+    }
+
+    /**
+     * Creates an OsRefused for a source that ran out of memory without calling the OS
+     */
+    pub fn out_of_memory(call: OsCall) -> Self {
+        AllocError::OsRefused {
+            call,
+            errno: libc::ENOMEM,
+        }
+    }
+
+    /**
+     * Creates an OsRefused for a source that can't do the call with the given arguments without calling
+     * the OS, like giving back memory that it doesn't have
+     */
+    pub fn invalid_argument(call: OsCall) -> Self {
+        AllocError::OsRefused {
+            call,
+            errno: libc::EINVAL,
+        }
+    }
+
+    /**
+     * Checks if the allocation failed because there isn't memory for it, freeing memory can make it succeed
+     */
+    pub fn is_out_of_memory(&self) -> bool {
+        matches!(
+            self,
+            AllocError::OsRefused { .. }
+                | AllocError::QuotaExceeded { .. }
+                | AllocError::TagLimitExceeded { .. }
+        )
+    }
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocError::OsRefused { call, errno } => write!(
+                formatter,
+                "{call} failed: {}",
+                io::Error::from_raw_os_error(*errno)
+            ),
+            AllocError::SizeOverflow { size } => {
+                write!(formatter, "the size {size} overflows with the headers")
+            }
+            AllocError::InvalidAlignment { align } => {
+                write!(formatter, "the alignment {align} isn't supported")
+            }
+            AllocError::QuotaExceeded { limit, requested } => write!(
+                formatter,
+                "allocating {requested} bytes goes over the quota of {limit} bytes"
+            ),
+            AllocError::TagLimitExceeded {
+                tag,
+                limit,
+                requested,
+            } => write!(
+                formatter,
+                "allocating {requested} bytes goes over the limit of {limit} bytes of the tag {tag}"
+            ),
+            AllocError::CorruptedHeap(err) => write!(formatter, "the heap is corrupted: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for AllocError {}
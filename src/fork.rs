@@ -0,0 +1,280 @@
+use std::{
+    cell::RefCell,
+    mem::MaybeUninit,
+    sync::{
+        Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU8, Ordering},
+    },
+};
+
+use libc::pthread_atfork;
+
+use crate::{
+    bump::globals::bump_memory,
+    error::{AllocError, OsCall},
+    guard::globals::guard_state,
+    mmap::globals::mmap_memory,
+    trace::globals::trace_state,
+};
+
+/*
+ * States of the registration of the fork handlers, a registration that fails goes back to unregistered, so
+ * the next allocation tries it again
+ */
+const HANDLERS_UNREGISTERED: u8 = 0;
+const HANDLERS_REGISTERING: u8 = 1;
+const HANDLERS_REGISTERED: u8 = 2;
+
+static FORK_HANDLERS_STATE: AtomicU8 = AtomicU8::new(HANDLERS_UNREGISTERED);
+
+/*
+ * Maximum number of locks taken by prepare_fork, the heap parts take at most two locks each
+ */
+const MAX_FORK_GUARDS: usize = 64;
+
+/*
+ * Memory of a MutexGuard of any type, a MutexGuard is a reference to its Mutex and the poison flag, so two
+ * words are enough for all of them
+ */
+type GuardStorage = MaybeUninit<[usize; 2]>;
+
+/**
+ * A guard taken by prepare_fork, it's only kept for releasing its lock after fork
+ *
+ * The guard is stored inline instead of boxed, because prepare_fork runs in the middle of fork and it can't
+ * count on the C library allocator, which can be locked by other thread or locked by its own fork handlers.
+ */
+pub struct ForkGuard {
+    storage: GuardStorage,
+    release: unsafe fn(&mut GuardStorage),
+}
+
+impl ForkGuard {
+    fn new<T: 'static>(guard: MutexGuard<'static, T>) -> Self {
+        const {
+            assert!(size_of::<MutexGuard<'static, T>>() <= size_of::<GuardStorage>());
+            assert!(align_of::<MutexGuard<'static, T>>() <= align_of::<GuardStorage>());
+        }
+
+        let mut storage = GuardStorage::uninit();
+
+        unsafe { storage.as_mut_ptr().cast::<MutexGuard<'static, T>>().write(guard) };
+
+        Self {
+            storage,
+            release: Self::release_guard::<T>,
+        }
+    }
+
+    /*
+     * The storage must have a MutexGuard of T written by new, it's dropped only once by the drop of the
+     * ForkGuard
+     */
+    unsafe fn release_guard<T: 'static>(storage: &mut GuardStorage) {
+        unsafe {
+            storage
+                .as_mut_ptr()
+                .cast::<MutexGuard<'static, T>>()
+                .drop_in_place()
+        };
+    }
+}
+
+impl Drop for ForkGuard {
+    fn drop(&mut self) {
+        unsafe { (self.release)(&mut self.storage) };
+    }
+}
+
+/**
+ * Guards taken by prepare_fork, they are kept in a fixed array, so taking them doesn't allocate memory
+ *
+ * @note The guards aren't released when the list is dropped, release must be called, so the thread local
+ * that keeps them doesn't need a destructor, which would allocate memory on its first use.
+ */
+pub struct ForkGuards {
+    guards: [MaybeUninit<ForkGuard>; MAX_FORK_GUARDS],
+    len: usize,
+}
+
+impl ForkGuards {
+    const fn new() -> Self {
+        Self {
+            guards: [const { MaybeUninit::uninit() }; MAX_FORK_GUARDS],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, guard: ForkGuard) {
+        assert!(
+            self.len < MAX_FORK_GUARDS,
+            "prepare_fork takes more than {MAX_FORK_GUARDS} locks"
+        );
+
+        self.guards[self.len].write(guard);
+        self.len += 1;
+    }
+
+    /*
+     * Releases the locks in the reverse order in which they were taken
+     */
+    fn release(&mut self) {
+        while self.len > 0 {
+            self.len -= 1;
+            unsafe { self.guards[self.len].assume_init_drop() };
+        }
+    }
+}
+
+/*
+ * Guards taken by the prepare handler, the thread that calls fork is the same thread that runs the
+ * prepare, parent and child handlers, so a thread local is enough for passing the guards between them
+ */
+thread_local! {
+    static FORK_GUARDS: RefCell<ForkGuards> = const { RefCell::new(ForkGuards::new()) };
+}
+
+/**
+ * Locks of the heap parts that haves their own Mutex, they are taken by prepare_fork too, so the child doesn't
+ * get a copy of a lock held by other thread
+ *
+ * They are taken after the heap locks, because the state of the heap parts is updated while the heap lock
+ * is taken. The handlers are cloned out of their lock before being called, so no other lock is taken while
+ * a lock of a heap part is held.
+ */
+pub trait ForkLocks {
+    fn lock_all(&'static self, guards: &mut ForkGuards);
+}
+
+/*
+ * A Mutex that isn't part of a bigger heap part, like the tuning of a heap
+ */
+impl<T: 'static> ForkLocks for Mutex<T> {
+    fn lock_all(&'static self, guards: &mut ForkGuards) {
+        guards.push(lock_for_fork(self));
+    }
+}
+
+/*
+ * Parts of both heaps that are locked by prepare_fork, always in this order
+ */
+fn heap_parts() -> [&'static dyn ForkLocks; 22] {
+    [
+        &bump_memory.free_checks,
+        &mmap_memory.free_checks,
+        &bump_memory.quarantine,
+        &mmap_memory.quarantine,
+        &bump_memory.canaries,
+        &mmap_memory.canaries,
+        &bump_memory.leak_suppressions,
+        &mmap_memory.leak_suppressions,
+        &bump_memory.call_sites,
+        &mmap_memory.call_sites,
+        &bump_memory.profiler,
+        &mmap_memory.profiler,
+        &bump_memory.watermarks,
+        &mmap_memory.watermarks,
+        &bump_memory.oom,
+        &mmap_memory.oom,
+        &bump_memory.tuning,
+        &mmap_memory.tuning,
+        &bump_memory.counters.last_os_error,
+        &mmap_memory.counters.last_os_error,
+        &bump_memory.hooks,
+        &mmap_memory.hooks,
+    ]
+}
+
+/**
+ * Takes a lock before fork, a poisoned lock is taken too, because a panic inside the fork handlers
+ * aborts the process, and the data of a poisoned lock is as consistent as it is for the thread that forks
+ */
+pub fn lock_for_fork<T: 'static>(mutex: &'static Mutex<T>) -> ForkGuard {
+    ForkGuard::new(mutex.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/**
+ * Registers the fork handlers for the allocators, it's safe to call it many times because
+ * handlers are registered only once per process
+ *
+ * If a process with many threads forks while other thread holds bump_memory or mmap_memory, the child
+ * process will have a copy of the locked mutex but not the thread that owns it, so the child deadlocks
+ * on its next allocation. For avoiding it, we take every allocator lock before fork, so the heap is in a
+ * consistent state when the address space is copied, and we release them in both processes after fork
+ *
+ * @return The error of pthread_atfork if the handlers can't be registered, then they aren't registered and
+ * the next call tries it again.
+ *
+ * @note This function is thread-safe, while a thread is registering the handlers the other threads return
+ * without waiting for it.
+ */
+pub fn register_fork_handlers() -> Result<(), AllocError> {
+    if FORK_HANDLERS_STATE.load(Ordering::Acquire) != HANDLERS_UNREGISTERED
+        || FORK_HANDLERS_STATE
+            .compare_exchange(
+                HANDLERS_UNREGISTERED,
+                HANDLERS_REGISTERING,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_err()
+    {
+        return Ok(());
+    }
+
+    /*
+     * pthread_atfork returns the error number instead of setting errno
+     */
+    let errno = unsafe {
+        pthread_atfork(
+            Some(prepare_fork),
+            Some(release_after_fork),
+            Some(release_after_fork),
+        )
+    };
+
+    if errno != 0 {
+        FORK_HANDLERS_STATE.store(HANDLERS_UNREGISTERED, Ordering::Release);
+
+        return Err(AllocError::OsRefused {
+            call: OsCall::PthreadAtfork,
+            errno,
+        });
+    }
+
+    FORK_HANDLERS_STATE.store(HANDLERS_REGISTERED, Ordering::Release);
+
+    Ok(())
+}
+
+/**
+ * Takes all the allocator locks before fork, the lock order must be always the same (bump, mmap, the guard page
+ * mode, the trace recorder and then the heap parts) for avoiding deadlocks against other thread that is doing
+ * the same
+ *
+ * @note The guards are kept in a thread local array, so this function doesn't allocate memory.
+ */
+extern "C" fn prepare_fork() {
+    FORK_GUARDS.with(|fork_guards| {
+        let mut guards = fork_guards.borrow_mut();
+
+        guards.push(lock_for_fork(&bump_memory.memory));
+        guards.push(lock_for_fork(&mmap_memory.memory));
+        guards.push(lock_for_fork(&*guard_state));
+        guards.push(lock_for_fork(&*trace_state));
+
+        for part in heap_parts() {
+            part.lock_all(&mut guards);
+        }
+    });
+}
+
+/**
+ * Releases the locks taken by prepare_fork, it runs in the parent after fork and in the child too
+ *
+ * In the child process the thread that called fork is the only thread alive and it's the owner of the
+ * locks, so releasing the guards leaves the allocators unlocked and the heap as it was before fork
+ */
+extern "C" fn release_after_fork() {
+    FORK_GUARDS.with(|fork_guards| fork_guards.borrow_mut().release());
+}
@@ -0,0 +1,305 @@
+use std::{
+    backtrace::Backtrace,
+    collections::HashMap,
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+    },
+};
+
+use crate::{
+    fork::{ForkGuards, ForkLocks, lock_for_fork},
+    walk::HeapEntry,
+};
+
+/**
+ * Kind of a deallocation that the heap can't do
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidFreeKind {
+    // The block was already deallocated
+    DoubleFree,
+    // The pointer is inside a block but it isn't the pointer given to the user
+    InteriorPointer,
+    // The pointer doesn't belong to the heap
+    ForeignPointer,
+    // The heap was torn down before the deallocation
+    AfterTeardown,
+}
+
+/**
+ * What a heap does when it finds an invalid deallocation
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InvalidFreeAction {
+    // Prints the report and aborts the process
+    Abort,
+    // Prints the report to stderr, or gives it to the report handler if there is one, and continues
+    Log,
+    // Continues without doing anything, like the allocators did before
+    Ignore,
+}
+
+/**
+ * Invalid deallocation found by a heap, it's detected and built while the heap lock is taken
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidFree {
+    pub kind: InvalidFreeKind,
+    // The block or section that contains the pointer, None if it isn't in the heap
+    pub block: Option<HeapEntry>,
+}
+
+/**
+ * Report of an invalid deallocation
+ *
+ * The backtraces are only captured when they are enabled with FreeChecks::set_backtraces, and only for
+ * the allocations and deallocations done after enabling them
+ */
+#[derive(Debug)]
+pub struct InvalidFreeReport {
+    pub kind: InvalidFreeKind,
+    pub ptr: usize,
+    pub block: Option<HeapEntry>,
+    pub allocation_backtrace: Option<String>,
+    pub first_free_backtrace: Option<String>,
+}
+
+impl fmt::Display for InvalidFreeReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self.kind {
+            InvalidFreeKind::DoubleFree => "double free",
+            InvalidFreeKind::InteriorPointer => "free of an interior pointer",
+            InvalidFreeKind::ForeignPointer => "free of a pointer that doesn't belong to the heap",
+            InvalidFreeKind::AfterTeardown => "free after the heap was torn down",
+        };
+
+        writeln!(formatter, "{description}: {:#x}", self.ptr)?;
+
+        match &self.block {
+            Some(block) => writeln!(
+                formatter,
+                "block {:#x}: user pointer {:#x}, size {} bytes, free: {}",
+                block.address, block.usr_address, block.size, block.is_free
+            )?,
+            None => writeln!(formatter, "no block contains the pointer")?,
+        }
+
+        if let Some(backtrace) = &self.allocation_backtrace {
+            writeln!(formatter, "allocated at:\n{backtrace}")?;
+        }
+
+        if let Some(backtrace) = &self.first_free_backtrace {
+            writeln!(formatter, "first freed at:\n{backtrace}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Function that receives the invalid free reports, it's an Arc because it's cloned out of its lock before
+ * being called, so it can use the heap or change the handler
+ */
+pub type InvalidFreeHandler = Arc<dyn Fn(&InvalidFreeReport) + Send + Sync>;
+
+/*
+ * Backtraces of the live pointers and of the pointers that were deallocated, the allocation backtrace of
+ * a deallocated pointer is kept for reporting double frees
+ */
+#[derive(Default)]
+struct FreeTraces {
+    allocations: HashMap<usize, Backtrace>,
+    frees: HashMap<usize, (Option<Backtrace>, Backtrace)>,
+}
+
+/**
+ * State used by a heap for detecting invalid deallocations and reporting them
+ */
+pub struct FreeChecks {
+    action: AtomicU8,
+    backtraces: AtomicBool,
+    torn_down: AtomicBool,
+    traces: Mutex<Option<FreeTraces>>,
+    handler: Mutex<Option<InvalidFreeHandler>>,
+}
+
+impl Default for FreeChecks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FreeChecks {
+    pub const fn new() -> Self {
+        Self {
+            action: AtomicU8::new(InvalidFreeAction::Log as u8),
+            backtraces: AtomicBool::new(false),
+            torn_down: AtomicBool::new(false),
+            traces: Mutex::new(None),
+            handler: Mutex::new(None),
+        }
+    }
+
+    pub fn action(&self) -> InvalidFreeAction {
+        match self.action.load(Ordering::Relaxed) {
+            0 => InvalidFreeAction::Abort,
+            1 => InvalidFreeAction::Log,
+            _ => InvalidFreeAction::Ignore,
+        }
+    }
+
+    pub fn set_action(&self, action: InvalidFreeAction) {
+        self.action.store(action as u8, Ordering::Relaxed);
+    }
+
+    /**
+     * Enables or disables capturing the backtraces of allocations and deallocations.
+     *
+     * @warning Capturing a backtrace is slow and the backtraces of deallocated pointers are kept until the
+     * pointer is allocated again, so this option is only for debugging.
+     */
+    pub fn set_backtraces(&self, enabled: bool) {
+        self.backtraces.store(enabled, Ordering::Relaxed);
+
+        if !enabled {
+            *self.traces.lock().unwrap() = None;
+        }
+    }
+
+    pub fn has_backtraces(&self) -> bool {
+        self.backtraces.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Sets the function that receives the reports when the action is Log, None prints them to stderr
+     */
+    pub fn set_handler(&self, handler: Option<InvalidFreeHandler>) {
+        *self.handler.lock().unwrap() = handler;
+    }
+
+    pub fn is_torn_down(&self) -> bool {
+        self.torn_down.load(Ordering::Relaxed)
+    }
+
+    pub fn set_torn_down(&self, torn_down: bool) {
+        self.torn_down.store(torn_down, Ordering::Relaxed);
+    }
+
+    /**
+     * Records the backtrace of an allocation, the heap lock must be taken by the caller
+     */
+    pub fn record_allocation(&self, usr_address: usize) {
+        if !self.backtraces.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut traces = self.traces.lock().unwrap();
+        let traces = traces.get_or_insert_default();
+
+        traces.frees.remove(&usr_address);
+        traces
+            .allocations
+            .insert(usr_address, Backtrace::force_capture());
+    }
+
+    /**
+     * Records the backtrace of a deallocation, the heap lock must be taken by the caller
+     */
+    pub fn record_free(&self, usr_address: usize) {
+        if !self.backtraces.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut traces = self.traces.lock().unwrap();
+        let traces = traces.get_or_insert_default();
+        let allocation = traces.allocations.remove(&usr_address);
+
+        traces
+            .frees
+            .insert(usr_address, (allocation, Backtrace::force_capture()));
+    }
+
+    /**
+     * Gets the backtrace of a live allocation, if it was captured
+     */
+    pub fn allocation_backtrace(&self, usr_address: usize) -> Option<String> {
+        self.traces
+            .lock()
+            .unwrap()
+            .as_ref()?
+            .allocations
+            .get(&usr_address)
+            .map(|backtrace| backtrace.to_string())
+    }
+
+    /**
+     * Checks if the pointer was deallocated before, it's used for finding double frees of blocks that were
+     * already given back to the memory source
+     */
+    pub fn was_freed(&self, usr_address: usize) -> bool {
+        self.traces
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|traces| traces.frees.contains_key(&usr_address))
+    }
+
+    /**
+     * Builds the report of an invalid deallocation and runs the configured action, it must be called after
+     * releasing the heap lock, so the handler can use the heap
+     */
+    pub fn report(&self, ptr: usize, invalid_free: InvalidFree) {
+        let action = self.action();
+
+        if action == InvalidFreeAction::Ignore {
+            return;
+        }
+
+        let report = self.build_report(ptr, invalid_free);
+
+        if action == InvalidFreeAction::Abort {
+            eprintln!("{report}");
+            std::process::abort();
+        }
+
+        let handler = self.handler.lock().unwrap().clone();
+
+        match handler {
+            Some(handler) => handler(&report),
+            None => eprintln!("{report}"),
+        }
+    }
+
+    fn build_report(&self, ptr: usize, invalid_free: InvalidFree) -> InvalidFreeReport {
+        let usr_address = invalid_free
+            .block
+            .map(|block| block.usr_address)
+            .unwrap_or(ptr);
+        let traces = self.traces.lock().unwrap();
+        let (allocation_backtrace, first_free_backtrace) = match traces.as_ref() {
+            Some(traces) => match traces.frees.get(&usr_address) {
+                Some((allocation, first_free)) => (allocation.as_ref(), Some(first_free)),
+                None => (traces.allocations.get(&usr_address), None),
+            },
+            None => (None, None),
+        };
+
+        InvalidFreeReport {
+            kind: invalid_free.kind,
+            ptr,
+            block: invalid_free.block,
+            allocation_backtrace: allocation_backtrace.map(|backtrace| backtrace.to_string()),
+            first_free_backtrace: first_free_backtrace.map(|backtrace| backtrace.to_string()),
+        }
+    }
+}
+
+impl ForkLocks for FreeChecks {
+    fn lock_all(&'static self, guards: &mut ForkGuards) {
+        guards.push(lock_for_fork(&self.handler));
+        guards.push(lock_for_fork(&self.traces));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{sync::atomic::Ordering, time::Instant};
+
+use crate::{
+    error::AllocError,
+    free_check::{InvalidFree, InvalidFreeKind},
+    hooks::{EventKind, HeapEvent},
+    mmap::globals::mmap_memory,
+};
+
+use super::{
+    GuardOptions,
+    globals::{guard_enabled, guard_state, guard_used},
+    utils::{map_guarded, protect_pages, release_expired_mappings, unmap_guarded},
+};
+
+/**
+ * Allocator of the guard page mode of MmapAllocator, see GuardOptions
+ *
+ * The events are sent to the hook of the mmap allocator, the mappings send RegionMap and RegionUnmap like
+ * the regions of the heap, and they are sent without the guard lock, so the hook can allocate
+ *
+ * @note The mappings are counted in the mmap and munmap calls of the mmap allocator statistics, but they
+ * aren't part of its heap size because they aren't regions.
+ */
+pub struct GuardAllocator {}
+
+impl GuardAllocator {
+    /**
+     * Enables the guard page mode for the next allocations, or changes its options.
+     */
+    pub fn enable(options: GuardOptions) {
+        guard_state.lock().unwrap().options = options;
+        guard_used.store(true, Ordering::SeqCst);
+        guard_enabled.store(true, Ordering::SeqCst);
+    }
+
+    /**
+     * Disables the guard page mode, the allocations done while it was enabled keep their guard pages until
+     * they are freed.
+     */
+    pub fn disable() {
+        guard_enabled.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled() -> bool {
+        guard_enabled.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Allocate memory in its own mapping with guard pages.
+     *
+     * @param size The size of the memory to allocate.
+     * @return A pointer to the allocated memory, it ends just before the trailing guard page when
+     * the size is a multiple of the alignment of T. Or the reason why it can't be allocated.
+     *
+     * @note This function is thread-safe.
+     */
+    pub fn allocate<T>(size: usize) -> Result<*mut T, AllocError> {
+        let usr_address = Self::allocate_mapping(size, align_of::<T>())?;
+
+        mmap_memory.hooks.flush();
+        mmap_memory.hooks.dispatch(HeapEvent {
+            kind: EventKind::Alloc,
+            address: usr_address,
+            size,
+        });
+
+        Ok(usr_address as *mut T)
+    }
+
+    fn allocate_mapping(size: usize, align: usize) -> Result<usize, AllocError> {
+        let mut state = guard_state.lock().unwrap();
+        release_expired_mappings(&mut state);
+
+        let mapping = map_guarded(size, align, state.options.leading_guard)?;
+
+        state.live.insert(mapping.usr_address, mapping);
+        mmap_memory
+            .free_checks
+            .record_allocation(mapping.usr_address);
+        mmap_memory
+            .call_sites
+            .record_allocation(mapping.usr_address, mapping.usr_size);
+        mmap_memory
+            .profiler
+            .record_allocation(mapping.usr_address, mapping.usr_size);
+        mmap_memory.leak_suppressions.forget(mapping.usr_address);
+        mmap_memory
+            .counters
+            .allocations
+            .fetch_add(1, Ordering::Relaxed);
+
+        Ok(mapping.usr_address)
+    }
+
+    /**
+     * Deallocate memory allocated by GuardAllocator::allocate, the pages are made PROT_NONE and they are
+     * unmapped after the free delay.
+     *
+     * @param usr_data The pointer to the memory to deallocate.
+     * @return True if the pointer belongs to a mapping of the guard page mode, even if it's an invalid free.
+     *
+     * @note Double frees and frees of interior pointers are reported with the invalid free action of the
+     * mmap allocator.
+     */
+    pub fn deallocate<T>(usr_data: *const T) -> bool {
+        if !guard_used.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        let address = usr_data as usize;
+        let result = {
+            let mut state = guard_state.lock().unwrap();
+
+            if let Some(mapping) = state.live.remove(&address) {
+                /*
+                 * If the pages can't be protected, the mapping is unmapped now, a use after free
+                 * faults anyway while the address isn't reused
+                 */
+                if protect_pages(mapping.start, mapping.size) {
+                    state.freed.push_back((mapping, Instant::now()));
+                } else {
+                    unmap_guarded(&mapping);
+                }
+
+                release_expired_mappings(&mut state);
+                mmap_memory.free_checks.record_free(address);
+                mmap_memory.call_sites.record_free(address);
+                mmap_memory.profiler.record_free(address);
+                mmap_memory.leak_suppressions.forget(address);
+                mmap_memory.counters.frees.fetch_add(1, Ordering::Relaxed);
+
+                Ok(mapping.usr_size)
+            } else if state.live.values().any(|mapping| mapping.contains(address)) {
+                Err(InvalidFreeKind::InteriorPointer)
+            } else if state
+                .freed
+                .iter()
+                .any(|(mapping, _)| mapping.contains(address))
+            {
+                Err(InvalidFreeKind::DoubleFree)
+            } else {
+                return false;
+            }
+        };
+
+        /*
+         * The events and the report are sent without the guard lock, so the hook and the report handler can
+         * allocate
+         */
+        match result {
+            Ok(usr_size) => mmap_memory.hooks.dispatch(HeapEvent {
+                kind: EventKind::Free,
+                address,
+                size: usr_size,
+            }),
+            Err(kind) => mmap_memory
+                .free_checks
+                .report(address, InvalidFree { kind, block: None }),
+        }
+
+        mmap_memory.hooks.flush();
+
+        true
+    }
+
+    /**
+     * Unmaps all the freed mappings without waiting for the free delay.
+     */
+    pub fn release_freed() {
+        {
+            let mut state = guard_state.lock().unwrap();
+
+            while let Some((mapping, _)) = state.freed.pop_front() {
+                unmap_guarded(&mapping);
+            }
+        }
+
+        mmap_memory.hooks.flush();
+    }
+}
@@ -0,0 +1,29 @@
+#![allow(non_upper_case_globals)]
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, atomic::AtomicBool},
+};
+
+use lazy_static::lazy_static;
+
+use super::{GuardOptions, GuardState};
+
+/*
+ * True while new allocations of the mmap allocator must use guard pages
+ */
+pub static guard_enabled: AtomicBool = AtomicBool::new(false);
+
+/*
+ * True since the first time the guard page mode was enabled, deallocations don't take the guard lock
+ * before it
+ */
+pub static guard_used: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    pub static ref guard_state: Mutex<GuardState> = Mutex::new(GuardState {
+        options: GuardOptions::default(),
+        live: HashMap::new(),
+        freed: VecDeque::new(),
+    });
+}
@@ -0,0 +1,66 @@
+use std::{collections::HashMap, collections::VecDeque, time::Duration, time::Instant};
+
+pub mod allocator;
+pub mod globals;
+pub mod utils;
+
+/**
+ * Guard page mode is a debug mode of the mmap allocator like Electric Fence, every allocation gets its own
+ * mapping and the user data is placed at the end of the mapping, just before a page that can't be accessed
+ *
+ * ________________________________________________________________
+ * | leading guard |     unused    |  user data  | trailing guard |
+ * |   PROT_NONE   |          read and write     |   PROT_NONE    |
+ * ________________________________________________________________
+ *
+ * So, a write after the end of the user data faults on the same instruction that does it, instead of
+ * corrupting other allocation. The leading guard is optional, it catches writes before the user data but the
+ * unused space between it and the user data isn't protected
+ *
+ * When an allocation is freed, all its pages are made PROT_NONE and the mapping is kept for some time before
+ * unmapping it, so a use after free faults too, and the address isn't reused by mmap while it's kept
+ *
+ * This mode uses a lot of memory, at least two pages per allocation, so it's only for finding memory bugs
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuardOptions {
+    pub leading_guard: bool,
+    // Time that a freed mapping is kept as PROT_NONE before unmapping it
+    pub free_delay: Duration,
+}
+
+impl Default for GuardOptions {
+    fn default() -> Self {
+        Self {
+            leading_guard: false,
+            free_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/**
+ * A mapping of one allocation, start and size describe the whole mapping including the guard pages
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuardedMapping {
+    pub start: usize,
+    pub size: usize,
+    pub usr_address: usize,
+    pub usr_size: usize,
+}
+
+impl GuardedMapping {
+    pub fn contains(&self, address: usize) -> bool {
+        (self.start..self.start + self.size).contains(&address)
+    }
+}
+
+/**
+ * Allocations done in guard page mode, the live ones by user pointer and the freed ones in the order they
+ * were freed with the moment they were freed
+ */
+pub struct GuardState {
+    pub options: GuardOptions,
+    pub live: HashMap<usize, GuardedMapping>,
+    pub freed: VecDeque<(GuardedMapping, Instant)>,
+}
@@ -0,0 +1,129 @@
+use std::{sync::atomic::Ordering, time::Instant};
+
+use libc::{PROT_NONE, mprotect};
+
+use crate::{
+    error::{AllocError, OsCall},
+    hooks::{EventKind, HeapEvent},
+    mmap::{
+        globals::mmap_memory,
+        utils::{get_page_size, round_up_to_page_size},
+    },
+    source::{MemorySource, mmap::MmapSource},
+};
+
+use super::{GuardState, GuardedMapping};
+
+/**
+ * Makes a range of pages inaccessible
+ */
+pub fn protect_pages(start: usize, size: usize) -> bool {
+    unsafe { mprotect(start as *mut _, size, PROT_NONE) == 0 }
+}
+
+/**
+ * Maps the pages of one allocation and protects its guard pages
+ *
+ * @param size The size of the user data.
+ * @param align The alignment of the user pointer, the user data is moved back from the trailing guard
+ * until the pointer is aligned.
+ * @param leading_guard Adds a guard page before the user data.
+ * @return The mapping, or the reason why it can't be mapped, alignments bigger than a page can't be given
+ * because the user data must end at the trailing guard.
+ */
+pub fn map_guarded(
+    size: usize,
+    align: usize,
+    leading_guard: bool,
+) -> Result<GuardedMapping, AllocError> {
+    let page_size = get_page_size();
+
+    if !align.max(1).is_power_of_two() || align > page_size {
+        return Err(AllocError::InvalidAlignment { align });
+    }
+
+    let leading_size = if leading_guard { page_size } else { 0 };
+
+    /*
+     * The size is rounded up to the page size and the guard pages are added, so it must leave room for
+     * them
+     */
+    if size > usize::MAX - leading_size - 2 * page_size {
+        return Err(AllocError::SizeOverflow { size });
+    }
+
+    let data_size = round_up_to_page_size(size.max(1), page_size);
+    let mapping_size = leading_size + data_size + page_size;
+
+    mmap_memory
+        .counters
+        .mmap_calls
+        .fetch_add(1, Ordering::Relaxed);
+    let start = MmapSource {}
+        .map(mapping_size)
+        .inspect_err(|err| mmap_memory.counters.record_os_failure(*err))? as usize;
+    let trailing_guard = start + leading_size + data_size;
+
+    if !protect_pages(trailing_guard, page_size)
+        || (leading_guard && !protect_pages(start, leading_size))
+    {
+        let error = AllocError::last_os_error(OsCall::Mprotect);
+
+        mmap_memory.counters.record_os_failure(error);
+
+        if let Err(err) = (MmapSource {}).unmap(start as *mut u8, mapping_size) {
+            mmap_memory.counters.record_os_failure(err);
+        }
+
+        return Err(error);
+    }
+
+    mmap_memory.hooks.record(HeapEvent {
+        kind: EventKind::RegionMap,
+        address: start,
+        size: mapping_size,
+    });
+
+    Ok(GuardedMapping {
+        start,
+        size: mapping_size,
+        usr_address: (trailing_guard - size) & !(align.max(1) - 1),
+        usr_size: size,
+    })
+}
+
+/**
+ * Unmaps the freed mappings that were kept for longer than the free delay
+ */
+pub fn release_expired_mappings(state: &mut GuardState) {
+    let now = Instant::now();
+
+    while let Some((mapping, freed_at)) = state.freed.front() {
+        if now.duration_since(*freed_at) < state.options.free_delay {
+            break;
+        }
+
+        unmap_guarded(mapping);
+        state.freed.pop_front();
+    }
+}
+
+/**
+ * Unmaps the pages of one allocation, if munmap fails the pages are lost, the failure is kept in the
+ * statistics of the mmap allocator
+ */
+pub fn unmap_guarded(mapping: &GuardedMapping) {
+    mmap_memory
+        .counters
+        .munmap_calls
+        .fetch_add(1, Ordering::Relaxed);
+
+    match (MmapSource {}).unmap(mapping.start as *mut u8, mapping.size) {
+        Ok(()) => mmap_memory.hooks.record(HeapEvent {
+            kind: EventKind::RegionUnmap,
+            address: mapping.start,
+            size: mapping.size,
+        }),
+        Err(err) => mmap_memory.counters.record_os_failure(err),
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    cell::Cell,
+    mem,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::fork::{ForkGuards, ForkLocks, lock_for_fork};
+
+/*
+ * Set while the hook runs on this thread, the events of the allocations done by the hook aren't sent, so the
+ * hook can't call itself
+ */
+thread_local! {
+    static IN_EVENT_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    // A block or section was given to the user, address is the user pointer
+    Alloc,
+    // A block or section was deallocated by the user, address is the user pointer
+    Free,
+    // A region was mapped by the mmap heap
+    RegionMap,
+    // A region was unmapped by the mmap heap
+    RegionUnmap,
+    // The program break or the memory source of the bump heap grew, address is the old break
+    BreakGrow,
+    // The program break or the memory source of the bump heap shrank, address is the new break
+    BreakShrink,
+}
+
+/**
+ * Event of a heap sent to its hook
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapEvent {
+    pub kind: EventKind,
+    pub address: usize,
+    // Size asked by the user for Alloc and Free, size of the memory taken or given back for the others, the
+    // Free of a small object haves the size of its class
+    pub size: usize,
+}
+
+/**
+ * Function that receives the events of a heap, it's an Arc because it's cloned out of its lock before being
+ * called, so it can use the heap
+ */
+pub type EventHook = Arc<dyn Fn(&HeapEvent) + Send + Sync>;
+
+/**
+ * Resets the reentrancy flag when the hook returns or panics
+ */
+struct HookGuard;
+
+impl Drop for HookGuard {
+    fn drop(&mut self) {
+        IN_EVENT_HOOK.set(false);
+    }
+}
+
+/**
+ * Hook of a heap, it's called on every allocation and deallocation and every time the heap takes or gives
+ * back memory from its memory source
+ *
+ * The events of the memory source happen with the heap lock taken, so they are kept until the operation
+ * releases the lock and then they are sent, always without the heap lock. Allocations and deallocations done
+ * by the hook don't send events.
+ *
+ * @note The events of an allocation that fails are sent by the next operation of the heap.
+ */
+pub struct EventHooks {
+    enabled: AtomicBool,
+    hook: Mutex<Option<EventHook>>,
+    pending: Mutex<Vec<HeapEvent>>,
+}
+
+impl Default for EventHooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventHooks {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            hook: Mutex::new(None),
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /**
+     * Sets the function that receives the events, None stops sending them
+     */
+    pub fn set_hook(&self, hook: Option<EventHook>) {
+        let mut hook_guard = self.hook.lock().unwrap();
+
+        self.enabled.store(hook.is_some(), Ordering::Relaxed);
+        *hook_guard = hook;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Checks if the hook is running on this thread
+     */
+    pub fn is_in_hook() -> bool {
+        IN_EVENT_HOOK.get()
+    }
+
+    /**
+     * Keeps an event of the memory source until flush, it's called with the heap lock taken
+     */
+    pub fn record(&self, event: HeapEvent) {
+        if !self.is_enabled() || Self::is_in_hook() {
+            return;
+        }
+
+        self.pending.lock().unwrap().push(event);
+    }
+
+    /**
+     * Sends the kept events, it must be called without the heap lock
+     */
+    pub fn flush(&self) {
+        if !self.is_enabled() || Self::is_in_hook() {
+            return;
+        }
+
+        let events = mem::take(&mut *self.pending.lock().unwrap());
+
+        for event in &events {
+            self.send(event);
+        }
+    }
+
+    /**
+     * Sends an event, it must be called without the heap lock
+     */
+    pub fn dispatch(&self, event: HeapEvent) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        self.send(&event);
+    }
+
+    fn send(&self, event: &HeapEvent) {
+        if Self::is_in_hook() {
+            return;
+        }
+
+        let Some(hook) = self.hook.lock().unwrap().clone() else {
+            return;
+        };
+
+        IN_EVENT_HOOK.set(true);
+        let _hook_guard = HookGuard;
+
+        hook(event);
+    }
+}
+
+impl ForkLocks for EventHooks {
+    fn lock_all(&'static self, guards: &mut ForkGuards) {
+        guards.push(lock_for_fork(&self.hook));
+        guards.push(lock_for_fork(&self.pending));
+    }
+}
@@ -0,0 +1,427 @@
+#![allow(non_upper_case_globals)]
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{
+        Mutex, Once,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+    },
+};
+
+use crate::{
+    bump::globals::bump_memory,
+    call_site::CallSites,
+    fork::{ForkGuards, ForkLocks, lock_for_fork},
+    free_check::FreeChecks,
+    guard::globals::guard_state,
+    mmap::globals::mmap_memory,
+    small::allocator::SmallAllocator,
+    tags::UNTAGGED,
+    walk::{HeapEntry, HeapEntryKind},
+};
+
+/**
+ * A block that is still allocated when the leaks are checked
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeakedBlock {
+    pub usr_address: usize,
+    pub size: usize,
+    /*
+     * Backtrace of the allocation, it's only known when the backtraces or the call sites of the heap are
+     * enabled
+     */
+    pub call_site: Option<String>,
+}
+
+/**
+ * Leaked blocks that were allocated from the same call site
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeakGroup {
+    pub call_site: Option<String>,
+    pub count: usize,
+    pub bytes: usize,
+    // User pointers of the blocks of the group
+    pub blocks: Vec<usize>,
+}
+
+/**
+ * Report of the blocks that are still allocated, grouped by call site and sorted by leaked bytes
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LeakReport {
+    pub groups: Vec<LeakGroup>,
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl LeakReport {
+    pub fn new(blocks: impl IntoIterator<Item = LeakedBlock>) -> Self {
+        let mut groups: HashMap<Option<String>, LeakGroup> = HashMap::new();
+        let mut report = LeakReport::default();
+
+        for block in blocks {
+            let group = groups
+                .entry(block.call_site.clone())
+                .or_insert_with(|| LeakGroup {
+                    call_site: block.call_site,
+                    count: 0,
+                    bytes: 0,
+                    blocks: Vec::new(),
+                });
+
+            group.count += 1;
+            group.bytes += block.size;
+            group.blocks.push(block.usr_address);
+            report.count += 1;
+            report.bytes += block.size;
+        }
+
+        report.groups = groups.into_values().collect();
+        report
+            .groups
+            .sort_by_key(|group| std::cmp::Reverse(group.bytes));
+
+        report
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            formatter,
+            "{} bytes leaked in {} blocks",
+            self.bytes, self.count
+        )?;
+
+        for group in &self.groups {
+            writeln!(
+                formatter,
+                "{} bytes in {} blocks, first block {:#x}",
+                group.bytes, group.count, group.blocks[0]
+            )?;
+
+            match &group.call_site {
+                Some(call_site) => writeln!(formatter, "allocated at:\n{call_site}")?,
+                None => writeln!(formatter, "allocated at an unknown call site")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Allocations of a heap that are known to live until the end of the process, so they aren't leaks
+ *
+ * A suppressed pointer is forgotten when it's deallocated, so a later allocation at the same address is
+ * checked again. A call site pattern suppresses every block whose allocation backtrace contains it
+ */
+pub struct LeakSuppressions {
+    used: AtomicBool,
+    pointers: Mutex<Option<HashSet<usize>>>,
+    call_sites: Mutex<Vec<String>>,
+}
+
+impl Default for LeakSuppressions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LeakSuppressions {
+    pub const fn new() -> Self {
+        Self {
+            used: AtomicBool::new(false),
+            pointers: Mutex::new(None),
+            call_sites: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn suppress(&self, usr_address: usize) {
+        self.pointers
+            .lock()
+            .unwrap()
+            .get_or_insert_default()
+            .insert(usr_address);
+        self.used.store(true, Ordering::Relaxed);
+    }
+
+    /**
+     * Suppresses the blocks allocated from a call site.
+     *
+     * @param pattern Text searched in the allocation backtraces, like a function name or a file path.
+     *
+     * @note It only works for the blocks allocated while the backtraces of the heap are enabled.
+     */
+    pub fn suppress_call_site(&self, pattern: &str) {
+        self.call_sites.lock().unwrap().push(pattern.to_string());
+    }
+
+    pub fn clear(&self) {
+        *self.pointers.lock().unwrap() = None;
+        self.call_sites.lock().unwrap().clear();
+        self.used.store(false, Ordering::Relaxed);
+    }
+
+    /**
+     * Forgets a pointer that is deallocated, the heap lock must be taken by the caller
+     */
+    pub fn forget(&self, usr_address: usize) {
+        /*
+         * The lock isn't taken until something is suppressed, so deallocations don't pay for it
+         */
+        if !self.used.load(Ordering::Relaxed) {
+            return;
+        }
+
+        if let Some(pointers) = self.pointers.lock().unwrap().as_mut() {
+            pointers.remove(&usr_address);
+        }
+    }
+
+    pub fn is_suppressed(&self, usr_address: usize, call_site: Option<&str>) -> bool {
+        let pointer_suppressed = self
+            .pointers
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|pointers| pointers.contains(&usr_address));
+
+        pointer_suppressed
+            || call_site.is_some_and(|call_site| {
+                self.call_sites
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|pattern| call_site.contains(pattern.as_str()))
+            })
+    }
+}
+
+impl ForkLocks for LeakSuppressions {
+    fn lock_all(&'static self, guards: &mut ForkGuards) {
+        guards.push(lock_for_fork(&self.pointers));
+        guards.push(lock_for_fork(&self.call_sites));
+    }
+}
+
+/**
+ * Finds the leaked blocks among the entries of a heap walk, entries that are free, regions and
+ * suppressed blocks are skipped
+ */
+pub(crate) fn find_leaked_blocks(
+    entries: Vec<HeapEntry>,
+    free_checks: &FreeChecks,
+    call_sites: &CallSites,
+    suppressions: &LeakSuppressions,
+) -> Vec<LeakedBlock> {
+    entries
+        .into_iter()
+        .filter(|entry| entry.kind != HeapEntryKind::Region && !entry.is_free)
+        .filter_map(|entry| {
+            let call_site = free_checks
+                .allocation_backtrace(entry.usr_address)
+                .or_else(|| {
+                    call_sites
+                        .call_site(entry.usr_address)
+                        .map(|backtrace| backtrace.to_string())
+                });
+
+            if suppressions.is_suppressed(entry.usr_address, call_site.as_deref()) {
+                return None;
+            }
+
+            Some(LeakedBlock {
+                usr_address: entry.usr_address,
+                size: entry.size,
+                call_site,
+            })
+        })
+        .collect()
+}
+
+/**
+ * What the leak check registered with LeakChecker::check_at_exit does when it finds leaks
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LeakAction {
+    // Prints the report to stderr
+    Report,
+    // Prints the report to stderr and exits with status 1, so a test suite fails
+    Fail,
+}
+
+static REGISTER_EXIT_CHECK: Once = Once::new();
+
+static exit_action: AtomicU8 = AtomicU8::new(LeakAction::Report as u8);
+
+extern "C" fn check_leaks_at_exit() {
+    let report = LeakChecker::check();
+
+    if report.is_empty() {
+        return;
+    }
+
+    eprintln!("{report}");
+
+    if exit_action.load(Ordering::Relaxed) == LeakAction::Fail as u8 {
+        unsafe { libc::_exit(1) };
+    }
+}
+
+/**
+ * Leak checker of the global allocators, it reports the blocks of the bump allocator, the sections and the
+ * small objects of the mmap allocator and the mappings of the guard page mode that are still allocated
+ *
+ * The call site of a leaked block is known when the backtraces of its allocator were enabled with
+ * set_free_backtraces, or its call sites with set_call_sites, before allocating it, small objects are only
+ * allocated while both are disabled, so their call site is unknown
+ */
+pub struct LeakChecker {}
+
+impl LeakChecker {
+    /**
+     * Finds the blocks of the global allocators that are still allocated and aren't suppressed.
+     */
+    pub fn check() -> LeakReport {
+        LeakReport::new(Self::leaked_blocks())
+    }
+
+    fn leaked_blocks() -> Vec<LeakedBlock> {
+        let mut blocks = bump_memory.leaked_blocks();
+
+        blocks.extend(mmap_memory.leaked_blocks());
+
+        let mut entries = SmallAllocator::live_objects();
+
+        entries.extend(
+            guard_state
+                .lock()
+                .unwrap()
+                .live
+                .values()
+                .map(|mapping| HeapEntry {
+                    kind: HeapEntryKind::Section,
+                    address: mapping.start,
+                    usr_address: mapping.usr_address,
+                    size: mapping.usr_size,
+                    is_free: false,
+                    region: None,
+                    header_size: 0,
+                    tag: UNTAGGED,
+                }),
+        );
+
+        blocks.extend(find_leaked_blocks(
+            entries,
+            &mmap_memory.free_checks,
+            &mmap_memory.call_sites,
+            &mmap_memory.leak_suppressions,
+        ));
+
+        blocks
+    }
+
+    /**
+     * Registers a leak check that runs when the process exits with atexit, it's registered only once,
+     * later calls only change the action.
+     */
+    pub fn check_at_exit(action: LeakAction) {
+        exit_action.store(action as u8, Ordering::Relaxed);
+
+        REGISTER_EXIT_CHECK.call_once(|| unsafe {
+            libc::atexit(check_leaks_at_exit);
+        });
+    }
+
+    /**
+     * Suppresses a live allocation of any global allocator, it's an allocation that is intended to live
+     * until the end of the process.
+     */
+    pub fn suppress<T>(usr_data: *const T) {
+        bump_memory.leak_suppressions.suppress(usr_data as usize);
+        mmap_memory.leak_suppressions.suppress(usr_data as usize);
+    }
+
+    /**
+     * Suppresses the allocations whose backtrace contains the given pattern, see
+     * LeakSuppressions::suppress_call_site.
+     */
+    pub fn suppress_call_site(pattern: &str) {
+        bump_memory.leak_suppressions.suppress_call_site(pattern);
+        mmap_memory.leak_suppressions.suppress_call_site(pattern);
+    }
+
+    pub fn clear_suppressions() {
+        bump_memory.leak_suppressions.clear();
+        mmap_memory.leak_suppressions.clear();
+    }
+
+    /**
+     * Starts a scope whose leaks are checked when the returned guard is dropped.
+     */
+    pub fn scope() -> LeakScope {
+        LeakScope::new()
+    }
+}
+
+/**
+ * Drop guard that checks the leaks of the global allocators done while it's alive, the blocks that were
+ * allocated before creating it aren't reported
+ *
+ * @warning Dropping it panics if there are new leaks, so it can be used for failing a test. Blocks
+ * allocated by other threads while it's alive are reported too.
+ */
+pub struct LeakScope {
+    baseline: HashSet<usize>,
+}
+
+impl Default for LeakScope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LeakScope {
+    pub fn new() -> Self {
+        Self {
+            baseline: LeakChecker::leaked_blocks()
+                .into_iter()
+                .map(|block| block.usr_address)
+                .collect(),
+        }
+    }
+
+    /**
+     * Finds the leaks done since the scope started.
+     */
+    pub fn check(&self) -> LeakReport {
+        LeakReport::new(
+            LeakChecker::leaked_blocks()
+                .into_iter()
+                .filter(|block| !self.baseline.contains(&block.usr_address)),
+        )
+    }
+}
+
+impl Drop for LeakScope {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
+
+        let report = self.check();
+
+        if !report.is_empty() {
+            panic!("{report}");
+        }
+    }
+}
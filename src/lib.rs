@@ -1,6 +1,29 @@
 pub mod bump;
+pub mod call_site;
+pub mod canary;
+pub mod error;
+pub mod fork;
+pub mod free_check;
+pub mod guard;
+pub mod hooks;
+pub mod leak;
+pub mod limits;
 pub mod mmap;
+pub mod oom;
+pub mod placement;
+pub mod pressure;
+pub mod profile;
+pub mod quarantine;
+pub mod quota;
+pub mod small;
+pub mod source;
+pub mod stats;
+pub mod tags;
+pub mod trace;
+pub mod tunables;
 pub mod utils;
+pub mod verify;
+pub mod walk;
 
 #[cfg(test)]
 pub mod test;
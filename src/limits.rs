@@ -0,0 +1,240 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use libc::{RLIM_INFINITY, RLIMIT_AS, RLIMIT_DATA, getrlimit, rlimit};
+
+use crate::quarantine::QUARANTINE_DEFAULT_SIZE;
+
+/*
+ * cgroup v1 writes a number near to i64::MAX instead of max when there isn't a limit, anything bigger than
+ * this is taken as no limit
+ */
+const CGROUP_UNLIMITED: usize = 1 << 62;
+
+/*
+ * Bounds of the minimum region size and of the quarantine size, the limit is divided by
+ * LIMIT_TO_CACHE_RATIO and clamped to them, so a 256 MiB container gets the smallest values and a 64 GiB one
+ * gets the biggest
+ */
+const LIMIT_TO_CACHE_RATIO: usize = 1024;
+const MIN_REGION_SIZE_FLOOR: usize = 64 * 1024;
+const MIN_REGION_SIZE_CEIL: usize = 4 * 1024 * 1024;
+const QUARANTINE_SIZE_FLOOR: usize = 64 * 1024;
+const QUARANTINE_SIZE_CEIL: usize = 16 * 1024 * 1024;
+
+/*
+ * Bounds of the trim threshold, the limit is divided by LIMIT_TO_TRIM_RATIO and clamped to them. The threshold
+ * is bigger than the minimum region size, so the empty regions are kept for the next allocations until the
+ * soft limit is crossed and the heap is trimmed
+ */
+const LIMIT_TO_TRIM_RATIO: usize = 512;
+const TRIM_THRESHOLD_FLOOR: usize = 128 * 1024;
+const TRIM_THRESHOLD_CEIL: usize = 8 * 1024 * 1024;
+
+/**
+ * Memory limits of the process found by LimitsProbe, None when there isn't a limit or it can't be read
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryLimits {
+    // memory.max of cgroup v2 or memory.limit_in_bytes of cgroup v1, the lowest one of the cgroup and its parents
+    pub cgroup: Option<usize>,
+    pub rlimit_as: Option<usize>,
+    pub rlimit_data: Option<usize>,
+}
+
+impl MemoryLimits {
+    /**
+     * Gets the lowest of the limits, it's the memory that the process can take before failing
+     */
+    pub fn effective(&self) -> Option<usize> {
+        [self.cgroup, self.rlimit_as, self.rlimit_data]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+}
+
+/**
+ * Reads the memory limits of the process from the cgroup filesystem and getrlimit
+ *
+ * The files are read under root, so the probe can be tested with a fake filesystem:
+ * - root/proc/self/cgroup: the cgroup of the process, "0::path" for v2 and "id:memory:path" for v1
+ * - root/sys/fs/cgroup/path/memory.max: the limit of cgroup v2, "max" when there isn't one
+ * - root/sys/fs/cgroup/memory/path/memory.limit_in_bytes: the limit of cgroup v1
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LimitsProbe {
+    pub root: PathBuf,
+    // The rlimits aren't files, so they are skipped when the filesystem is fake
+    pub read_rlimits: bool,
+}
+
+impl Default for LimitsProbe {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+            read_rlimits: true,
+        }
+    }
+}
+
+impl LimitsProbe {
+    /**
+     * Reads all the limits, cgroup v2 is tried before cgroup v1
+     */
+    pub fn probe(&self) -> MemoryLimits {
+        MemoryLimits {
+            cgroup: self.cgroup_v2_limit().or_else(|| self.cgroup_v1_limit()),
+            rlimit_as: self.rlimit(RLIMIT_AS as libc::c_int),
+            rlimit_data: self.rlimit(RLIMIT_DATA as libc::c_int),
+        }
+    }
+
+    fn cgroup_v2_limit(&self) -> Option<usize> {
+        let cgroup_path = self.cgroup_path(|controllers| controllers.is_empty())?;
+
+        Self::lowest_limit(&self.root.join("sys/fs/cgroup"), &cgroup_path, "memory.max")
+    }
+
+    fn cgroup_v1_limit(&self) -> Option<usize> {
+        let cgroup_path = self.cgroup_path(|controllers| {
+            controllers
+                .split(',')
+                .any(|controller| controller == "memory")
+        })?;
+
+        Self::lowest_limit(
+            &self.root.join("sys/fs/cgroup/memory"),
+            &cgroup_path,
+            "memory.limit_in_bytes",
+        )
+    }
+
+    /**
+     * Finds the path of the cgroup of the process in /proc/self/cgroup, if the file doesn't exist, then the
+     * cgroup is the root of the hierarchy.
+     *
+     * @param is_hierarchy Checks the controllers field of a line, it's empty for cgroup v2.
+     */
+    fn cgroup_path(&self, is_hierarchy: impl Fn(&str) -> bool) -> Option<String> {
+        let Ok(content) = fs::read_to_string(self.root.join("proc/self/cgroup")) else {
+            return Some(String::new());
+        };
+
+        content.lines().find_map(|line| {
+            let mut fields = line.splitn(3, ':');
+            let (_, controllers, path) = (fields.next()?, fields.next()?, fields.next()?);
+
+            is_hierarchy(controllers).then(|| path.trim_start_matches('/').to_string())
+        })
+    }
+
+    /**
+     * Reads the limit file of the cgroup and of all its parents, a parent can have a lower limit
+     */
+    fn lowest_limit(mount: &Path, cgroup_path: &str, file: &str) -> Option<usize> {
+        let mut path = Some(Path::new(cgroup_path));
+        let mut lowest: Option<usize> = None;
+
+        while let Some(current) = path {
+            if let Some(limit) = Self::read_limit(&mount.join(current).join(file)) {
+                lowest = Some(lowest.map_or(limit, |lowest| lowest.min(limit)));
+            }
+
+            path = current.parent();
+        }
+
+        lowest
+    }
+
+    fn read_limit(path: &Path) -> Option<usize> {
+        let limit = fs::read_to_string(path)
+            .ok()?
+            .trim()
+            .parse::<usize>()
+            .ok()?;
+
+        (limit < CGROUP_UNLIMITED).then_some(limit)
+    }
+
+    /*
+     * The type of the resource of getrlimit is different between the C libraries (c_int on musl and the BSDs,
+     * an unsigned enum on glibc), so the resource is taken as c_int and it's cast when getrlimit is called
+     */
+    fn rlimit(&self, resource: libc::c_int) -> Option<usize> {
+        if !self.read_rlimits {
+            return None;
+        }
+
+        let mut limit = rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+
+        if unsafe { getrlimit(resource as _, &mut limit) } != 0 || limit.rlim_cur == RLIM_INFINITY {
+            return None;
+        }
+
+        Some(limit.rlim_cur as usize)
+    }
+}
+
+/**
+ * Settings of a heap derived from the memory limit of the process
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AutoTuning {
+    pub limits: MemoryLimits,
+    // High watermark, the pressure handler is called and the heap is trimmed when it's crossed
+    pub soft_limit: Option<usize>,
+    // Low watermark, the pressure ends when the heap goes under it
+    pub low_watermark: usize,
+    // Minimum size of the regions of the mmap heap, small sections share the regions
+    pub min_region_size: usize,
+    pub quarantine_size: usize,
+    // Free memory at the end of the bump heap and empty regions of the mmap heap smaller than this are kept
+    pub trim_threshold: usize,
+}
+
+impl AutoTuning {
+    /**
+     * Derives the settings from the limits, the soft limit is 3/4 of the effective limit and the low
+     * watermark is half of it.
+     *
+     * @note Without a limit there isn't a soft limit, the region size is the biggest one, the quarantine
+     * keeps its default size and the free memory is always given back.
+     */
+    pub fn from_limits(limits: MemoryLimits) -> Self {
+        let Some(limit) = limits.effective() else {
+            return Self {
+                limits,
+                soft_limit: None,
+                low_watermark: 0,
+                min_region_size: MIN_REGION_SIZE_CEIL,
+                quarantine_size: QUARANTINE_DEFAULT_SIZE,
+                trim_threshold: 0,
+            };
+        };
+
+        let cache_size = limit / LIMIT_TO_CACHE_RATIO;
+
+        Self {
+            limits,
+            soft_limit: Some(limit / 4 * 3),
+            low_watermark: limit / 2,
+            min_region_size: cache_size.clamp(MIN_REGION_SIZE_FLOOR, MIN_REGION_SIZE_CEIL),
+            quarantine_size: cache_size.clamp(QUARANTINE_SIZE_FLOOR, QUARANTINE_SIZE_CEIL),
+            trim_threshold: (limit / LIMIT_TO_TRIM_RATIO)
+                .clamp(TRIM_THRESHOLD_FLOOR, TRIM_THRESHOLD_CEIL),
+        }
+    }
+
+    /**
+     * Reads the limits of the process and derives the settings from them
+     */
+    pub fn probe() -> Self {
+        Self::from_limits(LimitsProbe::default().probe())
+    }
+}
@@ -1,54 +1,211 @@
-use std::sync::atomic::{AtomicPtr, Ordering};
+use std::{
+    io,
+    path::Path,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::{
+    call_site::{AllocatorScope, CallSiteStats},
+    canary::{OverflowHandler, OverflowReport, canary_block_size},
+    error::AllocError,
+    fork::register_fork_handlers,
+    free_check::{InvalidFree, InvalidFreeAction, InvalidFreeHandler, InvalidFreeKind},
+    guard::{GuardOptions, allocator::GuardAllocator},
+    hooks::{EventHook, EventKind, HeapEvent},
+    limits::AutoTuning,
+    oom::OomHandler,
+    placement::PlacementPolicy,
+    pressure::PressureHandler,
+    profile::{HeapProfile, ProfileFormat},
+    quarantine::UseAfterFreeHandler,
+    quota::QuotaKind,
+    small::{SMALL_SLAB_SIZE, allocator::SmallAllocator},
+    source::{MemorySource, mmap::MmapSource},
+    stats::Stats,
+    tags::{AllocTag, TagScope, TagStats, UNTAGGED, current_tag},
+    trace::{TraceOp, recorder::TraceRecorder},
+    tunables::{Tunable, load_env_options},
+    utils::check_alignment,
+    verify::HeapError,
+    walk::HeapEntry,
+};
 
 use super::{
-    MmapMemoryRegion, MmapMemorySectionHeader,
+    MmapHeap, MmapHeapState, MmapMemoryRegion, MmapMemorySectionHeader,
     globals::mmap_memory,
-    utils::{allocate_region, deallocate_region, place_section_inside_region},
+    utils::{allocate_region, deallocate_region, find_section, place_section_inside_region},
+    walk::{MmapHeapWalker, region_entry, section_entry},
 };
 
-pub struct MmapAllocator {}
+impl<S: MemorySource> MmapHeap<S> {
+    /**
+     * Allocate memory on this heap using mmap regions.
+     *
+     * @param size The size of the memory to allocate.
+     * @return A pointer to the allocated memory, or the reason why it can't be allocated.
+     *
+     * @note This function is thread-safe, every allocation takes the lock of this heap, only MmapAllocator
+     * serves small sizes with the lock-free small allocator.
+     * @note When the heap runs out of memory, the emergency reserve is released and the OOM handler can
+     * ask for the allocation to be tried again.
+     */
+    pub fn allocate<T>(&self, size: usize) -> Result<*mut T, AllocError> {
+        let _allocator_scope = AllocatorScope::enter();
+        let mut attempt = 0;
 
-impl MmapAllocator {
-    pub fn allocate<T>(size: usize) -> Option<*mut T> {
-        let mut memory_guard = mmap_memory.lock().unwrap();
+        loop {
+            match self.try_allocate::<T>(size) {
+                Err(error) if error.is_out_of_memory() => {
+                    attempt += 1;
+
+                    if !self.handle_oom(error, size, attempt) {
+                        return Err(error);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /**
+     * Allocate memory on this heap counted in the given tag instead of the tag of the current thread.
+     *
+     * @param tag The tag stored in the header of the section.
+     * @param size The size of the memory to allocate.
+     * @return A pointer to the allocated memory, or the reason why it can't be allocated.
+     */
+    pub fn allocate_tagged<T>(&self, tag: AllocTag, size: usize) -> Result<*mut T, AllocError> {
+        let _allocator_scope = AllocatorScope::enter();
+        let _tag_scope = TagScope::enter(tag);
+
+        self.allocate::<T>(size)
+    }
+
+    /**
+     * Allocates once, without calling the OOM handler when the heap runs out of memory
+     */
+    pub(super) fn try_allocate<T>(&self, size: usize) -> Result<*mut T, AllocError> {
+        check_alignment::<T>()?;
+
+        let has_canaries = self.canaries.is_enabled();
+        let block_size =
+            canary_block_size(size, has_canaries).ok_or(AllocError::SizeOverflow { size })?;
+        let tag = current_tag();
+
+        let usr_pointer = {
+            let mut memory_guard = self.memory.lock().unwrap();
+
+            self.verify_before_allocation(&memory_guard)?;
+            self.quota.check_user_bytes(size)?;
+            self.tags.check(tag, size)?;
+
+            let section = self.take_section(&mut memory_guard, block_size)?;
+            let usr_pointer = (section as usize + MmapMemorySectionHeader::size()) as *mut T;
 
-        if memory_guard.is_none() {
+            unsafe {
+                self.arm_canaries(section, size, has_canaries);
+                (*section).tag = tag;
+            }
+
+            self.counters.allocations.fetch_add(1, Ordering::Relaxed);
+            self.quota.record_allocation(size);
+            self.tags.record_allocation(tag, size);
+            self.free_checks.set_torn_down(false);
+            self.free_checks.record_allocation(usr_pointer as usize);
+            self.call_sites.record_allocation(usr_pointer as usize, size);
+            self.profiler.record_allocation(usr_pointer as usize, size);
+            self.verify_after_operation(memory_guard);
+            usr_pointer
+        };
+
+        /*
+         * The events and the watermarks are checked without the heap lock, so the hook and the pressure
+         * handler can use the heap
+         */
+        self.hooks.flush();
+        self.hooks.dispatch(HeapEvent {
+            kind: EventKind::Alloc,
+            address: usr_pointer as usize,
+            size,
+        });
+        self.check_pressure();
+
+        Ok(usr_pointer)
+    }
+
+    /**
+     * Looks for a region that can store a section of the given size, if there isn't one, then a new region
+     * is mapped at the end of the region list
+     *
+     * @return The header of the section, it's already marked as not free.
+     *
+     * @note Sections of at least the mmap threshold aren't placed in the existing regions, they get a new
+     * region of their own size.
+     */
+    fn take_section(
+        &self,
+        memory_guard: &mut MmapHeapState<S>,
+        size: usize,
+    ) -> Result<*mut MmapMemorySectionHeader, AllocError> {
+        let dedicated_region = size >= self.mmap_threshold.load(Ordering::Relaxed);
+        let min_region_size = if dedicated_region {
+            0
+        } else {
+            self.min_region_size.load(Ordering::Relaxed)
+        };
+
+        if memory_guard.head.is_none() {
             /*
              * Creates a region with no sections stored inside
              */
-            let new_region = allocate_region(size);
+            let new_region = allocate_region(
+                &mut memory_guard.source,
+                &self.counters,
+                &self.quota,
+                &self.hooks,
+                size,
+                min_region_size,
+            )?;
+
+            /*
+             * The head section is stored just after the region header
+             */
+            let section_addr = unsafe { place_section_inside_region(new_region, size) };
 
-            if new_region.is_none() {
-                return None;
+            if section_addr.is_none() {
+                self.discard_region(memory_guard, new_region);
+                return Err(AllocError::SizeOverflow { size });
             }
 
-            let new_region = new_region.unwrap();
+            let section_addr = section_addr.unwrap();
 
-            /*
-             * Get the address of region + MmapMemoryRegion.size, because just after the region block we
-             * are going to store the header of the head section
-             */
-            let section_addr =
-                (new_region as usize + MmapMemoryRegion::size()) as *mut MmapMemorySectionHeader;
+            memory_guard.head = Some(AtomicPtr::new(new_region));
+
+            return Ok(section_addr);
+        }
 
+        if !dedicated_region
+            && self.placement.policy() == PlacementPolicy::BestFit
+            && let Some((region, section)) = self.find_best_fit_section(memory_guard, size)
+        {
             unsafe {
-                (*section_addr) = MmapMemorySectionHeader::new(size, false, None, None);
-                (*new_region).head_section = Some(AtomicPtr::new(section_addr));
-                (*memory_guard) = Some(AtomicPtr::new(new_region));
+                (*section).is_free = false;
+                (*region).space_available -= (*section).size + MmapMemorySectionHeader::size();
             }
 
-            let usr_pointer = (section_addr as usize + MmapMemorySectionHeader::size()) as *mut T;
-
-            return Some(usr_pointer);
+            return Ok(section);
         }
 
-        let mut current_region = memory_guard.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+        let mut current_region = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
         let mut last_region: Option<*mut MmapMemoryRegion> = None;
 
         while let Some(region) = current_region {
             unsafe {
                 last_region = Some(region);
-                if (*region).space_available < size {
+                if dedicated_region || (*region).space_available < size {
                     current_region = region
                         .as_ref()
                         .unwrap()
@@ -70,32 +227,30 @@ impl MmapAllocator {
                     continue;
                 }
 
-                let section = section.unwrap();
-                let usr_ptr = section as usize + MmapMemorySectionHeader::size();
-
-                return Some(usr_ptr as *mut T);
+                return Ok(section.unwrap());
             }
         }
 
         /*
          * If there aren't regions that can store the user data, then we must allocate a new one
          */
-        let new_region = allocate_region(size);
-
-        if new_region.is_none() {
-            return None;
-        }
-
-        let new_region = new_region.unwrap();
+        let new_region = allocate_region(
+            &mut memory_guard.source,
+            &self.counters,
+            &self.quota,
+            &self.hooks,
+            size,
+            min_region_size,
+        )?;
 
-        let section_addr = place_section_inside_region(new_region, size);
+        let section_addr = unsafe { place_section_inside_region(new_region, size) };
 
         /*
          * If for any reason, section can't be stored y the new_region, then we must abort and revert all
          */
         if section_addr.is_none() {
-            deallocate_region(new_region);
-            return None;
+            self.discard_region(memory_guard, new_region);
+            return Err(AllocError::SizeOverflow { size });
         }
 
         let section_addr = section_addr.unwrap();
@@ -113,8 +268,943 @@ impl MmapAllocator {
             }
         }
 
-        let usr_pointer = (section_addr as usize + MmapMemorySectionHeader::size()) as *mut T;
+        Ok(section_addr)
+    }
+
+    /**
+     * Looks for the smallest available section of all the regions that can store the given size, if there
+     * isn't one the first fit search is done
+     *
+     * @return The section and the region that owns it.
+     */
+    fn find_best_fit_section(
+        &self,
+        memory_guard: &MmapHeapState<S>,
+        size: usize,
+    ) -> Option<(*mut MmapMemoryRegion, *mut MmapMemorySectionHeader)> {
+        let mut best_section: Option<(*mut MmapMemoryRegion, *mut MmapMemorySectionHeader)> = None;
+        let mut current_region = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        while let Some(region) = current_region {
+            unsafe {
+                let mut current_section = (*region)
+                    .head_section
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst));
+
+                while let Some(section) = current_section {
+                    if (*section).is_available()
+                        && (*section).size >= size
+                        && best_section
+                            .is_none_or(|(_, best_section)| (*section).size < (*best_section).size)
+                    {
+                        best_section = Some((region, section));
+                    }
+
+                    current_section = (*section)
+                        .next
+                        .as_ref()
+                        .map(|ptr| ptr.load(Ordering::SeqCst));
+                }
+
+                current_region = (*region)
+                    .next
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst));
+            }
+        }
+
+        best_section
+    }
+
+    /**
+     * Deallocate memory allocated by MmapHeap::allocate.
+     *
+     * @param usr_data The pointer to the memory to deallocate.
+     *
+     * @note This function is thread-safe.
+     */
+    pub fn deallocate<T>(&self, usr_data: *const T) {
+        let (result, use_after_free_reports) = {
+            let mut memory_guard = self.memory.lock().unwrap();
+            let result = self.release_section(&mut memory_guard, usr_data as *const u8);
+
+            if result.is_ok() {
+                self.counters.frees.fetch_add(1, Ordering::Relaxed);
+                self.free_checks.record_free(usr_data as usize);
+                self.call_sites.record_free(usr_data as usize);
+                self.profiler.record_free(usr_data as usize);
+                self.leak_suppressions.forget(usr_data as usize);
+            }
+
+            let use_after_free_reports = self.evict_quarantine(&mut memory_guard, false);
+
+            self.verify_after_operation(memory_guard);
+            (result, use_after_free_reports)
+        };
+
+        /*
+         * The reports are done without the heap lock, so the report handlers can use the heap
+         */
+        match result {
+            Ok((usr_size, overflow_report)) => {
+                self.canaries.report(overflow_report);
+                self.hooks.dispatch(HeapEvent {
+                    kind: EventKind::Free,
+                    address: usr_data as usize,
+                    size: usr_size,
+                });
+            }
+            Err(invalid_free) => self.free_checks.report(usr_data as usize, invalid_free),
+        }
+
+        self.quarantine.report(use_after_free_reports);
+        self.hooks.flush();
+        self.check_pressure();
+    }
+
+    /**
+     * Looks for the section of the given user pointer and sets it free, if all the sections of its region
+     * are free, then the region is unmapped
+     *
+     * @return The user size and the report of the canaries of the section, or the reason why the section can't
+     * be deallocated.
+     */
+    fn release_section(
+        &self,
+        memory_guard: &mut MmapHeapState<S>,
+        usr_data: *const u8,
+    ) -> Result<(usize, Option<OverflowReport>), InvalidFree> {
+        if self.free_checks.is_torn_down() {
+            return Err(InvalidFree {
+                kind: InvalidFreeKind::AfterTeardown,
+                block: None,
+            });
+        }
+
+        let Some((region, section)) = find_section(
+            memory_guard
+                .head
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst)),
+            usr_data,
+        ) else {
+            return Err(self.find_invalid_free(memory_guard, usr_data));
+        };
+
+        unsafe {
+            if (*section).is_free {
+                return Err(InvalidFree {
+                    kind: InvalidFreeKind::DoubleFree,
+                    block: Some(section_entry(region, section)),
+                });
+            }
+
+            let overflow_report = self.check_canaries(region, section);
+
+            let usr_size = (*section).usr_size;
+
+            self.quota.record_free(usr_size);
+            self.tags.record_free((*section).tag, usr_size);
+
+            /*
+             * When the quarantine is enabled, the section is freed for real when it leaves the quarantine
+             */
+            if self.quarantine.is_enabled() {
+                (*section).is_free = true;
+                (*section).in_quarantine = true;
+                self.quarantine.push(section as usize, usr_data as usize, (*section).size);
+
+                return Ok((usr_size, overflow_report));
+            }
+
+            self.free_section(memory_guard, region, section);
+
+            Ok((usr_size, overflow_report))
+        }
+    }
+
+    /**
+     * Unmaps a region that was just mapped and isn't in the list yet, if the memory source can't unmap
+     * it, then it's kept in the list, so its memory can be used by the next allocations
+     */
+    fn discard_region(&self, memory_guard: &mut MmapHeapState<S>, region: *mut MmapMemoryRegion) {
+        let result = unsafe {
+            deallocate_region(
+                &mut memory_guard.source,
+                &self.counters,
+                &self.hooks,
+                region,
+            )
+        };
+
+        if result.is_err() {
+            Self::push_front_region(memory_guard, region);
+        }
+    }
+
+    /**
+     * Pushes a region that isn't in the list at the front of the list
+     */
+    fn push_front_region(memory_guard: &mut MmapHeapState<S>, region: *mut MmapMemoryRegion) {
+        let head = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        unsafe {
+            if let Some(head) = head {
+                (*head).prev = Some(AtomicPtr::new(region));
+            }
+
+            (*region).prev = None;
+            (*region).next = head.map(AtomicPtr::new);
+        }
+
+        memory_guard.head = Some(AtomicPtr::new(region));
+    }
+
+    /**
+     * Sets a section free, if all the sections of its region are free, then the region is unmapped
+     *
+     * @note If the memory source can't unmap the region or the region is smaller than the trim threshold,
+     * then it stays in the list with all its sections free.
+     */
+    pub(super) unsafe fn free_section(
+        &self,
+        memory_guard: &mut MmapHeapState<S>,
+        region: *mut MmapMemoryRegion,
+        section: *mut MmapMemorySectionHeader,
+    ) {
+        unsafe {
+            (*section).is_free = true;
+            (*section).in_quarantine = false;
+            (*region).space_available += (*section).size + MmapMemorySectionHeader::size();
+
+            /*
+             * If all the sections of the region are free, then the region is given back to the OS, unless
+             * it's smaller than the trim threshold
+             */
+            let region_size = (*region).total_space + MmapMemoryRegion::size();
+
+            if (*region).space_available != (*region).total_space
+                || region_size < self.trim_threshold.load(Ordering::Relaxed)
+            {
+                return;
+            }
+
+            self.release_region(memory_guard, region);
+        }
+    }
+
+    /**
+     * Unmaps a region of the list whose sections are all free and unlinks it
+     *
+     * @return If the region was unmapped, if the memory source can't unmap it, then it stays in the list.
+     */
+    pub(super) unsafe fn release_region(
+        &self,
+        memory_guard: &mut MmapHeapState<S>,
+        region: *mut MmapMemoryRegion,
+    ) -> bool {
+        unsafe {
+            /*
+             * The links are taken before unmapping, because the header can't be read after it
+             */
+            let next = (*region)
+                .next
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst));
+            let prev = (*region)
+                .prev
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst));
+
+            if deallocate_region(
+                &mut memory_guard.source,
+                &self.counters,
+                &self.hooks,
+                region,
+            )
+            .is_err()
+            {
+                return false;
+            }
+
+            if let Some(next) = next {
+                (*next).prev = prev.map(AtomicPtr::new);
+            }
+
+            match prev {
+                Some(prev) => (*prev).next = next.map(AtomicPtr::new),
+                None => memory_guard.head = next.map(AtomicPtr::new),
+            }
+
+            true
+        }
+    }
+
+    /**
+     * Finds why a pointer that isn't the user pointer of any section can't be deallocated
+     */
+    fn find_invalid_free(&self, memory_guard: &MmapHeapState<S>, usr_data: *const u8) -> InvalidFree {
+        let address = usr_data as usize;
+        let mut current_region = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        while let Some(region) = current_region {
+            unsafe {
+                let region_block = region_entry(region);
+
+                if (region_block.address..region_block.usr_address + region_block.size).contains(&address) {
+                    let mut current_section = (*region)
+                        .head_section
+                        .as_ref()
+                        .map(|ptr| ptr.load(Ordering::SeqCst));
+
+                    while let Some(section) = current_section {
+                        let block = section_entry(region, section);
+
+                        if (block.address..block.usr_address + block.size).contains(&address) {
+                            return InvalidFree {
+                                kind: InvalidFreeKind::InteriorPointer,
+                                block: Some(block),
+                            };
+                        }
+
+                        current_section = (*section)
+                            .next
+                            .as_ref()
+                            .map(|ptr| ptr.load(Ordering::SeqCst));
+                    }
+
+                    /*
+                     * The pointer is inside the region but not inside a section, like its header or its tail
+                     */
+                    return InvalidFree {
+                        kind: InvalidFreeKind::InteriorPointer,
+                        block: Some(region_block),
+                    };
+                }
+
+                current_region = (*region)
+                    .next
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst));
+            }
+        }
+
+        /*
+         * Regions given back to the memory source aren't in the list, but their pointers are known
+         * when the backtraces are enabled
+         */
+        let kind = if self.free_checks.was_freed(address) {
+            InvalidFreeKind::DoubleFree
+        } else {
+            InvalidFreeKind::ForeignPointer
+        };
+
+        InvalidFree { kind, block: None }
+    }
+
+    /**
+     * Unmaps all the regions of this heap, after it the deallocations of the pointers of this heap are
+     * reported as frees after teardown.
+     *
+     * @note The heap can be used again, the next allocation maps a new region.
+     * @note If the memory source can't unmap a region, then it stays in the heap with all its sections free.
+     * @note The emergency reserve is given back with the rest of the heap, it must be set again.
+     */
+    pub fn teardown(&self) {
+        let mut memory_guard = self.memory.lock().unwrap();
+
+        let mut current_region = memory_guard
+            .head
+            .take()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        while let Some(region) = current_region {
+            unsafe {
+                current_region = (*region)
+                    .next
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst));
+            }
+
+            let result = unsafe {
+                deallocate_region(
+                    &mut memory_guard.source,
+                    &self.counters,
+                    &self.hooks,
+                    region,
+                )
+            };
+
+            if result.is_ok() {
+                continue;
+            }
+
+            /*
+             * The sections of the regions that are kept are set free, so the region can be used again
+             */
+            let mut current_section = unsafe {
+                (*region)
+                    .head_section
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst))
+            };
+
+            while let Some(section) = current_section {
+                unsafe {
+                    (*section).is_free = true;
+                    (*section).in_quarantine = false;
+                    current_section = (*section)
+                        .next
+                        .as_ref()
+                        .map(|ptr| ptr.load(Ordering::SeqCst));
+                }
+            }
+
+            unsafe { (*region).space_available = (*region).total_space };
+
+            Self::push_front_region(&mut memory_guard, region);
+        }
+
+        self.quarantine.clear();
+        self.quota.clear();
+        self.tags.clear();
+        self.oom.take_reserve();
+        self.call_sites.clear();
+        self.profiler.clear();
+        self.free_checks.set_torn_down(true);
+
+        drop(memory_guard);
+        self.hooks.flush();
+    }
+}
+
+pub struct MmapAllocator {}
+
+impl MmapAllocator {
+    /**
+     * Allocate memory using mmap regions.
+     *
+     * @param size The size of the memory to allocate.
+     * @return A pointer to the allocated memory, or the reason why it can't be allocated.
+     *
+     * @note This function is thread-safe, sizes up to SMALL_MAX_SIZE (or the SmallMaxSize tunable) are served
+     * by the lock-free small allocator and bigger sizes take the mmap_memory lock. In guard page mode every allocation
+     * is served by GuardAllocator.
+     * @note The first allocation applies the settings of the QUALLOC_OPTIONS environment variable and
+     * registers the fork handlers, while they can't be registered the allocations fail with the error of
+     * pthread_atfork.
+     */
+    pub fn allocate<T>(size: usize) -> Result<*mut T, AllocError> {
+        let _allocator_scope = AllocatorScope::enter();
+        register_fork_handlers()?;
+        load_env_options();
+
+        let usr_pointer = Self::allocate_untraced::<T>(size)?;
+
+        TraceRecorder::record_allocation(
+            TraceOp::MmapAlloc,
+            usr_pointer as usize,
+            size,
+            align_of::<T>(),
+        );
+
+        Ok(usr_pointer)
+    }
+
+    fn allocate_untraced<T>(size: usize) -> Result<*mut T, AllocError> {
+        if GuardAllocator::is_enabled() {
+            return GuardAllocator::allocate(size);
+        }
+
+        if Self::uses_small_allocator(size)
+            && let Some(usr_pointer) = SmallAllocator::allocate_with::<T>(size, || {
+                mmap_memory.counters.record_heap_growth(SMALL_SLAB_SIZE)
+            })
+        {
+            mmap_memory
+                .counters
+                .allocations
+                .fetch_add(1, Ordering::Relaxed);
+            mmap_memory
+                .profiler
+                .record_allocation(usr_pointer as usize, size);
+
+            if let Some(class_size) = SmallAllocator::class_size::<T>(size) {
+                mmap_memory.quota.record_allocation(class_size);
+                mmap_memory.tags.record_allocation(UNTAGGED, class_size);
+            }
+
+            mmap_memory.hooks.dispatch(HeapEvent {
+                kind: EventKind::Alloc,
+                address: usr_pointer as usize,
+                size,
+            });
+            mmap_memory.check_pressure();
+
+            return Ok(usr_pointer);
+        }
+
+        mmap_memory.allocate(size)
+    }
+
+    /**
+     * Checks if an allocation can be served by the small allocator, small objects don't have headers, so
+     * the allocations go to the heap while a feature that needs the header of the section is used
+     */
+    fn uses_small_allocator(size: usize) -> bool {
+        mmap_memory.small_enabled.load(Ordering::Relaxed)
+            && size <= mmap_memory.small_max_size.load(Ordering::Relaxed)
+            && mmap_memory.quota.limit().is_none()
+            && current_tag() == UNTAGGED
+            && mmap_memory.tags.limit(UNTAGGED).is_none()
+            && !mmap_memory.free_checks.has_backtraces()
+            && !mmap_memory.quarantine.is_enabled()
+            && !mmap_memory.canaries.is_enabled()
+            && !mmap_memory.call_sites.is_enabled()
+    }
+
+    /**
+     * Allocate memory counted in the given tag instead of the tag of the current thread, it's the same as
+     * allocating inside a TagScope.
+     *
+     * @param tag The tag stored in the header of the section.
+     * @param size The size of the memory to allocate.
+     * @return A pointer to the allocated memory, or the reason why it can't be allocated.
+     */
+    pub fn allocate_tagged<T>(tag: AllocTag, size: usize) -> Result<*mut T, AllocError> {
+        let _allocator_scope = AllocatorScope::enter();
+        let _tag_scope = TagScope::enter(tag);
+
+        Self::allocate::<T>(size)
+    }
+
+    /**
+     * Deallocate memory allocated by MmapAllocator::allocate.
+     *
+     * @param usr_data The pointer to the memory to deallocate.
+     *
+     * @note This function is thread-safe.
+     */
+    pub fn deallocate<T>(usr_data: *const T) {
+        TraceRecorder::record_free(TraceOp::MmapFree, usr_data as usize);
+
+        if GuardAllocator::deallocate(usr_data) {
+            return;
+        }
+
+        let small_free = SmallAllocator::deallocate_with(usr_data, |size| {
+            mmap_memory.free_checks.record_free(usr_data as usize);
+            mmap_memory.leak_suppressions.forget(usr_data as usize);
+            mmap_memory.profiler.record_free(usr_data as usize);
+            mmap_memory.quota.record_free(size);
+            mmap_memory.tags.record_free(UNTAGGED, size);
+            mmap_memory.hooks.dispatch(HeapEvent {
+                kind: EventKind::Free,
+                address: usr_data as usize,
+                size,
+            });
+        });
+
+        match small_free {
+            Ok(true) => {
+                mmap_memory.counters.frees.fetch_add(1, Ordering::Relaxed);
+                mmap_memory.check_pressure();
+
+                return;
+            }
+            Ok(false) => {}
+            /*
+             * The pointer is inside the arena but it isn't a live object, so it isn't freed
+             */
+            Err(kind) => {
+                mmap_memory.free_checks.report(
+                    usr_data as usize,
+                    InvalidFree {
+                        kind,
+                        block: SmallAllocator::object_entry(usr_data),
+                    },
+                );
+
+                return;
+            }
+        }
+
+        mmap_memory.deallocate(usr_data)
+    }
+
+    /**
+     * Walks over the regions of the mmap allocator heap and their sections.
+     *
+     * @return An iterator that holds the heap lock until it's dropped.
+     *
+     * @note Objects of the small allocator aren't part of the walk, they don't have headers.
+     * @warning Allocating or deallocating with the mmap allocator while the walker is alive deadlocks.
+     */
+    pub fn walk() -> MmapHeapWalker<'static, MmapSource> {
+        mmap_memory.walk()
+    }
+
+    /**
+     * Takes a consistent copy of all the regions and sections of the mmap allocator heap.
+     */
+    pub fn snapshot() -> Vec<HeapEntry> {
+        mmap_memory.snapshot()
+    }
+
+    /**
+     * Takes the statistics of the mmap allocator heap, the slabs and the objects of the small allocator are
+     * counted in them.
+     */
+    pub fn stats() -> Stats {
+        mmap_memory
+            .stats()
+            .add_small_objects(SmallAllocator::stats())
+    }
 
-        return Some(usr_pointer);
+    /**
+     * Checks the invariants of the mmap allocator heap.
+     *
+     * @return The first violation found.
+     */
+    pub fn verify() -> Result<(), HeapError> {
+        mmap_memory.verify()
+    }
+
+    /**
+     * Enables or disables running verify after every operation of the mmap allocator, only in debug builds.
+     */
+    pub fn set_verify_after_operations(enabled: bool) {
+        mmap_memory.set_verify_after_operations(enabled)
+    }
+
+    /**
+     * Sets how the mmap allocator chooses the free space for new allocations.
+     */
+    pub fn set_placement_policy(policy: PlacementPolicy) {
+        mmap_memory.placement.set_policy(policy)
+    }
+
+    /**
+     * Sets the byte budget of the mmap allocator, allocations that would go over it fail with
+     * AllocError::QuotaExceeded.
+     *
+     * @param limit The maximum number of bytes, None removes the quota.
+     * @param kind Counts the bytes asked by the user or the bytes of the mapped regions.
+     *
+     * @note While a limit is set the small allocator isn't used, so every allocation is counted. The
+     * allocations of the guard page mode aren't counted.
+     */
+    pub fn set_quota(limit: Option<usize>, kind: QuotaKind) {
+        mmap_memory.quota.set_limit(limit, kind)
+    }
+
+    /**
+     * Sets the soft limits of the mmap allocator, the pressure handler is called once when the usage goes
+     * over the high watermark and once when it goes back under the low watermark.
+     *
+     * @param high The usage that starts the pressure, None removes the watermarks.
+     * @param low The usage that ends the pressure.
+     * @param kind Counts the bytes asked by the user or the bytes of the mapped regions.
+     *
+     * @note Small objects are counted with the size of their class, and their slabs are counted in the bytes
+     * of the mapped regions when they are taken from the arena. Slabs are never given back, so trimming
+     * doesn't lower the bytes of the slabs.
+     */
+    pub fn set_watermarks(high: Option<usize>, low: usize, kind: QuotaKind) {
+        mmap_memory.watermarks.set(high, low, kind)
+    }
+
+    /**
+     * Sets the function that receives the crossings of the watermarks of the mmap allocator, None prints
+     * them to stderr.
+     *
+     * @note The handler is called without the heap lock, so it can free memory of the mmap allocator.
+     */
+    pub fn set_pressure_handler(handler: Option<PressureHandler>) {
+        mmap_memory.watermarks.set_handler(handler)
+    }
+
+    /**
+     * Enables or disables trimming the mmap allocator after its high watermark is reported.
+     */
+    pub fn set_auto_trim(enabled: bool) {
+        mmap_memory.watermarks.set_auto_trim(enabled)
+    }
+
+    /**
+     * Gives back the free memory of the mmap allocator to the OS.
+     *
+     * @return The number of bytes given back.
+     */
+    pub fn trim() -> usize {
+        mmap_memory.trim()
+    }
+
+    /**
+     * Sets the function called when the mmap allocator runs out of memory, it can free memory and ask for
+     * the allocation to be tried again, None fails the allocations.
+     *
+     * @note Allocations of the small allocator that fail are tried on the mmap heap, so they reach the
+     * handler too.
+     */
+    pub fn set_oom_handler(handler: Option<OomHandler>) {
+        mmap_memory.oom.set_handler(handler)
+    }
+
+    /**
+     * Allocates the emergency reserve of the mmap allocator, it's released on the first out of memory.
+     *
+     * @param size The size of the reserve, 0 removes the reserve.
+     * @return The reason why the reserve can't be allocated.
+     */
+    pub fn set_emergency_reserve(size: usize) -> Result<(), AllocError> {
+        mmap_memory.set_emergency_reserve(size)
+    }
+
+    /**
+     * Reads the cgroup and rlimit memory limits of the process and sizes the mmap allocator from them, the
+     * settings are reported by the stats.
+     *
+     * @return The settings that were applied.
+     */
+    pub fn auto_tune() -> AutoTuning {
+        let tuning = AutoTuning::probe();
+
+        mmap_memory.apply_tuning(tuning);
+        tuning
+    }
+
+    /**
+     * Changes a setting of the mmap allocator at runtime, like mallopt, the settings can also be given in the
+     * QUALLOC_OPTIONS environment variable, it's read at the first allocation.
+     *
+     * @return If the mmap allocator haves the setting.
+     *
+     * @note Objects of the small allocator only use SmallAllocator and SmallMaxSize, the settings that need
+     * the header of the section (canaries and the quarantine) turn the small allocator off while they are
+     * enabled.
+     */
+    pub fn set_option(tunable: Tunable) -> bool {
+        load_env_options();
+        mmap_memory.set_tunable(tunable)
+    }
+
+    /**
+     * Sets the function that receives the events of the mmap allocator, it's called on every allocation and
+     * deallocation and every time a region is mapped or unmapped, None stops the events.
+     *
+     * @note Objects of the small allocator send Alloc and Free events too, and the mappings of the guard
+     * page mode send RegionMap and RegionUnmap.
+     */
+    pub fn set_event_hook(hook: Option<EventHook>) {
+        mmap_memory.hooks.set_hook(hook)
+    }
+
+    /**
+     * Sets the limit of the live bytes of a tag, the allocations that would go over it fail with
+     * AllocError::TagLimitExceeded.
+     *
+     * @param limit The maximum number of bytes, None removes the limit.
+     * @note Tagged allocations aren't served by the small allocator, so every tagged allocation has a header.
+     */
+    pub fn set_tag_limit(tag: AllocTag, limit: Option<usize>) {
+        mmap_memory.tags.set_limit(tag, limit)
+    }
+
+    /**
+     * Gets the live bytes, the live allocations and the peak of a tag.
+     *
+     * @note Objects of the small allocator are counted in UNTAGGED with the size of their class. While
+     * UNTAGGED haves a limit the small allocator isn't used, so the limit is checked for every allocation.
+     */
+    pub fn tag_stats(tag: AllocTag) -> TagStats {
+        mmap_memory.tags.stats(tag)
+    }
+
+    /**
+     * Gets the stats of the tags that were used or have a limit
+     */
+    pub fn all_tag_stats() -> Vec<TagStats> {
+        mmap_memory.tags.all_stats()
+    }
+
+    /**
+     * Sets what the mmap allocator does when it finds a double free or an invalid free.
+     *
+     * @note Objects of the small allocator are checked with the free bitmaps of their slabs.
+     */
+    pub fn set_invalid_free_action(action: InvalidFreeAction) {
+        mmap_memory.free_checks.set_action(action)
+    }
+
+    /**
+     * Sets the function that receives the invalid free reports of the mmap allocator, None prints them
+     * to stderr.
+     */
+    pub fn set_invalid_free_handler(handler: Option<InvalidFreeHandler>) {
+        mmap_memory.free_checks.set_handler(handler)
+    }
+
+    /**
+     * Enables or disables capturing the allocation and free backtraces used by the invalid free reports.
+     *
+     * @note While the backtraces are enabled the small allocator isn't used, so every allocation gets its
+     * allocation backtrace.
+     */
+    pub fn set_free_backtraces(enabled: bool) {
+        mmap_memory.free_checks.set_backtraces(enabled)
+    }
+
+    /**
+     * Enables or disables recording the call site of every allocation of the mmap allocator, disabling it
+     * forgets the recorded call sites.
+     *
+     * @note While the call sites are enabled the small allocator isn't used, so every new allocation is
+     * recorded.
+     */
+    pub fn set_call_sites(enabled: bool) {
+        mmap_memory.call_sites.set_enabled(enabled)
+    }
+
+    /**
+     * Gets the call sites of the mmap allocator with the most live bytes.
+     *
+     * @param count Maximum number of call sites returned.
+     */
+    pub fn top_call_sites_by_bytes(count: usize) -> Vec<CallSiteStats> {
+        mmap_memory.call_sites.top_by_bytes(count)
+    }
+
+    /**
+     * Gets the call sites of the mmap allocator with the most live allocations.
+     *
+     * @param count Maximum number of call sites returned.
+     */
+    pub fn top_call_sites_by_count(count: usize) -> Vec<CallSiteStats> {
+        mmap_memory.call_sites.top_by_count(count)
+    }
+
+    /**
+     * Enables or disables the sampling heap profiler of the mmap allocator, objects of the small allocator
+     * are sampled too.
+     *
+     * @param sample_interval Mean bytes allocated between two samples, SAMPLE_INTERVAL_DEFAULT is
+     * the default of tcmalloc.
+     */
+    pub fn set_heap_profiling(enabled: bool, sample_interval: usize) {
+        mmap_memory.profiler.set_enabled(enabled, sample_interval)
+    }
+
+    /**
+     * Takes the live samples of the heap profiler of the mmap allocator.
+     */
+    pub fn heap_profile() -> HeapProfile {
+        mmap_memory.profiler.profile()
+    }
+
+    /**
+     * Writes the heap profile of the mmap allocator to a file, for analyzing it with pprof.
+     */
+    pub fn dump_heap_profile(path: impl AsRef<Path>, format: ProfileFormat) -> io::Result<()> {
+        mmap_memory.profiler.profile().dump(path, format)
+    }
+
+    /**
+     * Enables or disables the quarantine of the mmap allocator, freed blocks are poisoned and they aren't
+     * reused until max_bytes of other blocks are freed after them.
+     *
+     * @note While the quarantine is enabled the small allocator isn't used, so the blocks allocated while it's
+     * enabled are poisoned when they are freed. Small objects allocated before enabling it don't go to the
+     * quarantine.
+     */
+    pub fn set_quarantine(enabled: bool, max_bytes: usize) {
+        mmap_memory.quarantine.set_enabled(enabled, max_bytes)
+    }
+
+    /**
+     * Sets the function that receives the write after free reports of the mmap allocator, None prints them
+     * to stderr.
+     */
+    pub fn set_use_after_free_handler(handler: Option<UseAfterFreeHandler>) {
+        mmap_memory.quarantine.set_handler(handler)
+    }
+
+    /**
+     * Takes all the blocks out of the quarantine of the mmap allocator, checking their poison pattern.
+     */
+    pub fn flush_quarantine() {
+        mmap_memory.flush_quarantine()
+    }
+
+    /**
+     * Enables or disables the canaries of the mmap allocator, the blocks allocated while it's enabled get canaries
+     * before and after their user data, they are checked on free and by check_all.
+     *
+     * @note While the canaries are enabled the small allocator isn't used, so every new allocation gets them.
+     */
+    pub fn set_canaries(enabled: bool) {
+        mmap_memory.canaries.set_enabled(enabled)
+    }
+
+    /**
+     * Sets the function that receives the overflow reports found on free by the mmap allocator, None prints
+     * them to stderr.
+     */
+    pub fn set_overflow_handler(handler: Option<OverflowHandler>) {
+        mmap_memory.canaries.set_handler(handler)
+    }
+
+    /**
+     * Checks the canaries of all the blocks of the mmap allocator that aren't free.
+     *
+     * @return The reports of the blocks whose canaries were changed.
+     */
+    pub fn check_all() -> Vec<OverflowReport> {
+        mmap_memory.check_all()
+    }
+
+    /**
+     * Enables the guard page mode, every allocation gets its own mapping with guard pages, so out of bounds
+     * accesses and accesses after free fault on the instruction that does them.
+     *
+     * @note Allocations done before enabling it aren't affected, and small objects use guard pages too
+     * while the mode is enabled.
+     */
+    pub fn enable_guard_pages(options: GuardOptions) {
+        GuardAllocator::enable(options)
+    }
+
+    /**
+     * Disables the guard page mode for the next allocations.
+     */
+    pub fn disable_guard_pages() {
+        GuardAllocator::disable()
+    }
+
+    /**
+     * Unmaps all the regions of the mmap allocator.
+     *
+     * @warning Every pointer given by the mmap allocator, except small objects, becomes invalid.
+     * @note Small objects survive the teardown, so they are counted again in the quota and the untagged
+     * allocations, and their deallocations don't take the counters under 0. Small objects allocated or
+     * deallocated by other threads while tearing down can be miscounted.
+     */
+    pub fn teardown() {
+        mmap_memory.teardown();
+
+        for object in SmallAllocator::live_objects() {
+            mmap_memory.quota.record_allocation(object.size);
+            mmap_memory.tags.record_allocation(UNTAGGED, object.size);
+        }
     }
 }
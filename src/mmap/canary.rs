@@ -0,0 +1,116 @@
+use std::sync::atomic::Ordering;
+
+use crate::{
+    canary::{
+        OverflowReport, canary_value, count_clobbered_bytes, read_rear_canary, write_rear_canary,
+    },
+    source::MemorySource,
+};
+
+use super::{MmapHeap, MmapMemoryRegion, MmapMemorySectionHeader, walk::section_entry};
+
+impl<S: MemorySource> MmapHeap<S> {
+    /**
+     * Stores the user size of a section that was just allocated and writes its canaries if it was sized for
+     * them, the heap lock must be taken by the caller
+     */
+    pub(super) unsafe fn arm_canaries(
+        &self,
+        section: *mut MmapMemorySectionHeader,
+        usr_size: usize,
+        has_canaries: bool,
+    ) {
+        unsafe {
+            (*section).usr_size = usr_size;
+            (*section).has_canaries = has_canaries;
+
+            if !(*section).has_canaries {
+                return;
+            }
+
+            let canary = canary_value(section as usize);
+
+            (*section).front_canary = canary;
+            write_rear_canary(section.add(1) as usize, usr_size, canary);
+        }
+    }
+
+    /**
+     * Checks the canaries of a section, the heap lock must be taken by the caller
+     *
+     * @return The report if any canary was changed.
+     */
+    pub(super) unsafe fn check_canaries(
+        &self,
+        region: *mut MmapMemoryRegion,
+        section: *mut MmapMemorySectionHeader,
+    ) -> Option<OverflowReport> {
+        unsafe {
+            if !(*section).has_canaries {
+                return None;
+            }
+
+            let canary = canary_value(section as usize);
+            let usr_size = (*section).usr_size;
+            let front_clobbered = count_clobbered_bytes((*section).front_canary, canary);
+            let rear_clobbered =
+                count_clobbered_bytes(read_rear_canary(section.add(1) as usize, usr_size), canary);
+
+            if front_clobbered == 0 && rear_clobbered == 0 {
+                return None;
+            }
+
+            Some(OverflowReport {
+                block: section_entry(region, section),
+                usr_size,
+                front_clobbered,
+                rear_clobbered,
+            })
+        }
+    }
+
+    /**
+     * Checks the canaries of all the sections of this heap that aren't free.
+     *
+     * @return The reports of the sections whose canaries were changed.
+     *
+     * @note A section whose header was overwritten can break the list, verify can find it.
+     */
+    pub fn check_all(&self) -> Vec<OverflowReport> {
+        let memory_guard = self.memory.lock().unwrap();
+        let mut reports = Vec::new();
+        let mut current_region = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        while let Some(region) = current_region {
+            unsafe {
+                let mut current_section = (*region)
+                    .head_section
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst));
+
+                while let Some(section) = current_section {
+                    if !(*section).is_free
+                        && let Some(report) = self.check_canaries(region, section)
+                    {
+                        reports.push(report);
+                    }
+
+                    current_section = (*section)
+                        .next
+                        .as_ref()
+                        .map(|ptr| ptr.load(Ordering::SeqCst));
+                }
+
+                current_region = (*region)
+                    .next
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst));
+            }
+        }
+
+        reports
+    }
+}
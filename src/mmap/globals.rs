@@ -1,9 +1,9 @@
-use std::sync::{Mutex, atomic::AtomicPtr};
-
 use lazy_static::lazy_static;
 
-use super::MmapMemoryRegion;
+use crate::source::mmap::MmapSource;
+
+use super::MmapHeap;
 
 lazy_static! {
-    pub static ref mmap_memory: Mutex<Option<AtomicPtr<MmapMemoryRegion>>> = Mutex::new(None);
+    pub static ref mmap_memory: MmapHeap<MmapSource> = MmapHeap::new(MmapSource {});
 }
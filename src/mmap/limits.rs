@@ -0,0 +1,30 @@
+use std::sync::atomic::Ordering;
+
+use crate::{limits::AutoTuning, quota::QuotaKind, source::MemorySource};
+
+use super::MmapHeap;
+
+impl<S: MemorySource> MmapHeap<S> {
+    /**
+     * Applies the settings derived from the memory limits, the soft limit and the low watermark become the
+     * watermarks of the mapped bytes, the heap is trimmed when the soft limit is crossed, new regions are
+     * mapped with at least the minimum region size and empty regions smaller than the trim threshold are
+     * kept mapped.
+     *
+     * @note The quarantine keeps its enabled state, only its size is changed. Without a pressure handler the
+     * crossings of the soft limit are printed to stderr.
+     */
+    pub fn apply_tuning(&self, tuning: AutoTuning) {
+        self.watermarks
+            .set(tuning.soft_limit, tuning.low_watermark, QuotaKind::OsBytes);
+        self.watermarks.set_auto_trim(tuning.soft_limit.is_some());
+        self.quarantine
+            .set_enabled(self.quarantine.is_enabled(), tuning.quarantine_size);
+        self.min_region_size
+            .store(tuning.min_region_size, Ordering::Relaxed);
+        self.trim_threshold
+            .store(tuning.trim_threshold, Ordering::Relaxed);
+
+        *self.tuning.lock().unwrap() = Some(tuning);
+    }
+}
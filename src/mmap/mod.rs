@@ -1,8 +1,27 @@
-use std::sync::atomic::AtomicPtr;
+use std::sync::{
+    Mutex,
+    atomic::{AtomicBool, AtomicPtr, AtomicUsize},
+};
+
+use crate::{
+    call_site::CallSites, canary::Canaries, free_check::FreeChecks, hooks::EventHooks,
+    leak::LeakSuppressions, limits::AutoTuning, oom::OomHooks, placement::Placement,
+    pressure::Watermarks, profile::HeapProfiler, quarantine::Quarantine, quota::Quota,
+    small::SMALL_MAX_SIZE, source::MemorySource, stats::HeapCounters, tags::{AllocTag, Tags, UNTAGGED},
+};
 
 pub mod globals;
 pub mod utils;
 pub mod allocator;
+pub mod canary;
+pub mod leak;
+pub mod limits;
+pub mod oom;
+pub mod pressure;
+pub mod quarantine;
+pub mod tunables;
+pub mod verify;
+pub mod walk;
 
 /**
  * Mmap memory allocator is the modern way to make a memory allocator, it uses mmap and unmap
@@ -115,11 +134,23 @@ impl MmapMemoryRegion {
     }
 }
 
+/*
+ * The header is repr(C) because the front canary must be the last field, just before the user data
+ */
+#[repr(C)]
 pub struct MmapMemorySectionHeader {
     pub size: usize,
+    // Size asked by the user, it's smaller than size when the section haves canaries or it was reused
+    pub usr_size: usize,
     pub is_free: bool,
+    // A freed section that is kept in the quarantine, it can't be reused until it leaves the quarantine
+    pub in_quarantine: bool,
+    pub has_canaries: bool,
+    // Tag of the allocation, it's counted in the tags of the heap until the user deallocates it
+    pub tag: AllocTag,
     pub next: Option<AtomicPtr<MmapMemorySectionHeader>>,
     pub prev: Option<AtomicPtr<MmapMemorySectionHeader>>,
+    pub front_canary: u64,
 }
 
 impl MmapMemorySectionHeader {
@@ -131,13 +162,91 @@ impl MmapMemorySectionHeader {
     ) -> Self {
         Self {
             size,
+            usr_size: size,
             is_free,
+            in_quarantine: false,
+            has_canaries: false,
+            tag: UNTAGGED,
             next,
             prev,
+            front_canary: 0,
         }
     }
 
     pub fn size() -> usize {
         size_of::<Self>()
     }
+
+    /**
+     * Checks if the section can be given to the user
+     */
+    pub fn is_available(&self) -> bool {
+        self.is_free && !self.in_quarantine
+    }
+}
+
+/**
+ * A mmap heap is the list of regions of the mmap allocator together with the memory source where the regions
+ * are mapped from, MmapAllocator uses a global heap over mmap but a heap can be created over any memory source
+ */
+pub struct MmapHeap<S: MemorySource> {
+    pub memory: Mutex<MmapHeapState<S>>,
+    pub counters: HeapCounters,
+    pub verify_after_operations: AtomicBool,
+    pub free_checks: FreeChecks,
+    pub quarantine: Quarantine,
+    pub canaries: Canaries,
+    pub call_sites: CallSites,
+    pub profiler: HeapProfiler,
+    pub leak_suppressions: LeakSuppressions,
+    pub placement: Placement,
+    pub quota: Quota,
+    pub watermarks: Watermarks,
+    pub oom: OomHooks,
+    pub hooks: EventHooks,
+    pub tags: Tags,
+    // Regions are mapped with at least this size, 0 maps every region with the size of its first section
+    pub min_region_size: AtomicUsize,
+    // Settings applied by apply_tuning, they are reported by the stats
+    pub tuning: Mutex<Option<AutoTuning>>,
+    // A region smaller than this isn't unmapped when its last section is freed
+    pub trim_threshold: AtomicUsize,
+    // Sections of at least this size are placed in a region of their own
+    pub mmap_threshold: AtomicUsize,
+    // Settings of the small allocator, they are only used by the global heap, MmapAllocator
+    pub small_enabled: AtomicBool,
+    pub small_max_size: AtomicUsize,
+}
+
+impl<S: MemorySource> MmapHeap<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            memory: Mutex::new(MmapHeapState { head: None, source }),
+            counters: HeapCounters::new(),
+            verify_after_operations: AtomicBool::new(false),
+            free_checks: FreeChecks::new(),
+            quarantine: Quarantine::new(),
+            canaries: Canaries::new(),
+            call_sites: CallSites::new(),
+            profiler: HeapProfiler::new(),
+            leak_suppressions: LeakSuppressions::new(),
+            placement: Placement::new(),
+            quota: Quota::new(),
+            watermarks: Watermarks::new(),
+            oom: OomHooks::new(),
+            hooks: EventHooks::new(),
+            tags: Tags::new(),
+            min_region_size: AtomicUsize::new(0),
+            tuning: Mutex::new(None),
+            trim_threshold: AtomicUsize::new(0),
+            mmap_threshold: AtomicUsize::new(usize::MAX),
+            small_enabled: AtomicBool::new(true),
+            small_max_size: AtomicUsize::new(SMALL_MAX_SIZE),
+        }
+    }
+}
+
+pub struct MmapHeapState<S: MemorySource> {
+    pub head: Option<AtomicPtr<MmapMemoryRegion>>,
+    pub source: S,
 }
@@ -0,0 +1,61 @@
+use crate::{error::AllocError, oom::OomReport, source::MemorySource};
+
+use super::MmapHeap;
+
+impl<S: MemorySource> MmapHeap<S> {
+    /**
+     * Allocates the emergency reserve of this heap, it's deallocated when the heap runs out of memory for the
+     * first time, so the program has memory to shut down.
+     *
+     * @param size The size of the reserve, 0 removes the reserve.
+     * @return The reason why the reserve can't be allocated, the previous reserve is kept in that case.
+     *
+     * @note The reserve is counted as used memory by the stats and the quota, and it isn't reported as a leak.
+     */
+    pub fn set_emergency_reserve(&self, size: usize) -> Result<(), AllocError> {
+        let usr_address = match size {
+            0 => 0,
+            _ => {
+                let usr_address = self.try_allocate::<u8>(size)? as usize;
+
+                self.leak_suppressions.suppress(usr_address);
+                usr_address
+            }
+        };
+
+        if let Some(previous) = self.oom.replace_reserve(usr_address, size) {
+            self.deallocate(previous as *const u8);
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Releases the emergency reserve and trims the heap, then asks the OOM handler if the allocation must be
+     * tried again, it must be called without the heap lock.
+     *
+     * @param error The reason why the allocation failed.
+     * @param requested The size asked by the user.
+     * @param attempt The number of times the allocation failed.
+     * @return If the allocation must be tried again.
+     */
+    pub(super) fn handle_oom(&self, error: AllocError, requested: usize, attempt: usize) -> bool {
+        let reserve = self.oom.take_reserve();
+
+        if let Some(reserve) = reserve {
+            self.deallocate(reserve as *const u8);
+        }
+
+        /*
+         * The quarantine is flushed too, so the quarantined sections can be reused by the next attempt
+         */
+        self.trim();
+
+        self.oom.report(&OomReport {
+            error,
+            requested,
+            attempt,
+            reserve_released: reserve.is_some(),
+        })
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::atomic::Ordering;
+
+use crate::{
+    pressure::{PressureLevel, PressureReport},
+    source::MemorySource,
+};
+
+use super::MmapHeap;
+
+impl<S: MemorySource> MmapHeap<S> {
+    /**
+     * Unmaps the regions whose sections are all free, the quarantine is flushed before, so its sections can
+     * be given back too.
+     *
+     * @return The number of bytes unmapped.
+     *
+     * @note Regions are unmapped when their last section is freed, so trimming only gives back the
+     * quarantined sections and the regions that the memory source couldn't unmap before.
+     */
+    pub fn trim(&self) -> usize {
+        let heap_size = self.counters.heap_size.load(Ordering::Relaxed);
+
+        let reports = {
+            let mut memory_guard = self.memory.lock().unwrap();
+            let reports = self.evict_quarantine(&mut memory_guard, true);
+
+            let mut current_region = memory_guard
+                .head
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst));
+
+            while let Some(region) = current_region {
+                unsafe {
+                    current_region = (*region)
+                        .next
+                        .as_ref()
+                        .map(|ptr| ptr.load(Ordering::SeqCst));
+
+                    if (*region).space_available == (*region).total_space {
+                        self.release_region(&mut memory_guard, region);
+                    }
+                }
+            }
+
+            self.verify_after_operation(memory_guard);
+            reports
+        };
+
+        self.quarantine.report(reports);
+        self.hooks.flush();
+
+        heap_size.saturating_sub(self.counters.heap_size.load(Ordering::Relaxed))
+    }
+
+    /**
+     * Checks the watermarks after an operation and reports the crossing, it must be called without the
+     * heap lock
+     */
+    pub(super) fn check_pressure(&self) {
+        let Some(report) = self.watermarks.check(
+            self.quota.user_bytes(),
+            self.counters.heap_size.load(Ordering::Relaxed),
+        ) else {
+            return;
+        };
+
+        self.report_pressure(&report);
+    }
+
+    fn report_pressure(&self, report: &PressureReport) {
+        self.watermarks.report(report);
+
+        if report.level == PressureLevel::High && self.watermarks.is_auto_trim_enabled() {
+            self.trim();
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::atomic::Ordering;
+
+use crate::{
+    quarantine::{Quarantine, UseAfterFreeReport},
+    source::MemorySource,
+};
+
+use super::{
+    MmapHeap, MmapHeapState, MmapMemorySectionHeader, utils::find_section, walk::section_entry,
+};
+
+impl<S: MemorySource> MmapHeap<S> {
+    /**
+     * Takes the sections that must leave the quarantine, checks their poison pattern and frees them for real,
+     * the heap lock must be taken by the caller
+     *
+     * @param flush Takes all the sections of the quarantine.
+     * @return The reports of the sections that were written after free.
+     */
+    pub(super) fn evict_quarantine(
+        &self,
+        memory_guard: &mut MmapHeapState<S>,
+        flush: bool,
+    ) -> Vec<UseAfterFreeReport> {
+        let mut reports = Vec::new();
+
+        while let Some(block) = self.quarantine.pop(flush) {
+            let usr_data = (block.address + MmapMemorySectionHeader::size()) as *const u8;
+
+            /*
+             * The quarantine only keeps the section, so its region is looked for again
+             */
+            let Some((region, section)) = find_section(
+                memory_guard
+                    .head
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst)),
+                usr_data,
+            ) else {
+                continue;
+            };
+
+            let entry = section_entry(region, section);
+
+            if block.poisoned
+                && let Some((offset, value)) =
+                    Quarantine::find_poison_change(entry.usr_address, block.size)
+            {
+                reports.push(UseAfterFreeReport {
+                    block: entry,
+                    offset,
+                    value,
+                });
+            }
+
+            unsafe { self.free_section(memory_guard, region, section) };
+        }
+
+        reports
+    }
+
+    /**
+     * Takes all the sections out of the quarantine, checking their poison pattern, so they can be reused.
+     */
+    pub fn flush_quarantine(&self) {
+        let reports = {
+            let mut memory_guard = self.memory.lock().unwrap();
+
+            self.evict_quarantine(&mut memory_guard, true)
+        };
+
+        self.quarantine.report(reports);
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::atomic::Ordering;
+
+use crate::{small::SMALL_MAX_SIZE, source::MemorySource, tunables::Tunable};
+
+use super::MmapHeap;
+
+impl<S: MemorySource> MmapHeap<S> {
+    /**
+     * Changes a setting of this heap, like mallopt.
+     *
+     * @return If the heap haves the setting, the mmap heap haves all of them.
+     */
+    pub fn set_tunable(&self, tunable: Tunable) -> bool {
+        match tunable {
+            Tunable::TrimThreshold(size) => self.trim_threshold.store(size, Ordering::Relaxed),
+            Tunable::MmapThreshold(size) => self.mmap_threshold.store(size, Ordering::Relaxed),
+            Tunable::MinRegionSize(size) => self.min_region_size.store(size, Ordering::Relaxed),
+            Tunable::QuarantineSize(size) => self
+                .quarantine
+                .set_enabled(self.quarantine.is_enabled(), size),
+            Tunable::Placement(policy) => self.placement.set_policy(policy),
+            Tunable::Canaries(enabled) => self.canaries.set_enabled(enabled),
+            Tunable::Quarantine(enabled) => self
+                .quarantine
+                .set_enabled(enabled, self.quarantine.max_bytes()),
+            Tunable::Poisoning(enabled) => self.quarantine.set_poisoning(enabled),
+            Tunable::SmallAllocator(enabled) => {
+                self.small_enabled.store(enabled, Ordering::Relaxed)
+            }
+            Tunable::SmallMaxSize(size) => self
+                .small_max_size
+                .store(size.min(SMALL_MAX_SIZE), Ordering::Relaxed),
+            Tunable::VerifyAfterOperations(enabled) => self.set_verify_after_operations(enabled),
+        }
+
+        true
+    }
+}
@@ -1,11 +1,12 @@
-use libc::{
-    _SC_PAGESIZE, MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, PROT_READ, PROT_WRITE, mmap, munmap,
-    sysconf,
-};
-use std::{
-    os::raw::c_void,
-    ptr,
-    sync::atomic::{AtomicPtr, Ordering},
+use libc::{_SC_PAGESIZE, sysconf};
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::{
+    error::AllocError,
+    hooks::{EventHooks, EventKind, HeapEvent},
+    quota::Quota,
+    source::MemorySource,
+    stats::HeapCounters,
 };
 
 use super::{MmapMemoryRegion, MmapMemorySectionHeader};
@@ -26,34 +27,54 @@ pub fn get_page_size() -> usize {
  *
  * rounded to page size = 2048
  */
-pub fn round_up_to_page_size(size: usize) -> usize {
-    let page_size = get_page_size();
+pub fn round_up_to_page_size(size: usize, page_size: usize) -> usize {
     ((size + page_size - 1) / page_size) * page_size
 }
 
 /**
- * Allocates a region into heap, uses mmap for asking to OS a block of memory
+ * Allocates a region into heap, uses the memory source for asking a block of memory
  * and returns a pointer to the Region
+ *
+ * @param hooks The hooks of the heap, the mapping is recorded for them.
+ * @param min_region_size The region is at least this size, so the next sections can be placed in it.
+ * @warning This function returns an error if the size with the headers overflows, the region goes over
+ * the quota when it counts OS bytes or the memory source can't give the memory.
  */
-pub fn allocate_region(size: usize) -> Option<*mut MmapMemoryRegion> {
-    let block_size = round_up_to_page_size(size + MmapMemoryRegion::size());
-
-    let addr = unsafe {
-        mmap(
-            ptr::null_mut(),
-            block_size,
-            PROT_READ | PROT_WRITE,
-            MAP_PRIVATE | MAP_ANONYMOUS,
-            -1,
-            0,
-        )
-    } as *mut MmapMemoryRegion;
-
-    if addr as *mut c_void == MAP_FAILED {
-        return None;
-    }
+pub fn allocate_region<S: MemorySource>(
+    source: &mut S,
+    counters: &HeapCounters,
+    quota: &Quota,
+    hooks: &EventHooks,
+    size: usize,
+    min_region_size: usize,
+) -> Result<*mut MmapMemoryRegion, AllocError> {
+    let page_size = source.page_size();
 
-    let stored_size = block_size - MmapMemorySectionHeader::size();
+    /*
+     * The headers and the rounding to the page size are added with a checked add, so a huge size fails
+     * instead of wrapping to a small region
+     */
+    let block_size = size
+        .checked_add(MmapMemoryRegion::size() + MmapMemorySectionHeader::size())
+        .and_then(|block_size| block_size.max(min_region_size).checked_add(page_size - 1))
+        .map(|block_size| block_size / page_size * page_size)
+        .ok_or(AllocError::SizeOverflow { size })?;
+
+    quota.check_os_bytes(counters.heap_size.load(Ordering::Relaxed), block_size)?;
+
+    counters.mmap_calls.fetch_add(1, Ordering::Relaxed);
+    let addr = source
+        .map(block_size)
+        .inspect_err(|err| counters.record_os_failure(*err))?
+        as *mut MmapMemoryRegion;
+    counters.record_heap_growth(block_size);
+    hooks.record(HeapEvent {
+        kind: EventKind::RegionMap,
+        address: addr as usize,
+        size: block_size,
+    });
+
+    let stored_size = block_size - MmapMemoryRegion::size();
 
     /*
      * In region size we are going to store the memory block size minus Header Region size
@@ -64,31 +85,100 @@ pub fn allocate_region(size: usize) -> Option<*mut MmapMemoryRegion> {
      */
     unsafe { *addr = MmapMemoryRegion::new(stored_size, stored_size, None, None, None) }
 
-    return Some(addr);
+    Ok(addr)
 }
 
 /**
- * Uses munmap for deallocating a block from the heap
+ * Uses the memory source for deallocating a block from the heap
+ *
+ * @return Nothing if the region was given back, or the error of the memory source, then the region is
+ * still mapped and it must stay in the heap.
+ * @warning The region must be a region mapped by the given source, its header is read for knowing the size
+ * of the mapping, and the heap lock must be taken by the caller. The region can't be used after it's given
+ * back.
  */
-pub fn deallocate_region(region: *mut MmapMemoryRegion) {
+pub(crate) unsafe fn deallocate_region<S: MemorySource>(
+    source: &mut S,
+    counters: &HeapCounters,
+    hooks: &EventHooks,
+    region: *mut MmapMemoryRegion,
+) -> Result<(), AllocError> {
     unsafe {
         /*
          * Region.total_space contains the block size without the region size itself
          */
-        munmap(
-            region as *mut _,
-            (*region).total_space + MmapMemoryRegion::size(),
-        );
+        let block_size = (*region).total_space + MmapMemoryRegion::size();
+
+        counters.munmap_calls.fetch_add(1, Ordering::Relaxed);
+        source
+            .unmap(region as *mut u8, block_size)
+            .inspect_err(|err| counters.record_os_failure(*err))?;
+        counters.record_heap_release(block_size);
+        hooks.record(HeapEvent {
+            kind: EventKind::RegionUnmap,
+            address: region as usize,
+            size: block_size,
+        });
+
+        Ok(())
+    }
+}
+
+/**
+ * Looks for the section that contains the given user pointer
+ *
+ * @param head_region The first region of mmap_memory.
+ * @param usr_data The pointer given to the user by MmapAllocator::allocate.
+ * @return The region and the section of the user pointer or None if it isn't found
+ */
+pub fn find_section<T>(
+    head_region: Option<*mut MmapMemoryRegion>,
+    usr_data: *const T,
+) -> Option<(*mut MmapMemoryRegion, *mut MmapMemorySectionHeader)> {
+    let mut current_region = head_region;
+
+    while let Some(region) = current_region {
+        unsafe {
+            let mut current_section = (*region)
+                .head_section
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst));
+
+            while let Some(section) = current_section {
+                if section as usize + MmapMemorySectionHeader::size() == usr_data as usize {
+                    return Some((region, section));
+                }
+
+                current_section = (*section)
+                    .next
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst));
+            }
+
+            current_region = (*region)
+                .next
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst));
+        }
     }
+
+    None
 }
 
 /**
  * Gets a region and puts a section of memory inside it
+ *
+ * @note The size of new sections is rounded up to the alignment of the header, so the header of the next
+ * section placed after it is aligned.
+ * @warning The region must be a mapped region of the heap, with its sections linked, and the heap lock must be
+ * taken by the caller.
  */
-pub fn place_section_inside_region(
+pub(crate) unsafe fn place_section_inside_region(
     region: *mut MmapMemoryRegion,
     size: usize,
 ) -> Option<*mut MmapMemorySectionHeader> {
+    let size = size.next_multiple_of(align_of::<MmapMemorySectionHeader>());
+
     unsafe {
         /*
          * If regions doesn't have enough space to store the section of memory, then
@@ -120,13 +210,16 @@ pub fn place_section_inside_region(
             return Some(section_addr);
         }
 
+        let mut last_section = current_section.unwrap();
 
         /*
          * If region is already initialized, then we must iterate over every child until we found
          * a section that is free and haves enough space for storing user data
          */
         while let Some(section) = current_section {
-            if !(*section).is_free {
+            last_section = section;
+
+            if !(*section).is_available() {
                 current_section = section
                     .as_ref()
                     .unwrap()
@@ -167,7 +260,29 @@ pub fn place_section_inside_region(
         }
 
         /*
-         * If free blocks aren't found, then we must take the last section of the region and calculate this
+         * If free blocks aren't found, then the section is placed after the last section of the region
+         */
+        append_section(region, last_section, size)
+    }
+}
+
+/**
+ * Places a section just after the last section of a region, or returns None if it doesn't fit
+ *
+ * @note Regions are mapped with at least the minimum region size of the heap, which is derived from the memory
+ * limit by AutoTuning, so a region haves room for more sections after its first one. The sections that don't
+ * fit in a free section are placed in that room instead of mapping a new region for each of them.
+ * @warning The last section must be the last section of the region, the size must be rounded up to the
+ * alignment of the header and the heap lock must be taken by the caller.
+ */
+unsafe fn append_section(
+    region: *mut MmapMemoryRegion,
+    last_section: *mut MmapMemorySectionHeader,
+    size: usize,
+) -> Option<*mut MmapMemorySectionHeader> {
+    unsafe {
+        /*
+         * We must take the last section of the region and calculate this
          * 
          * First, we need to calculate the range of memory that a region haves
          * 
@@ -195,7 +310,21 @@ pub fn place_section_inside_region(
          * 
          * If this condition is met, then we can place the user section just after the last section of region
          */
+        let section_addr =
+            last_section as usize + MmapMemorySectionHeader::size() + (*last_section).size;
+        let region_end = region as usize + MmapMemoryRegion::size() + (*region).total_space;
+
+        if section_addr + MmapMemorySectionHeader::size() + size > region_end {
+            return None;
+        }
+
+        let section_addr = section_addr as *mut MmapMemorySectionHeader;
+
+        (*section_addr) =
+            MmapMemorySectionHeader::new(size, false, None, Some(AtomicPtr::new(last_section)));
+        (*last_section).next = Some(AtomicPtr::new(section_addr));
+        (*region).space_available -= (*section_addr).size + MmapMemorySectionHeader::size();
 
-        None
+        Some(section_addr)
     }
 }
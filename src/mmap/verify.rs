@@ -0,0 +1,209 @@
+use std::sync::{MutexGuard, atomic::Ordering};
+
+use crate::{error::AllocError, source::MemorySource, verify::HeapError};
+
+use super::{MmapHeap, MmapHeapState, MmapMemoryRegion, MmapMemorySectionHeader};
+
+/**
+ * Checks the invariants of the regions of a mmap heap and their sections, the heap lock must be taken by
+ * the caller
+ *
+ * - The heads don't have prev and the prev of every next region or section points back to it
+ * - Regions are multiples of the page size, they aren't address-ordered because mmap can give any address
+ * - Sections are address-ordered inside their region, they don't overlap and they stay inside its bounds
+ * - Section sizes are multiples of the alignment of the header, so the header of the next section is aligned
+ * - The space available of a region is its space minus the sections that aren't free or are in the
+ *   quarantine, and their headers
+ *
+ * @return The first violation found.
+ */
+pub fn verify_mmap_heap<S: MemorySource>(memory: &MmapHeapState<S>) -> Result<(), HeapError> {
+    let page_size = memory.source.page_size();
+    let mut current_region = memory.head.as_ref().map(|ptr| ptr.load(Ordering::SeqCst));
+
+    if let Some(head) = current_region
+        && unsafe { (*head).prev.is_some() }
+    {
+        return Err(HeapError::HeadWithPrev {
+            address: head as usize,
+        });
+    }
+
+    while let Some(region) = current_region {
+        unsafe {
+            let region_size = (*region).total_space + MmapMemoryRegion::size();
+
+            if !region_size.is_multiple_of(page_size) {
+                return Err(HeapError::UnalignedSize {
+                    address: region as usize,
+                    size: (*region).total_space,
+                });
+            }
+
+            verify_region_sections(region)?;
+
+            current_region = (*region)
+                .next
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst));
+
+            if let Some(next) = current_region
+                && (*next).prev.as_ref().map(|ptr| ptr.load(Ordering::SeqCst)) != Some(region)
+            {
+                return Err(HeapError::BrokenLink {
+                    address: region as usize,
+                    next: next as usize,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Checks the sections of a region and its space available
+ */
+unsafe fn verify_region_sections(region: *mut MmapMemoryRegion) -> Result<(), HeapError> {
+    unsafe {
+        let region_start = region as usize + MmapMemoryRegion::size();
+        let region_end = region_start + (*region).total_space;
+        let mut used_space = 0;
+        let mut previous_end = region_start;
+        let mut previous: Option<*mut MmapMemorySectionHeader> = None;
+        let mut current_section = (*region)
+            .head_section
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        while let Some(section) = current_section {
+            let prev = (*section)
+                .prev
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst));
+
+            if prev != previous {
+                return Err(match previous {
+                    Some(previous) => HeapError::BrokenLink {
+                        address: previous as usize,
+                        next: section as usize,
+                    },
+                    None => HeapError::HeadWithPrev {
+                        address: section as usize,
+                    },
+                });
+            }
+
+            if (section as usize) < previous_end {
+                return Err(match previous {
+                    Some(previous) => HeapError::Overlap {
+                        address: section as usize,
+                        previous: previous as usize,
+                    },
+                    None => HeapError::OutOfRegion {
+                        region: region as usize,
+                        section: section as usize,
+                    },
+                });
+            }
+
+            if !(*section)
+                .size
+                .is_multiple_of(align_of::<MmapMemorySectionHeader>())
+            {
+                return Err(HeapError::UnalignedSize {
+                    address: section as usize,
+                    size: (*section).size,
+                });
+            }
+
+            let section_end = section as usize + MmapMemorySectionHeader::size() + (*section).size;
+
+            if section_end > region_end {
+                return Err(HeapError::OutOfRegion {
+                    region: region as usize,
+                    section: section as usize,
+                });
+            }
+
+            if !(*section).is_free || (*section).in_quarantine {
+                used_space += (*section).size + MmapMemorySectionHeader::size();
+            }
+
+            previous_end = section_end;
+            previous = Some(section);
+            current_section = (*section)
+                .next
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst));
+        }
+
+        let expected = (*region).total_space - used_space;
+
+        if (*region).space_available != expected {
+            return Err(HeapError::SpaceAvailableMismatch {
+                region: region as usize,
+                space_available: (*region).space_available,
+                expected,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: MemorySource> MmapHeap<S> {
+    /**
+     * Checks the invariants of the regions of this heap and their sections.
+     *
+     * @return The first violation found.
+     */
+    pub fn verify(&self) -> Result<(), HeapError> {
+        verify_mmap_heap(&self.memory.lock().unwrap())
+    }
+
+    /**
+     * Enables or disables running verify after every allocation and deallocation of this heap, if a
+     * violation is found, then the operation panics with it.
+     *
+     * @note Allocations also verify the heap before taking memory and return AllocError::CorruptedHeap
+     * if it was corrupted since the last operation.
+     * @note The checks only run in debug builds, release builds ignore this option.
+     */
+    pub fn set_verify_after_operations(&self, enabled: bool) {
+        self.verify_after_operations
+            .store(enabled, Ordering::Relaxed);
+    }
+
+    /**
+     * Runs verify if it was enabled with set_verify_after_operations and releases the heap lock taken by the
+     * operation, a violation panics after releasing it, so the lock isn't poisoned for the next callers
+     */
+    pub(super) fn verify_after_operation(&self, memory_guard: MutexGuard<'_, MmapHeapState<S>>) {
+        if !cfg!(debug_assertions) || !self.verify_after_operations.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let result = verify_mmap_heap(&memory_guard);
+        drop(memory_guard);
+
+        if let Err(err) = result {
+            panic!("mmap heap is corrupted: {err}");
+        }
+    }
+
+    /**
+     * Runs verify before an allocation if it was enabled with set_verify_after_operations, so memory isn't
+     * given from a heap that was corrupted since the last operation, the heap lock must be taken by the caller
+     */
+    pub(super) fn verify_before_allocation(
+        &self,
+        memory: &MmapHeapState<S>,
+    ) -> Result<(), AllocError> {
+        if !cfg!(debug_assertions) || !self.verify_after_operations.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        verify_mmap_heap(memory).map_err(AllocError::CorruptedHeap)
+    }
+}
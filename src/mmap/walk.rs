@@ -0,0 +1,149 @@
+use std::sync::{MutexGuard, atomic::Ordering};
+
+use crate::{
+    source::MemorySource,
+    stats::Stats,
+    tags::UNTAGGED,
+    walk::{HeapEntry, HeapEntryKind},
+};
+
+use super::{MmapHeap, MmapHeapState, MmapMemoryRegion, MmapMemorySectionHeader};
+
+/**
+ * Iterator over the regions of a mmap heap and the sections inside them, every region is followed by its
+ * sections. It holds the heap lock while it's alive, so the regions can't change during the walk
+ *
+ * @warning Allocating or deallocating on the same heap while the walker is alive deadlocks.
+ */
+pub struct MmapHeapWalker<'a, S: MemorySource> {
+    _memory_guard: MutexGuard<'a, MmapHeapState<S>>,
+    current_region: Option<*mut MmapMemoryRegion>,
+    current_section: Option<*mut MmapMemorySectionHeader>,
+}
+
+impl<S: MemorySource> Iterator for MmapHeapWalker<'_, S> {
+    type Item = HeapEntry;
+
+    fn next(&mut self) -> Option<HeapEntry> {
+        unsafe {
+            /*
+             * If there are sections left in the current region, then the next entry is a section
+             */
+            if let Some(section) = self.current_section {
+                let region = self.current_region?;
+
+                self.current_section = (*section)
+                    .next
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst));
+
+                if self.current_section.is_none() {
+                    self.current_region = (*region)
+                        .next
+                        .as_ref()
+                        .map(|ptr| ptr.load(Ordering::SeqCst));
+                }
+
+                return Some(section_entry(region, section));
+            }
+
+            let region = self.current_region?;
+
+            self.current_section = (*region)
+                .head_section
+                .as_ref()
+                .map(|ptr| ptr.load(Ordering::SeqCst));
+
+            if self.current_section.is_none() {
+                self.current_region = (*region)
+                    .next
+                    .as_ref()
+                    .map(|ptr| ptr.load(Ordering::SeqCst));
+            }
+
+            Some(region_entry(region))
+        }
+    }
+}
+
+/**
+ * Describes a region of a mmap heap, the heap lock must be taken by the caller
+ */
+pub(crate) fn region_entry(region: *const MmapMemoryRegion) -> HeapEntry {
+    unsafe {
+        HeapEntry {
+            kind: HeapEntryKind::Region,
+            address: region as usize,
+            usr_address: region as usize + MmapMemoryRegion::size(),
+            size: (*region).total_space,
+            is_free: (*region).space_available == (*region).total_space,
+            region: None,
+            header_size: MmapMemoryRegion::size(),
+            tag: UNTAGGED,
+        }
+    }
+}
+
+/**
+ * Describes a section of a mmap heap, the heap lock must be taken by the caller
+ */
+pub(crate) fn section_entry(
+    region: *const MmapMemoryRegion,
+    section: *const MmapMemorySectionHeader,
+) -> HeapEntry {
+    unsafe {
+        HeapEntry {
+            kind: HeapEntryKind::Section,
+            address: section as usize,
+            usr_address: section as usize + MmapMemorySectionHeader::size(),
+            size: (*section).size,
+            is_free: (*section).is_free,
+            region: Some(region as usize),
+            header_size: MmapMemorySectionHeader::size(),
+            tag: (*section).tag,
+        }
+    }
+}
+
+impl<S: MemorySource> MmapHeap<S> {
+    /**
+     * Walks over the regions of this heap and their sections in list order.
+     *
+     * @return An iterator that holds the heap lock until it's dropped.
+     */
+    pub fn walk(&self) -> MmapHeapWalker<'_, S> {
+        let memory_guard = self.memory.lock().unwrap();
+        let current_region = memory_guard
+            .head
+            .as_ref()
+            .map(|ptr| ptr.load(Ordering::SeqCst));
+
+        MmapHeapWalker {
+            _memory_guard: memory_guard,
+            current_region,
+            current_section: None,
+        }
+    }
+
+    /**
+     * Takes a consistent copy of all the regions and sections of this heap, the lock is released before
+     * returning.
+     */
+    pub fn snapshot(&self) -> Vec<HeapEntry> {
+        self.walk().collect()
+    }
+
+    /**
+     * Takes the statistics of this heap, the counters are read while the heap lock is taken, so they agree
+     * with the regions and sections found by the walk.
+     */
+    pub fn stats(&self) -> Stats {
+        let walker = self.walk();
+
+        self.counters
+            .load()
+            .add_entries(walker)
+            .add_quota(&self.quota)
+            .add_tuning(*self.tuning.lock().unwrap())
+    }
+}
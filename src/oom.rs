@@ -0,0 +1,169 @@
+use std::{
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::{
+    error::AllocError,
+    fork::{ForkGuards, ForkLocks, lock_for_fork},
+};
+
+/*
+ * Maximum number of retries of an allocation, so a handler that always asks for a retry can't hang it
+ */
+pub const MAX_OOM_RETRIES: usize = 16;
+
+/**
+ * What the allocator does after the OOM handler returns
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OomAction {
+    // The handler freed memory, so the allocation is tried again
+    Retry,
+    // The allocation returns the error
+    Fail,
+}
+
+/**
+ * Allocation that failed because there isn't memory for it
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OomReport {
+    pub error: AllocError,
+    // Size asked by the user
+    pub requested: usize,
+    // Number of times the allocation failed, it's 1 the first time the handler is called for it
+    pub attempt: usize,
+    // The emergency reserve was given back to the heap because of this failure
+    pub reserve_released: bool,
+}
+
+impl fmt::Display for OomReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "out of memory allocating {} bytes (attempt {}): {}",
+            self.requested, self.attempt, self.error
+        )?;
+
+        if self.reserve_released {
+            write!(formatter, ", the emergency reserve was released")?;
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Function called when an allocation runs out of memory, it's an Arc because it's cloned out of its lock
+ * before being called, so it can free memory of the heap
+ */
+pub type OomHandler = Arc<dyn Fn(&OomReport) -> OomAction + Send + Sync>;
+
+/**
+ * Out of memory handling of a heap, when an allocation fails because the memory source or the quota
+ * refused it, the emergency reserve is released and the handler decides if the allocation is tried again
+ *
+ * The emergency reserve is a block allocated ahead of time and kept by the heap, it's deallocated on the
+ * first out of memory, so the program has some memory to log, flush its state and shut down
+ *
+ * @note Without a handler, the allocation is only tried again when the reserve was just released.
+ */
+pub struct OomHooks {
+    handler: Mutex<Option<OomHandler>>,
+    // User pointer of the emergency reserve, 0 when the heap doesn't have one
+    reserve: AtomicUsize,
+    reserve_size: AtomicUsize,
+    events: AtomicUsize,
+}
+
+impl Default for OomHooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OomHooks {
+    pub const fn new() -> Self {
+        Self {
+            handler: Mutex::new(None),
+            reserve: AtomicUsize::new(0),
+            reserve_size: AtomicUsize::new(0),
+            events: AtomicUsize::new(0),
+        }
+    }
+
+    /**
+     * Sets the function called when an allocation runs out of memory, None fails the allocations
+     */
+    pub fn set_handler(&self, handler: Option<OomHandler>) {
+        *self.handler.lock().unwrap() = handler;
+    }
+
+    /**
+     * Gets the size of the emergency reserve that isn't released, it's 0 if there isn't one
+     */
+    pub fn reserve_size(&self) -> usize {
+        self.reserve_size.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Gets the number of allocations that ran out of memory, every retry is counted
+     */
+    pub fn events(&self) -> usize {
+        self.events.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Keeps the user pointer of a new emergency reserve.
+     *
+     * @return The user pointer of the previous reserve, it must be deallocated by the heap.
+     */
+    pub fn replace_reserve(&self, usr_address: usize, size: usize) -> Option<usize> {
+        self.reserve_size.store(size, Ordering::Relaxed);
+
+        match self.reserve.swap(usr_address, Ordering::AcqRel) {
+            0 => None,
+            previous => Some(previous),
+        }
+    }
+
+    /**
+     * Takes the emergency reserve, only one thread gets it.
+     *
+     * @return The user pointer of the reserve, it must be deallocated by the heap.
+     */
+    pub fn take_reserve(&self) -> Option<usize> {
+        self.replace_reserve(0, 0)
+    }
+
+    /**
+     * Counts the failure and asks the handler what to do, it must be called without the heap lock.
+     *
+     * @return If the allocation must be tried again.
+     */
+    pub fn report(&self, report: &OomReport) -> bool {
+        self.events.fetch_add(1, Ordering::Relaxed);
+
+        if report.attempt > MAX_OOM_RETRIES {
+            return false;
+        }
+
+        let handler = self.handler.lock().unwrap().clone();
+        let action = match handler {
+            Some(handler) => handler(report),
+            None => OomAction::Fail,
+        };
+
+        action == OomAction::Retry || report.reserve_released
+    }
+}
+
+impl ForkLocks for OomHooks {
+    fn lock_all(&'static self, guards: &mut ForkGuards) {
+        guards.push(lock_for_fork(&self.handler));
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/**
+ * How a heap chooses the free block or section that stores a new allocation
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PlacementPolicy {
+    // Takes the first free block that is big enough, merging adjacent free blocks when it's needed
+    FirstFit,
+    // Takes the smallest free block that is big enough, it falls back to first fit if there isn't one
+    BestFit,
+}
+
+/**
+ * Placement policy of a heap, it can be changed at any time
+ */
+pub struct Placement {
+    policy: AtomicU8,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Placement {
+    pub const fn new() -> Self {
+        Self {
+            policy: AtomicU8::new(PlacementPolicy::FirstFit as u8),
+        }
+    }
+
+    pub fn policy(&self) -> PlacementPolicy {
+        match self.policy.load(Ordering::Relaxed) {
+            0 => PlacementPolicy::FirstFit,
+            _ => PlacementPolicy::BestFit,
+        }
+    }
+
+    pub fn set_policy(&self, policy: PlacementPolicy) {
+        self.policy.store(policy as u8, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+    },
+};
+
+use crate::{
+    fork::{ForkGuards, ForkLocks, lock_for_fork},
+    quota::QuotaKind,
+};
+
+/*
+ * High watermark stored when the heap doesn't have watermarks
+ */
+const NO_WATERMARK: usize = usize::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PressureLevel {
+    // The usage went over the high watermark
+    High,
+    // The usage went back under the low watermark after being over the high watermark
+    Low,
+}
+
+/**
+ * Crossing of a watermark of a heap
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PressureReport {
+    pub level: PressureLevel,
+    // Bytes counted by the watermarks after the operation that crossed it
+    pub used: usize,
+    pub watermark: usize,
+}
+
+impl fmt::Display for PressureReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.level {
+            PressureLevel::High => "over the high",
+            PressureLevel::Low => "under the low",
+        };
+
+        write!(
+            formatter,
+            "heap usage is {direction} watermark: {} bytes used, watermark of {} bytes",
+            self.used, self.watermark
+        )
+    }
+}
+
+/**
+ * Function called when the usage of a heap crosses a watermark, it's an Arc because it's cloned out of
+ * its lock before being called, so it can allocate, free or change the handler
+ */
+pub type PressureHandler = Arc<dyn Fn(&PressureReport) + Send + Sync>;
+
+/**
+ * Soft limits of a heap, when the usage goes over the high watermark the handler is called with
+ * PressureLevel::High, and when it goes back under the low watermark it's called with PressureLevel::Low
+ *
+ * Every crossing is reported once, the high watermark isn't reported again until the usage goes under
+ * the low watermark, so a handler that allocates doesn't call itself again. The checks are done after the
+ * allocations and deallocations, without the heap lock, so the handler can free memory of the heap
+ *
+ * @note With auto trim, the heap is trimmed after reporting the high watermark, so the memory freed by
+ * the handler is given back to the memory source.
+ */
+pub struct Watermarks {
+    high: AtomicUsize,
+    low: AtomicUsize,
+    kind: AtomicU8,
+    auto_trim: AtomicBool,
+    under_pressure: AtomicBool,
+    handler: Mutex<Option<PressureHandler>>,
+}
+
+impl Default for Watermarks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Watermarks {
+    pub const fn new() -> Self {
+        Self {
+            high: AtomicUsize::new(NO_WATERMARK),
+            low: AtomicUsize::new(0),
+            kind: AtomicU8::new(QuotaKind::UserBytes as u8),
+            auto_trim: AtomicBool::new(false),
+            under_pressure: AtomicBool::new(false),
+            handler: Mutex::new(None),
+        }
+    }
+
+    /**
+     * Sets the watermarks of the heap.
+     *
+     * @param high The usage that starts the pressure, None removes the watermarks.
+     * @param low The usage that ends the pressure, it's clamped to the high watermark.
+     * @param kind Counts the bytes asked by the user or the bytes taken from the memory source.
+     */
+    pub fn set(&self, high: Option<usize>, low: usize, kind: QuotaKind) {
+        let high = high.unwrap_or(NO_WATERMARK);
+
+        self.kind.store(kind as u8, Ordering::Relaxed);
+        self.low.store(low.min(high), Ordering::Relaxed);
+        self.high.store(high, Ordering::Relaxed);
+        self.under_pressure.store(false, Ordering::Relaxed);
+    }
+
+    /**
+     * Gets the high watermark, or None if the heap doesn't have watermarks
+     */
+    pub fn high(&self) -> Option<usize> {
+        match self.high.load(Ordering::Relaxed) {
+            NO_WATERMARK => None,
+            high => Some(high),
+        }
+    }
+
+    pub fn low(&self) -> usize {
+        self.low.load(Ordering::Relaxed)
+    }
+
+    pub fn kind(&self) -> QuotaKind {
+        match self.kind.load(Ordering::Relaxed) {
+            0 => QuotaKind::UserBytes,
+            _ => QuotaKind::OsBytes,
+        }
+    }
+
+    pub fn is_under_pressure(&self) -> bool {
+        self.under_pressure.load(Ordering::Relaxed)
+    }
+
+    pub fn is_auto_trim_enabled(&self) -> bool {
+        self.auto_trim.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Enables or disables trimming the heap when the high watermark is crossed
+     */
+    pub fn set_auto_trim(&self, enabled: bool) {
+        self.auto_trim.store(enabled, Ordering::Relaxed);
+    }
+
+    /**
+     * Sets the function that receives the crossings of the watermarks, None prints them to stderr
+     */
+    pub fn set_handler(&self, handler: Option<PressureHandler>) {
+        *self.handler.lock().unwrap() = handler;
+    }
+
+    /**
+     * Checks if the usage crossed a watermark since the last check, it doesn't take any lock.
+     *
+     * @param user_bytes The bytes asked by the user that aren't deallocated.
+     * @param heap_size The bytes that the heap took from its memory source.
+     * @return The crossing, it's returned only to the thread that saw it first.
+     */
+    pub fn check(&self, user_bytes: usize, heap_size: usize) -> Option<PressureReport> {
+        let high = self.high()?;
+        let used = match self.kind() {
+            QuotaKind::UserBytes => user_bytes,
+            QuotaKind::OsBytes => heap_size,
+        };
+
+        let (level, watermark) = if used > high {
+            (PressureLevel::High, high)
+        } else if used <= self.low() {
+            (PressureLevel::Low, self.low())
+        } else {
+            return None;
+        };
+
+        let under_pressure = level == PressureLevel::High;
+
+        self.under_pressure
+            .compare_exchange(
+                !under_pressure,
+                under_pressure,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            )
+            .ok()?;
+
+        Some(PressureReport {
+            level,
+            used,
+            watermark,
+        })
+    }
+
+    /**
+     * Gives the crossing to the handler, it must be called without the heap lock
+     */
+    pub fn report(&self, report: &PressureReport) {
+        let handler = self.handler.lock().unwrap().clone();
+
+        match handler {
+            Some(handler) => handler(report),
+            None => eprintln!("{report}"),
+        }
+    }
+}
+
+impl ForkLocks for Watermarks {
+    fn lock_all(&'static self, guards: &mut ForkGuards) {
+        guards.push(lock_for_fork(&self.handler));
+    }
+}
@@ -0,0 +1,527 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering},
+    },
+};
+
+use crate::{
+    call_site::CompactBacktrace,
+    fork::{ForkGuards, ForkLocks, lock_for_fork},
+};
+
+/*
+ * Mean bytes allocated between two samples when no interval is given, it's the default of tcmalloc
+ */
+pub const SAMPLE_INTERVAL_DEFAULT: usize = 512 * 1024;
+
+/*
+ * Seed of the generator of the sampling intervals, a fixed seed makes the profiles of the same
+ * program reproducible
+ */
+const SAMPLING_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/**
+ * A sampled allocation that is still alive, the weight is the number of bytes that it represents in the profile
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeapSample {
+    pub backtrace: CompactBacktrace,
+    pub size: usize,
+    pub weight: f64,
+}
+
+impl HeapSample {
+    /**
+     * Gets the number of allocations that this sample represents
+     */
+    pub fn count(&self) -> f64 {
+        self.weight / self.size as f64
+    }
+}
+
+/**
+ * Format of a dumped heap profile
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileFormat {
+    // Profile message of pprof, uncompressed, pprof reads it with or without gzip
+    Pprof,
+    // Legacy heap profile text of gperftools, pprof reads it too
+    Text,
+}
+
+/**
+ * Live sampled allocations of a heap, the samples with the same stack are merged when the profile is written
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeapProfile {
+    pub sample_interval: usize,
+    pub samples: Vec<HeapSample>,
+}
+
+impl HeapProfile {
+    /**
+     * Gets the estimated live bytes of the heap
+     */
+    pub fn estimated_bytes(&self) -> f64 {
+        self.samples.iter().map(|sample| sample.weight).sum()
+    }
+
+    /*
+     * Merges the samples with the same stack, the result is the estimated count and bytes of every stack
+     */
+    fn stacks(&self) -> Vec<(CompactBacktrace, f64, f64)> {
+        let mut stacks: Vec<(CompactBacktrace, f64, f64)> = Vec::new();
+        let mut indexes: HashMap<u64, usize> = HashMap::new();
+
+        for sample in &self.samples {
+            let index = *indexes.entry(sample.backtrace.hash()).or_insert_with(|| {
+                stacks.push((sample.backtrace, 0.0, 0.0));
+                stacks.len() - 1
+            });
+
+            stacks[index].1 += sample.count();
+            stacks[index].2 += sample.weight;
+        }
+
+        stacks
+    }
+
+    pub fn write(&self, writer: &mut impl Write, format: ProfileFormat) -> io::Result<()> {
+        match format {
+            ProfileFormat::Pprof => writer.write_all(&self.encode_pprof()),
+            ProfileFormat::Text => self.write_text(writer),
+        }
+    }
+
+    pub fn dump(&self, path: impl AsRef<Path>, format: ProfileFormat) -> io::Result<()> {
+        let mut file = File::create(path)?;
+
+        self.write(&mut file, format)?;
+        file.flush()
+    }
+
+    /*
+     * The text format haves a line per stack with the in use and the allocated objects and bytes, both are
+     * the live ones because freed samples are forgotten, the mapped libraries at the end let pprof
+     * symbolize the addresses
+     */
+    fn write_text(&self, writer: &mut impl Write) -> io::Result<()> {
+        let stacks = self.stacks();
+        let count: f64 = stacks.iter().map(|stack| stack.1).sum();
+        let bytes: f64 = stacks.iter().map(|stack| stack.2).sum();
+
+        writeln!(
+            writer,
+            "heap profile: {}: {} [{}: {}] @ heap_v2/{}",
+            count.round(),
+            bytes.round(),
+            count.round(),
+            bytes.round(),
+            self.sample_interval
+        )?;
+
+        for (backtrace, count, bytes) in stacks {
+            write!(
+                writer,
+                "{}: {} [{}: {}] @",
+                count.round(),
+                bytes.round(),
+                count.round(),
+                bytes.round()
+            )?;
+
+            for frame in backtrace.frames() {
+                write!(writer, " {frame:#x}")?;
+            }
+
+            writeln!(writer)?;
+        }
+
+        writeln!(writer, "\nMAPPED_LIBRARIES:")?;
+        writer.write_all(&std::fs::read("/proc/self/maps").unwrap_or_default())
+    }
+
+    /*
+     * Encodes the Profile message of profile.proto, every frame is a location with only its address and the
+     * mappings are the executable mappings of /proc/self/maps, so pprof symbolizes the addresses with the
+     * binaries
+     */
+    fn encode_pprof(&self) -> Vec<u8> {
+        let mut strings = StringTable::default();
+        let mut profile = ProtoWriter::default();
+        let mappings = executable_mappings();
+
+        for (kind, unit) in [("inuse_objects", "count"), ("inuse_space", "bytes")] {
+            let mut value_type = ProtoWriter::default();
+
+            value_type.int(1, strings.index(kind));
+            value_type.int(2, strings.index(unit));
+            profile.message(1, &value_type);
+        }
+
+        let mut locations: HashMap<usize, u64> = HashMap::new();
+
+        for (backtrace, count, bytes) in self.stacks() {
+            let location_ids: Vec<u64> = backtrace
+                .frames()
+                .iter()
+                .map(|frame| {
+                    let next_id = locations.len() as u64 + 1;
+
+                    *locations.entry(*frame).or_insert(next_id)
+                })
+                .collect();
+            let mut sample = ProtoWriter::default();
+
+            sample.packed(1, &location_ids);
+            sample.packed(2, &[count.round() as u64, bytes.round() as u64]);
+            profile.message(2, &sample);
+        }
+
+        for (index, (start, limit, offset, path)) in mappings.iter().enumerate() {
+            let mut mapping = ProtoWriter::default();
+
+            mapping.int(1, index as u64 + 1);
+            mapping.int(2, *start as u64);
+            mapping.int(3, *limit as u64);
+            mapping.int(4, *offset as u64);
+            mapping.int(5, strings.index(path));
+            profile.message(3, &mapping);
+        }
+
+        let mut locations: Vec<(usize, u64)> = locations.into_iter().collect();
+        locations.sort_by_key(|(_, id)| *id);
+
+        for (address, id) in locations {
+            let mut location = ProtoWriter::default();
+            let mapping_id = mappings
+                .iter()
+                .position(|(start, limit, _, _)| (*start..*limit).contains(&address))
+                .map_or(0, |index| index as u64 + 1);
+
+            location.int(1, id);
+            location.int(2, mapping_id);
+            location.int(3, address as u64);
+            profile.message(4, &location);
+        }
+
+        let mut period_type = ProtoWriter::default();
+
+        period_type.int(1, strings.index("space"));
+        period_type.int(2, strings.index("bytes"));
+
+        for string in &strings.strings {
+            profile.bytes(6, string.as_bytes());
+        }
+
+        profile.message(11, &period_type);
+        profile.int(12, self.sample_interval as u64);
+
+        profile.buffer
+    }
+}
+
+/*
+ * Writer of the protobuf wire format, only the field types used by profile.proto
+ */
+#[derive(Default)]
+struct ProtoWriter {
+    buffer: Vec<u8>,
+}
+
+impl ProtoWriter {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buffer.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+
+        self.buffer.push(value as u8);
+    }
+
+    fn int(&mut self, field: u32, value: u64) {
+        self.varint((field as u64) << 3);
+        self.varint(value);
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.varint(((field as u64) << 3) | 2);
+        self.varint(value.len() as u64);
+        self.buffer.extend_from_slice(value);
+    }
+
+    fn message(&mut self, field: u32, message: &ProtoWriter) {
+        self.bytes(field, &message.buffer);
+    }
+
+    fn packed(&mut self, field: u32, values: &[u64]) {
+        let mut packed = ProtoWriter::default();
+
+        for value in values {
+            packed.varint(*value);
+        }
+
+        self.bytes(field, &packed.buffer);
+    }
+}
+
+/*
+ * String table of a pprof profile, the first string must be empty
+ */
+struct StringTable {
+    strings: Vec<String>,
+    indexes: HashMap<String, u64>,
+}
+
+impl Default for StringTable {
+    fn default() -> Self {
+        Self {
+            strings: vec![String::new()],
+            indexes: HashMap::from([(String::new(), 0)]),
+        }
+    }
+}
+
+impl StringTable {
+    fn index(&mut self, string: &str) -> u64 {
+        if let Some(index) = self.indexes.get(string) {
+            return *index;
+        }
+
+        let index = self.strings.len() as u64;
+
+        self.strings.push(string.to_string());
+        self.indexes.insert(string.to_string(), index);
+
+        index
+    }
+}
+
+/*
+ * Gets the executable mappings of the process as start, limit, file offset and path
+ */
+fn executable_mappings() -> Vec<(usize, usize, usize, String)> {
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap_or_default();
+
+    maps.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, limit) = fields.next()?.split_once('-')?;
+            let permissions = fields.next()?;
+            let offset = fields.next()?;
+            let path = fields.nth(2).unwrap_or_default();
+
+            if !permissions.contains('x') {
+                return None;
+            }
+
+            Some((
+                usize::from_str_radix(start, 16).ok()?,
+                usize::from_str_radix(limit, 16).ok()?,
+                usize::from_str_radix(offset, 16).ok()?,
+                path.to_string(),
+            ))
+        })
+        .collect()
+}
+
+/*
+ * Sampled allocations by user pointer and the generator of the sampling intervals
+ */
+struct SamplerState {
+    random: u64,
+    samples: HashMap<usize, HeapSample>,
+}
+
+impl SamplerState {
+    /*
+     * Draws the bytes until the next sample from an exponential distribution, so the sampled bytes are a
+     * Poisson process over the allocated bytes
+     */
+    fn next_interval(&mut self, mean: usize) -> i64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+
+        let uniform = ((self.random >> 11) + 1) as f64 / (1u64 << 53) as f64;
+
+        (-uniform.ln() * mean as f64) as i64 + 1
+    }
+}
+
+/**
+ * Sampling heap profiler of a heap, like the ones of jemalloc and tcmalloc. An allocation is sampled when the
+ * bytes allocated since the last sample reach an interval that is drawn with mean sample_interval, so the
+ * chance of sampling an allocation grows with its size and the cost is paid by a few allocations
+ *
+ * A sampled allocation of size s with a mean interval N represents s / (1 - e^(-s/N)) bytes, so the profile
+ * estimates the live bytes of every stack without bias
+ *
+ * The samples are recorded while the heap lock is taken, or without it by the small allocator, so the samples
+ * haves their own lock, the allocations that aren't sampled only do an atomic subtraction
+ */
+pub struct HeapProfiler {
+    enabled: AtomicBool,
+    sample_interval: AtomicUsize,
+    bytes_until_sample: AtomicI64,
+    sample_count: AtomicUsize,
+    state: Mutex<Option<SamplerState>>,
+}
+
+impl Default for HeapProfiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapProfiler {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            sample_interval: AtomicUsize::new(SAMPLE_INTERVAL_DEFAULT),
+            bytes_until_sample: AtomicI64::new(0),
+            sample_count: AtomicUsize::new(0),
+            state: Mutex::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Enables or disables the sampling.
+     *
+     * @param sample_interval Mean bytes allocated between two samples.
+     *
+     * @note Disabling it forgets the samples.
+     */
+    pub fn set_enabled(&self, enabled: bool, sample_interval: usize) {
+        let sample_interval = sample_interval.max(1);
+        let mut state = self.state.lock().unwrap();
+
+        self.enabled.store(false, Ordering::Relaxed);
+        self.sample_interval
+            .store(sample_interval, Ordering::Relaxed);
+        self.sample_count.store(0, Ordering::Relaxed);
+
+        if !enabled {
+            *state = None;
+            return;
+        }
+
+        let state = state.insert(SamplerState {
+            random: SAMPLING_SEED,
+            samples: HashMap::new(),
+        });
+
+        self.bytes_until_sample
+            .store(state.next_interval(sample_interval), Ordering::Relaxed);
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /**
+     * Counts the bytes of an allocation and samples it when the interval is reached, only one of the threads
+     * that reach the same interval takes the sample
+     */
+    pub fn record_allocation(&self, usr_address: usize, size: usize) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let remaining = self
+            .bytes_until_sample
+            .fetch_sub(size as i64, Ordering::Relaxed)
+            - size as i64;
+
+        if remaining > 0 {
+            return;
+        }
+
+        let sample_interval = self.sample_interval.load(Ordering::Relaxed);
+        let mut state = self.state.lock().unwrap();
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+
+        /*
+         * Other thread that crossed the same interval can take the sample first, then the interval was
+         * restarted and this allocation isn't sampled
+         */
+        if self.bytes_until_sample.load(Ordering::Relaxed) > 0 {
+            return;
+        }
+
+        /*
+         * The next interval starts after this allocation, the bytes that passed the interval aren't carried
+         */
+        self.bytes_until_sample
+            .store(state.next_interval(sample_interval), Ordering::Relaxed);
+
+        let weight = size as f64 / (1.0 - (-(size as f64) / sample_interval as f64).exp());
+
+        state.samples.insert(
+            usr_address,
+            HeapSample {
+                backtrace: CompactBacktrace::capture(),
+                size,
+                weight,
+            },
+        );
+        self.sample_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /**
+     * Forgets the sample of a deallocation
+     */
+    pub fn record_free(&self, usr_address: usize) {
+        if self.sample_count.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        if let Some(state) = self.state.lock().unwrap().as_mut()
+            && state.samples.remove(&usr_address).is_some()
+        {
+            self.sample_count.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /**
+     * Forgets all the samples, it's used when the memory of the heap is given back
+     */
+    pub fn clear(&self) {
+        if let Some(state) = self.state.lock().unwrap().as_mut() {
+            state.samples.clear();
+        }
+
+        self.sample_count.store(0, Ordering::Relaxed);
+    }
+
+    /**
+     * Takes a copy of the live samples.
+     */
+    pub fn profile(&self) -> HeapProfile {
+        HeapProfile {
+            sample_interval: self.sample_interval.load(Ordering::Relaxed),
+            samples: self
+                .state
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|state| state.samples.values().copied().collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl ForkLocks for HeapProfiler {
+    fn lock_all(&'static self, guards: &mut ForkGuards) {
+        guards.push(lock_for_fork(&self.state));
+    }
+}
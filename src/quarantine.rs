@@ -0,0 +1,230 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+use crate::{
+    fork::{ForkGuards, ForkLocks, lock_for_fork},
+    walk::HeapEntry,
+};
+
+/*
+ * Byte written over the user data of the blocks that are in the quarantine
+ */
+pub const POISON_BYTE: u8 = 0xdf;
+
+/*
+ * Maximum bytes of user data kept in the quarantine when no size is given
+ */
+pub const QUARANTINE_DEFAULT_SIZE: usize = 256 * 1024;
+
+/**
+ * Report of a block that was written after it was deallocated, it's built when the block leaves the
+ * quarantine and its poison pattern isn't intact
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UseAfterFreeReport {
+    pub block: HeapEntry,
+    // Offset from the user pointer of the first byte that was changed
+    pub offset: usize,
+    pub value: u8,
+}
+
+impl fmt::Display for UseAfterFreeReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "write after free in block {:#x}: user pointer {:#x}, size {} bytes, byte at offset {} is {:#04x}",
+            self.block.address, self.block.usr_address, self.block.size, self.offset, self.value
+        )
+    }
+}
+
+/**
+ * Function that receives the write after free reports, it's an Arc because it's cloned out of its lock
+ * before being called, so it can use the heap or change the handler
+ */
+pub type UseAfterFreeHandler = Arc<dyn Fn(&UseAfterFreeReport) + Send + Sync>;
+
+/*
+ * A block in the quarantine, the address is the address of its header, only the blocks that were poisoned
+ * when they were pushed have their pattern checked
+ */
+#[derive(Clone, Copy)]
+pub struct QuarantinedBlock {
+    pub address: usize,
+    pub size: usize,
+    pub poisoned: bool,
+}
+
+#[derive(Default)]
+struct QuarantineQueue {
+    blocks: VecDeque<QuarantinedBlock>,
+    bytes: usize,
+}
+
+/**
+ * FIFO of freed blocks of a heap, a freed block is poisoned and kept in the quarantine, so it isn't reused
+ * until other blocks are freed after it. When the quarantine is bigger than its size, the oldest blocks leave
+ * it, their pattern is checked and they are deallocated for real
+ *
+ * The blocks are pushed and popped while the heap lock is taken
+ */
+pub struct Quarantine {
+    enabled: AtomicBool,
+    poisoning: AtomicBool,
+    max_bytes: AtomicUsize,
+    queue: Mutex<Option<QuarantineQueue>>,
+    handler: Mutex<Option<UseAfterFreeHandler>>,
+}
+
+impl Default for Quarantine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Quarantine {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            poisoning: AtomicBool::new(true),
+            max_bytes: AtomicUsize::new(QUARANTINE_DEFAULT_SIZE),
+            queue: Mutex::new(None),
+            handler: Mutex::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn is_poisoning_enabled(&self) -> bool {
+        self.poisoning.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Enables or disables poisoning the blocks pushed into the quarantine, without poisoning the quarantine
+     * only delays the reuse of the blocks and writes after free aren't detected.
+     *
+     * @note The blocks that are already in the quarantine keep being checked if they were poisoned.
+     */
+    pub fn set_poisoning(&self, enabled: bool) {
+        self.poisoning.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Enables or disables the quarantine.
+     *
+     * @param max_bytes Bytes of user data that can be kept in the quarantine.
+     *
+     * @note Disabling the quarantine doesn't release the blocks that are in it, they leave it with the next
+     * deallocation or with a flush.
+     */
+    pub fn set_enabled(&self, enabled: bool, max_bytes: usize) {
+        self.max_bytes.store(max_bytes, Ordering::Relaxed);
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /**
+     * Sets the function that receives the write after free reports, None prints them to stderr
+     */
+    pub fn set_handler(&self, handler: Option<UseAfterFreeHandler>) {
+        *self.handler.lock().unwrap() = handler;
+    }
+
+    /**
+     * Poisons the user data of a block if poisoning is enabled and puts it at the end of the quarantine
+     */
+    pub fn push(&self, address: usize, usr_address: usize, size: usize) {
+        let poisoned = self.is_poisoning_enabled();
+
+        if poisoned {
+            unsafe { (usr_address as *mut u8).write_bytes(POISON_BYTE, size) };
+        }
+
+        let mut queue = self.queue.lock().unwrap();
+        let queue = queue.get_or_insert_default();
+
+        queue.blocks.push_back(QuarantinedBlock {
+            address,
+            size,
+            poisoned,
+        });
+        queue.bytes += size;
+    }
+
+    /**
+     * Takes the oldest block if the quarantine is bigger than its size, or if it's disabled
+     *
+     * @param flush Takes the oldest block even if the quarantine isn't full.
+     */
+    pub fn pop(&self, flush: bool) -> Option<QuarantinedBlock> {
+        let drain = flush || !self.is_enabled();
+        let mut queue = self.queue.lock().unwrap();
+        let queue = queue.as_mut()?;
+
+        if !drain && queue.bytes <= self.max_bytes.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let block = queue.blocks.pop_front()?;
+        queue.bytes -= block.size;
+
+        Some(block)
+    }
+
+    /**
+     * Forgets all the blocks of the quarantine, it's used when the memory of the heap is given back
+     */
+    pub fn clear(&self) {
+        *self.queue.lock().unwrap() = None;
+    }
+
+    /**
+     * Checks that the user data of a block still haves the poison pattern
+     *
+     * @return The offset and the value of the first byte that was changed.
+     */
+    pub fn find_poison_change(usr_address: usize, size: usize) -> Option<(usize, u8)> {
+        let data = unsafe { std::slice::from_raw_parts(usr_address as *const u8, size) };
+
+        data.iter()
+            .position(|byte| *byte != POISON_BYTE)
+            .map(|offset| (offset, data[offset]))
+    }
+
+    /**
+     * Gives the write after free reports to the handler, it must be called after releasing the heap lock,
+     * so the handler can use the heap
+     */
+    pub fn report(&self, reports: Vec<UseAfterFreeReport>) {
+        if reports.is_empty() {
+            return;
+        }
+
+        let handler = self.handler.lock().unwrap().clone();
+
+        for report in reports {
+            match handler.as_ref() {
+                Some(handler) => handler(&report),
+                None => eprintln!("{report}"),
+            }
+        }
+    }
+}
+
+impl ForkLocks for Quarantine {
+    fn lock_all(&'static self, guards: &mut ForkGuards) {
+        guards.push(lock_for_fork(&self.handler));
+        guards.push(lock_for_fork(&self.queue));
+    }
+}
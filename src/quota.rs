@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use crate::error::AllocError;
+
+/*
+ * Limit stored when the heap doesn't have a quota
+ */
+const NO_LIMIT: usize = usize::MAX;
+
+/**
+ * Bytes that are counted by the quota of a heap
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum QuotaKind {
+    // Bytes asked by the user that aren't deallocated, headers and free blocks aren't counted
+    #[default]
+    UserBytes,
+    // Bytes that the heap took from its memory source, it's the heap size of the stats
+    OsBytes,
+}
+
+/**
+ * Byte budget of a heap, an allocation that would take the counted bytes over the limit fails with
+ * AllocError::QuotaExceeded, the limit and its kind can be changed at any time
+ *
+ * @note Lowering the limit under the bytes that are already counted doesn't free anything, only the next
+ * allocations fail until the usage goes under the limit.
+ */
+pub struct Quota {
+    limit: AtomicUsize,
+    kind: AtomicU8,
+    // The user bytes are always counted, so the kind can be changed without losing the usage
+    user_bytes: AtomicUsize,
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Quota {
+    pub const fn new() -> Self {
+        Self {
+            limit: AtomicUsize::new(NO_LIMIT),
+            kind: AtomicU8::new(QuotaKind::UserBytes as u8),
+            user_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /**
+     * Gets the limit in bytes, or None if the heap doesn't have a quota
+     */
+    pub fn limit(&self) -> Option<usize> {
+        match self.limit.load(Ordering::Relaxed) {
+            NO_LIMIT => None,
+            limit => Some(limit),
+        }
+    }
+
+    pub fn kind(&self) -> QuotaKind {
+        match self.kind.load(Ordering::Relaxed) {
+            0 => QuotaKind::UserBytes,
+            _ => QuotaKind::OsBytes,
+        }
+    }
+
+    /**
+     * Sets the quota of the heap.
+     *
+     * @param limit The maximum number of bytes, None removes the quota.
+     * @param kind The bytes that are counted.
+     */
+    pub fn set_limit(&self, limit: Option<usize>, kind: QuotaKind) {
+        self.kind.store(kind as u8, Ordering::Relaxed);
+        self.limit
+            .store(limit.unwrap_or(NO_LIMIT), Ordering::Relaxed);
+    }
+
+    /**
+     * Gets the bytes asked by the user that aren't deallocated
+     */
+    pub fn user_bytes(&self) -> usize {
+        self.user_bytes.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Gets the bytes that are counted by the quota.
+     *
+     * @param heap_size The bytes that the heap took from its memory source.
+     */
+    pub fn used(&self, heap_size: usize) -> usize {
+        match self.kind() {
+            QuotaKind::UserBytes => self.user_bytes(),
+            QuotaKind::OsBytes => heap_size,
+        }
+    }
+
+    /**
+     * Checks that the user bytes of a new allocation fit in the quota, it must be called with the heap lock
+     * taken, so no other allocation is counted between the check and record_allocation
+     */
+    pub fn check_user_bytes(&self, requested: usize) -> Result<(), AllocError> {
+        if self.kind() != QuotaKind::UserBytes {
+            return Ok(());
+        }
+
+        self.check(self.user_bytes(), requested)
+    }
+
+    /**
+     * Checks that taking more memory from the memory source fits in the quota, it must be called with the
+     * heap lock taken before growing the heap.
+     *
+     * @param heap_size The bytes that the heap took from its memory source.
+     * @param growth The bytes that are going to be taken.
+     */
+    pub fn check_os_bytes(&self, heap_size: usize, growth: usize) -> Result<(), AllocError> {
+        if self.kind() != QuotaKind::OsBytes {
+            return Ok(());
+        }
+
+        self.check(heap_size, growth)
+    }
+
+    fn check(&self, used: usize, requested: usize) -> Result<(), AllocError> {
+        match self.limit() {
+            Some(limit) if used.saturating_add(requested) > limit => {
+                Err(AllocError::QuotaExceeded { limit, requested })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_allocation(&self, usr_size: usize) {
+        self.user_bytes.fetch_add(usr_size, Ordering::Relaxed);
+    }
+
+    pub fn record_free(&self, usr_size: usize) {
+        self.user_bytes.fetch_sub(usr_size, Ordering::Relaxed);
+    }
+
+    /**
+     * Forgets the user bytes, it's used when the heap is torn down
+     */
+    pub fn clear(&self) {
+        self.user_bytes.store(0, Ordering::Relaxed);
+    }
+}
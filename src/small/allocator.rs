@@ -0,0 +1,221 @@
+use crate::{
+    free_check::InvalidFreeKind,
+    tags::UNTAGGED,
+    walk::{HeapEntry, HeapEntryKind},
+};
+
+use super::{
+    SMALL_SIZE_CLASSES, SMALL_SLAB_SIZE, SmallStats,
+    utils::{
+        carve_slab, count_free_objects, find_object_class, get_arena, get_offset_class,
+        get_size_class, get_slabs_used, is_object_free, is_small_pointer, mark_object_free,
+        pop_free_object, push_free_objects,
+    },
+};
+
+/**
+ * Lock-free allocator of small objects, it's only used by MmapAllocator for the allocations up to
+ * SmallMaxSize, BumpAllocator and the heaps created with BumpHeap::new or MmapHeap::new never use it
+ *
+ * @note Calling it directly gives objects that aren't counted in the stats, the quota or the tags of any
+ * heap, they must be freed with SmallAllocator::deallocate or MmapAllocator::deallocate.
+ */
+pub struct SmallAllocator {}
+
+impl SmallAllocator {
+    /**
+     * Allocate a small object without taking any lock.
+     *
+     * @param size The size of the memory to allocate.
+     * @return A pointer to the allocated memory.
+     *
+     * @note This function is thread-safe and lock-free.
+     * @warning This function returns None if the size is bigger than SMALL_MAX_SIZE or the arena is full,
+     * in that case the caller must use the locked allocators
+     */
+    pub fn allocate<T>(size: usize) -> Option<*mut T> {
+        Self::allocate_with(size, || {})
+    }
+
+    /**
+     * Allocate a small object like allocate, and runs on_new_slab when a slab is taken from the arena for
+     * serving it, so the caller can count the memory of the slab.
+     */
+    pub fn allocate_with<T>(size: usize, on_new_slab: impl FnOnce()) -> Option<*mut T> {
+        /*
+         * Objects are aligned to their class size, so taking the alignment as minimum size is
+         * enough for storing any type
+         */
+        let class = get_size_class(size.max(align_of::<T>()))?;
+        let arena = get_arena()?;
+
+        let offset = match pop_free_object(arena, class) {
+            Some(offset) => offset,
+            None => {
+                let offset = carve_slab(arena, class)?;
+
+                on_new_slab();
+                offset
+            }
+        };
+
+        unsafe { Some(arena.add(offset as usize) as *mut T) }
+    }
+
+    /**
+     * Gets the size of the class that serves an allocation, it's the usable size of the object.
+     *
+     * @return The size or None if the size is bigger than SMALL_MAX_SIZE.
+     */
+    pub fn class_size<T>(size: usize) -> Option<usize> {
+        get_size_class(size.max(align_of::<T>())).map(|class| SMALL_SIZE_CLASSES[class])
+    }
+
+    /**
+     * Deallocate a small object without taking any lock.
+     *
+     * @param usr_data The pointer to the memory to deallocate.
+     * @return True if the pointer belongs to the small allocator and was deallocated, false if it doesn't
+     * belong to the small allocator, or the reason why it can't be deallocated.
+     *
+     * @note This function is thread-safe and lock-free.
+     * @note A pointer that isn't the start of an object or an object that is already free isn't pushed into
+     * the free list, so the free list is never corrupted by an invalid free.
+     */
+    pub fn deallocate<T>(usr_data: *const T) -> Result<bool, InvalidFreeKind> {
+        Self::deallocate_with(usr_data, |_| {})
+    }
+
+    /**
+     * Deallocate a small object like deallocate, and runs on_free with the size of its class after the
+     * object is marked as free and before it's pushed into the free list.
+     *
+     * The object can't be allocated again by other thread until on_free returns, so the state kept by the
+     * address of the object (samples, suppressions, backtraces...) is forgotten before the address is reused.
+     */
+    pub fn deallocate_with<T>(
+        usr_data: *const T,
+        on_free: impl FnOnce(usize),
+    ) -> Result<bool, InvalidFreeKind> {
+        if !is_small_pointer(usr_data as *const u8) {
+            return Ok(false);
+        }
+
+        /*
+         * is_small_pointer already checked that the arena is reserved
+         */
+        let Some(arena) = get_arena() else {
+            return Ok(false);
+        };
+
+        let offset = (usr_data as usize - arena as usize) as u32;
+        let class = find_object_class(offset)?;
+
+        if !mark_object_free(offset, class) {
+            return Err(InvalidFreeKind::DoubleFree);
+        }
+
+        on_free(SMALL_SIZE_CLASSES[class]);
+        push_free_objects(arena, class, offset, offset);
+
+        Ok(true)
+    }
+
+    /**
+     * Describes the object that contains a pointer of the arena, like the entries of a heap walk, it's used
+     * by the invalid free reports.
+     *
+     * @return The entry or None if the pointer isn't inside an object of a slab taken from the arena.
+     */
+    pub fn object_entry<T>(usr_data: *const T) -> Option<HeapEntry> {
+        if !is_small_pointer(usr_data as *const u8) {
+            return None;
+        }
+
+        let arena = get_arena()?;
+        let offset = (usr_data as usize - arena as usize) as u32;
+        let class = get_offset_class(offset)?;
+        let class_size = SMALL_SIZE_CLASSES[class];
+        let object_offset = offset as usize / class_size * class_size;
+
+        Some(Self::entry_at(arena, object_offset, class))
+    }
+
+    /**
+     * Describes the objects of the slabs that aren't free, like the entries of a heap walk, it's used by
+     * the leak checker.
+     *
+     * @note Objects allocated or deallocated by other threads while walking the slabs can be missed.
+     */
+    pub fn live_objects() -> Vec<HeapEntry> {
+        let Some(arena) = get_arena() else {
+            return Vec::new();
+        };
+
+        let mut entries = Vec::new();
+
+        for slab in 0..get_slabs_used() {
+            let slab_offset = slab * SMALL_SLAB_SIZE;
+            let Some(class) = get_offset_class(slab_offset as u32) else {
+                continue;
+            };
+
+            let class_size = SMALL_SIZE_CLASSES[class];
+
+            entries.extend(
+                (slab_offset..slab_offset + SMALL_SLAB_SIZE)
+                    .step_by(class_size)
+                    .filter(|object_offset| !is_object_free(*object_offset as u32, class))
+                    .map(|object_offset| Self::entry_at(arena, object_offset, class)),
+            );
+        }
+
+        entries
+    }
+
+    fn entry_at(arena: *mut u8, object_offset: usize, class: usize) -> HeapEntry {
+        let address = arena as usize + object_offset;
+
+        HeapEntry {
+            kind: HeapEntryKind::Section,
+            address,
+            usr_address: address,
+            size: SMALL_SIZE_CLASSES[class],
+            is_free: is_object_free(object_offset as u32, class),
+            region: None,
+            header_size: 0,
+            tag: UNTAGGED,
+        }
+    }
+
+    /**
+     * Counts the slabs and the objects of the small allocator.
+     *
+     * @note This function is lock-free, the objects allocated or deallocated while it runs can be counted
+     * or not.
+     */
+    pub fn stats() -> SmallStats {
+        let mut stats = SmallStats::default();
+
+        for slab in 0..get_slabs_used() {
+            /*
+             * A slab whose class isn't stored yet is being carved, it doesn't have objects yet
+             */
+            let Some(class) = get_offset_class((slab * SMALL_SLAB_SIZE) as u32) else {
+                continue;
+            };
+
+            let class_size = SMALL_SIZE_CLASSES[class];
+            let free_objects = count_free_objects(slab, class);
+            let live_objects = SMALL_SLAB_SIZE / class_size - free_objects;
+
+            stats.slabs += 1;
+            stats.live_objects += live_objects;
+            stats.free_objects += free_objects;
+            stats.live_bytes += live_objects * class_size;
+            stats.free_bytes += free_objects * class_size;
+        }
+
+        stats
+    }
+}
@@ -0,0 +1,29 @@
+#![allow(non_upper_case_globals)]
+
+use std::sync::atomic::{AtomicPtr, AtomicU8, AtomicU64, AtomicUsize};
+
+use super::{
+    SMALL_BITMAP_WORDS, SMALL_SIZE_CLASSES, SMALL_SLAB_COUNT, SMALL_SLAB_UNUSED, SmallFreeList,
+};
+
+/*
+ * These globals don't use lazy_static because its initialization takes a lock, all of them can be
+ * initialized at compile time so the small allocator never waits for other threads
+ */
+
+// Start of the arena, it's null until the first small allocation
+pub static small_arena: AtomicPtr<u8> = AtomicPtr::new(std::ptr::null_mut());
+
+// Number of slabs already taken from the arena
+pub static small_slabs_used: AtomicUsize = AtomicUsize::new(0);
+
+// Size class index of every slab of the arena, SMALL_SLAB_UNUSED until the slab is taken
+pub static small_slab_classes: [AtomicU8; SMALL_SLAB_COUNT] =
+    [const { AtomicU8::new(SMALL_SLAB_UNUSED) }; SMALL_SLAB_COUNT];
+
+// Free bitmap of every slab of the arena, the bit of an object is set while it's free
+pub static small_free_bitmaps: [[AtomicU64; SMALL_BITMAP_WORDS]; SMALL_SLAB_COUNT] =
+    [const { [const { AtomicU64::new(0) }; SMALL_BITMAP_WORDS] }; SMALL_SLAB_COUNT];
+
+pub static small_free_lists: [SmallFreeList; SMALL_SIZE_CLASSES.len()] =
+    [const { SmallFreeList::new() }; SMALL_SIZE_CLASSES.len()];
@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicU32, AtomicU64};
+
+pub mod allocator;
+pub mod globals;
+pub mod utils;
+
+/*
+ * Sizes that are served by the small allocator, bigger sizes must use the locked allocators
+ */
+pub const SMALL_SIZE_CLASSES: [usize; 5] = [16, 32, 64, 128, 256];
+
+pub const SMALL_MAX_SIZE: usize = SMALL_SIZE_CLASSES[SMALL_SIZE_CLASSES.len() - 1];
+
+pub const SMALL_SLAB_SIZE: usize = 64 * 1024;
+
+pub const SMALL_ARENA_SIZE: usize = 1024 * 1024 * 1024;
+
+pub const SMALL_SLAB_COUNT: usize = SMALL_ARENA_SIZE / SMALL_SLAB_SIZE;
+
+/*
+ * Words of the free bitmap of a slab, a slab of the smallest class haves the most objects
+ */
+pub const SMALL_BITMAP_WORDS: usize = SMALL_SLAB_SIZE / SMALL_SIZE_CLASSES[0] / u64::BITS as usize;
+
+/*
+ * Class stored for the slabs that aren't taken from the arena yet
+ */
+pub const SMALL_SLAB_UNUSED: u8 = u8::MAX;
+
+/*
+ * Offset used as null pointer in the free lists, offsets are relative to the start of the arena
+ */
+pub const SMALL_NULL_OFFSET: u32 = u32::MAX;
+
+/**
+ * Small allocator is a lock-free path for allocations of small fixed sizes, the other allocators take a mutex
+ * for every operation, so when many threads are allocating small objects they are waiting for the lock most
+ * of the time
+ *
+ * All the memory for small objects comes from an arena, a big range of virtual memory reserved with mmap at
+ * the first use, the arena is split into slabs of the same size and every slab only stores objects of one
+ * size class
+ *
+ * __________________________________________________________________
+ * |   slab 0 (16 bytes)   |   slab 1 (64 bytes)   |   slab 2 ...   |
+ * __________________________________________________________________
+ *
+ * Every size class haves a free list, the free list is a Treiber stack, this is a linked list where push and
+ * pop are done with a compare and swap over the head, so threads never wait for other threads
+ *
+ * Free objects don't have a header, the link to the next free object is stored in the first bytes of the
+ * object itself, and the class of an object is found by the slab where it lives, so freeing only needs
+ * the pointer
+ *
+ * Treiber stacks have the ABA problem: a thread reads head A and its next B, other threads pop A, pop B
+ * and push A again, then the first thread compare and swap succeeds because head is A again, but B isn't
+ * free anymore. For avoiding it, the head stores a tag that is increased on every push and pop, so the
+ * compare and swap fails if the stack changed even if the head object is the same
+ *
+ * Slabs are never given back to the OS, that is what allows reading the next link of an object that other
+ * thread is popping at the same time, the memory is always mapped
+ *
+ * Every slab haves a bitmap with a bit per object that is set while the object is free, it's changed with
+ * atomic operations on every pop and push, so a deallocation of a pointer that isn't at the start of an object
+ * or of an object that is already free is rejected instead of corrupting the free list
+ *
+ * This is the head of a free list, it stores the offset of the first free object in the lower 32 bits and
+ * the ABA tag in the higher 32 bits
+ */
+pub struct SmallFreeList {
+    pub head: AtomicU64,
+}
+
+impl SmallFreeList {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicU64::new(SMALL_NULL_OFFSET as u64),
+        }
+    }
+}
+
+impl Default for SmallFreeList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * The structure stored in the first bytes of a free object
+ */
+pub struct SmallFreeNode {
+    pub next: AtomicU32,
+}
+
+/**
+ * Memory of the small allocator, the slabs are never given back, so all of them are counted
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SmallStats {
+    pub slabs: usize,
+    pub live_objects: usize,
+    pub free_objects: usize,
+    // Bytes of the live objects, counted with the size of their class
+    pub live_bytes: usize,
+    pub free_bytes: usize,
+}
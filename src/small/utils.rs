@@ -0,0 +1,304 @@
+use libc::{
+    MAP_ANONYMOUS, MAP_FAILED, MAP_NORESERVE, MAP_PRIVATE, PROT_READ, PROT_WRITE, mmap, munmap,
+};
+use std::{
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::free_check::InvalidFreeKind;
+
+use super::{
+    SMALL_ARENA_SIZE, SMALL_NULL_OFFSET, SMALL_SIZE_CLASSES, SMALL_SLAB_COUNT, SMALL_SLAB_SIZE,
+    SMALL_SLAB_UNUSED, SmallFreeNode,
+    globals::{
+        small_arena, small_free_bitmaps, small_free_lists, small_slab_classes, small_slabs_used,
+    },
+};
+
+/**
+ * Gets the index of the smallest size class that can store the given size
+ *
+ * @return The size class index or None if the size is too big for the small allocator
+ */
+pub fn get_size_class(size: usize) -> Option<usize> {
+    SMALL_SIZE_CLASSES
+        .iter()
+        .position(|class_size| size <= *class_size)
+}
+
+/**
+ * Joins an offset and a tag into a free list head
+ */
+pub fn pack_head(offset: u32, tag: u32) -> u64 {
+    ((tag as u64) << 32) | offset as u64
+}
+
+/**
+ * Splits a free list head into its offset and its tag
+ */
+pub fn unpack_head(head: u64) -> (u32, u32) {
+    (head as u32, (head >> 32) as u32)
+}
+
+/**
+ * Gets the start of the arena, if the arena isn't reserved yet then it's reserved with mmap
+ *
+ * The arena is reserved with MAP_NORESERVE, so the OS only gives physical memory to the pages that are used.
+ * If two threads reserve the arena at the same time, only one of them is stored and the other one is unmapped
+ *
+ * @return The start of the arena or None if mmap fails
+ */
+pub fn get_arena() -> Option<*mut u8> {
+    let arena = small_arena.load(Ordering::Acquire);
+
+    if !arena.is_null() {
+        return Some(arena);
+    }
+
+    let new_arena = unsafe {
+        mmap(
+            ptr::null_mut(),
+            SMALL_ARENA_SIZE,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE,
+            -1,
+            0,
+        )
+    };
+
+    if new_arena == MAP_FAILED {
+        return None;
+    }
+
+    match small_arena.compare_exchange(
+        ptr::null_mut(),
+        new_arena as *mut u8,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    ) {
+        Ok(_) => Some(new_arena as *mut u8),
+        Err(stored_arena) => {
+            unsafe { munmap(new_arena, SMALL_ARENA_SIZE) };
+            Some(stored_arena)
+        }
+    }
+}
+
+/**
+ * Checks if a pointer was given by the small allocator
+ */
+pub fn is_small_pointer(ptr: *const u8) -> bool {
+    let arena = small_arena.load(Ordering::Acquire);
+
+    !arena.is_null()
+        && ptr as usize >= arena as usize
+        && (ptr as usize) < arena as usize + SMALL_ARENA_SIZE
+}
+
+/**
+ * Gets the free node stored at the given offset of the arena
+ */
+fn get_node(arena: *mut u8, offset: u32) -> *const SmallFreeNode {
+    unsafe { arena.add(offset as usize) as *const SmallFreeNode }
+}
+
+/**
+ * Pops a free object from the free list of a size class
+ *
+ * The next link is read before the compare and swap, at that moment other thread could pop the same object
+ * and write user data over the link, but in that case the tag of the head changed and the compare and swap fails
+ *
+ * @return The offset of the object or None if the free list is empty
+ */
+pub fn pop_free_object(arena: *mut u8, class: usize) -> Option<u32> {
+    let free_list = &small_free_lists[class].head;
+    let mut head = free_list.load(Ordering::Acquire);
+
+    loop {
+        let (offset, tag) = unpack_head(head);
+
+        if offset == SMALL_NULL_OFFSET {
+            return None;
+        }
+
+        let next = unsafe { (*get_node(arena, offset)).next.load(Ordering::Relaxed) };
+
+        match free_list.compare_exchange_weak(
+            head,
+            pack_head(next, tag.wrapping_add(1)),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => {
+                let (word, mask) = get_object_bit(offset, class);
+                word.fetch_and(!mask, Ordering::AcqRel);
+
+                return Some(offset);
+            }
+            Err(current_head) => head = current_head,
+        }
+    }
+}
+
+/**
+ * Pushes a chain of free objects into the free list of a size class, the objects from first to last
+ * must be already linked between them
+ */
+pub fn push_free_objects(arena: *mut u8, class: usize, first: u32, last: u32) {
+    let free_list: &AtomicU64 = &small_free_lists[class].head;
+    let mut head = free_list.load(Ordering::Acquire);
+
+    loop {
+        let (offset, tag) = unpack_head(head);
+
+        unsafe {
+            (*get_node(arena, last))
+                .next
+                .store(offset, Ordering::Relaxed)
+        };
+
+        match free_list.compare_exchange_weak(
+            head,
+            pack_head(first, tag.wrapping_add(1)),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => return,
+            Err(current_head) => head = current_head,
+        }
+    }
+}
+
+/**
+ * Takes a new slab from the arena for a size class, the first object of the slab is returned to the caller
+ * and the others are pushed into the free list of the class
+ *
+ * @return The offset of the first object of the slab or None if the arena is full
+ */
+pub fn carve_slab(arena: *mut u8, class: usize) -> Option<u32> {
+    let slab = small_slabs_used.fetch_add(1, Ordering::Relaxed);
+
+    if slab >= SMALL_SLAB_COUNT {
+        small_slabs_used.fetch_sub(1, Ordering::Relaxed);
+        return None;
+    }
+
+    let class_size = SMALL_SIZE_CLASSES[class];
+    let slab_offset = slab * SMALL_SLAB_SIZE;
+    let objects = SMALL_SLAB_SIZE / class_size;
+
+    /*
+     * All the objects except the first are free, the bitmap is filled before the class is stored, so
+     * a slab with a class always haves its bitmap
+     */
+    for object in 1..objects {
+        let (word, mask) = get_object_bit((slab_offset + object * class_size) as u32, class);
+        word.fetch_or(mask, Ordering::Relaxed);
+    }
+
+    small_slab_classes[slab].store(class as u8, Ordering::Release);
+
+    /*
+     * Links all the objects of the slab except the first, the last link is written by push_free_objects
+     */
+    for object in 1..objects - 1 {
+        let offset = slab_offset + object * class_size;
+
+        unsafe {
+            (*get_node(arena, offset as u32))
+                .next
+                .store((offset + class_size) as u32, Ordering::Relaxed);
+        }
+    }
+
+    push_free_objects(
+        arena,
+        class,
+        (slab_offset + class_size) as u32,
+        (slab_offset + (objects - 1) * class_size) as u32,
+    );
+
+    Some(slab_offset as u32)
+}
+
+/**
+ * Gets the size class of the slab that contains the given offset
+ *
+ * @return The size class index or None if the slab isn't taken from the arena yet
+ */
+pub fn get_offset_class(offset: u32) -> Option<usize> {
+    match small_slab_classes[offset as usize / SMALL_SLAB_SIZE].load(Ordering::Acquire) {
+        SMALL_SLAB_UNUSED => None,
+        class => Some(class as usize),
+    }
+}
+
+/**
+ * Gets the word of the free bitmap and the mask of the bit of an object
+ */
+fn get_object_bit(offset: u32, class: usize) -> (&'static AtomicU64, u64) {
+    let offset = offset as usize;
+    let object = offset % SMALL_SLAB_SIZE / SMALL_SIZE_CLASSES[class];
+
+    (
+        &small_free_bitmaps[offset / SMALL_SLAB_SIZE][object / u64::BITS as usize],
+        1 << (object % u64::BITS as usize),
+    )
+}
+
+/**
+ * Checks that an offset is the start of an object of a slab taken from the arena
+ *
+ * @return The size class of the object or the reason why it can't be an object
+ */
+pub fn find_object_class(offset: u32) -> Result<usize, InvalidFreeKind> {
+    let class = get_offset_class(offset).ok_or(InvalidFreeKind::ForeignPointer)?;
+
+    if !(offset as usize % SMALL_SLAB_SIZE).is_multiple_of(SMALL_SIZE_CLASSES[class]) {
+        return Err(InvalidFreeKind::InteriorPointer);
+    }
+
+    Ok(class)
+}
+
+/**
+ * Sets the bit of an object, the bit is set with an atomic operation, so only one of two threads that
+ * free the same object at the same time sees it clear
+ *
+ * @return False if the object was already free
+ */
+pub fn mark_object_free(offset: u32, class: usize) -> bool {
+    let (word, mask) = get_object_bit(offset, class);
+
+    word.fetch_or(mask, Ordering::AcqRel) & mask == 0
+}
+
+/**
+ * Checks if an object of a slab taken from the arena is free
+ */
+pub fn is_object_free(offset: u32, class: usize) -> bool {
+    let (word, mask) = get_object_bit(offset, class);
+
+    word.load(Ordering::Acquire) & mask != 0
+}
+
+/**
+ * Counts the free objects of a slab with a class, objects carved after the count started are ignored
+ */
+pub fn count_free_objects(slab: usize, class: usize) -> usize {
+    let objects = SMALL_SLAB_SIZE / SMALL_SIZE_CLASSES[class];
+
+    small_free_bitmaps[slab]
+        .iter()
+        .take(objects.div_ceil(u64::BITS as usize))
+        .map(|word| word.load(Ordering::Acquire).count_ones() as usize)
+        .sum()
+}
+
+/**
+ * Gets the number of slabs taken from the arena, a slab can be taken but its class not stored yet
+ */
+pub fn get_slabs_used() -> usize {
+    small_slabs_used.load(Ordering::Acquire).min(SMALL_SLAB_COUNT)
+}
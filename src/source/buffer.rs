@@ -0,0 +1,78 @@
+use crate::error::{AllocError, OsCall};
+
+use super::MemorySource;
+
+/*
+ * Page size used by buffers, buffers are not managed by the OS so any size can be used
+ */
+pub const BUFFER_PAGE_SIZE: usize = 4096;
+
+/*
+ * Alignment of the start of the buffer, it's enough for the headers of the allocators
+ */
+const BUFFER_ALIGNMENT: usize = 16;
+
+/**
+ * Memory source over a buffer given by the caller, the buffer works like a private program break, so
+ * the allocators never call the OS
+ *
+ * This source is useful for running the allocators over preallocated memory, in unit tests or in Miri
+ */
+pub struct BufferSource {
+    start: *mut u8,
+    end: *mut u8,
+    current_break: *mut u8,
+}
+
+/*
+ * The buffer is owned by the source during the whole program, so it can be moved to other threads
+ */
+unsafe impl Send for BufferSource {}
+
+impl BufferSource {
+    pub fn new(buffer: &'static mut [u8]) -> Self {
+        let range = buffer.as_mut_ptr_range();
+        let padding = range.start.align_offset(BUFFER_ALIGNMENT).min(buffer.len());
+        let start = unsafe { range.start.add(padding) };
+
+        Self {
+            start,
+            end: range.end,
+            current_break: start,
+        }
+    }
+
+    /**
+     * Gets the number of bytes that are inside the break
+     */
+    pub fn used(&self) -> usize {
+        self.current_break as usize - self.start as usize
+    }
+}
+
+impl MemorySource for BufferSource {
+    fn grow(&mut self, increment: usize) -> Result<*mut u8, AllocError> {
+        if increment > self.end as usize - self.current_break as usize {
+            return Err(AllocError::out_of_memory(OsCall::Sbrk));
+        }
+
+        let old_break = self.current_break;
+        self.current_break = unsafe { self.current_break.add(increment) };
+
+        Ok(old_break)
+    }
+
+    fn shrink(&mut self, decrement: usize) -> Result<(), AllocError> {
+        if decrement > self.used() {
+            return Err(AllocError::invalid_argument(OsCall::Sbrk));
+        }
+
+        self.current_break = unsafe { self.current_break.sub(decrement) };
+
+        Ok(())
+    }
+
+    fn page_size(&self) -> usize {
+        BUFFER_PAGE_SIZE
+    }
+}
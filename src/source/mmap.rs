@@ -0,0 +1,47 @@
+use libc::{MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, PROT_READ, PROT_WRITE, mmap, munmap};
+use std::ptr;
+
+use crate::{
+    error::{AllocError, OsCall},
+    mmap::utils::get_page_size,
+};
+
+use super::MemorySource;
+
+/**
+ * Memory source that asks the OS for anonymous mappings with mmap, it doesn't have a break
+ */
+pub struct MmapSource {}
+
+impl MemorySource for MmapSource {
+    fn map(&mut self, size: usize) -> Result<*mut u8, AllocError> {
+        let addr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                size,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+
+        if addr == MAP_FAILED {
+            return Err(AllocError::last_os_error(OsCall::Mmap));
+        }
+
+        Ok(addr as *mut u8)
+    }
+
+    fn unmap(&mut self, addr: *mut u8, size: usize) -> Result<(), AllocError> {
+        if unsafe { munmap(addr as *mut _, size) } != 0 {
+            return Err(AllocError::last_os_error(OsCall::Munmap));
+        }
+
+        Ok(())
+    }
+
+    fn page_size(&self) -> usize {
+        get_page_size()
+    }
+}
@@ -0,0 +1,81 @@
+use crate::error::{AllocError, OsCall};
+
+pub mod buffer;
+pub mod mmap;
+pub mod sbrk;
+pub mod simulated;
+pub mod system;
+pub mod virtual_break;
+
+/**
+ * A memory source is the place where the allocators take memory from, the allocators don't call the OS
+ * directly, so the same allocator logic can run over the real OS memory, over a buffer given by the caller
+ * or over any other source of memory
+ *
+ * There are two ways of giving memory:
+ *
+ * - Break: like sbrk, the memory is a contiguous range that grows or shrinks from its end, so the only
+ *   memory that can be given back is the memory at the end
+ * - Mappings: like mmap, every request gives an independent block of memory that can be given back
+ *   at any moment
+ *
+ * The bump allocator uses the break and the mmap allocator uses mappings. A source doesn't need to support
+ * both ways, by default mappings are taken from the break and a mapping can only be given back if it's at the
+ * end of the break
+ */
+pub trait MemorySource {
+    /**
+     * Increases the break of the source.
+     *
+     * @param increment The number of bytes to add to the break.
+     * @return The old break, that is the start of the new memory, or the reason why the source can't grow.
+     */
+    fn grow(&mut self, _increment: usize) -> Result<*mut u8, AllocError> {
+        Err(AllocError::out_of_memory(OsCall::Sbrk))
+    }
+
+    /**
+     * Decreases the break of the source.
+     *
+     * @param decrement The number of bytes to remove from the break.
+     * @return Nothing if the memory was given back, or the reason why it wasn't, then the break didn't move.
+     */
+    fn shrink(&mut self, _decrement: usize) -> Result<(), AllocError> {
+        Err(AllocError::invalid_argument(OsCall::Sbrk))
+    }
+
+    /**
+     * Gets a new block of memory.
+     *
+     * @param size The size of the block, it must be a multiple of the page size.
+     * @return The start of the block or the reason why the source can't give the memory.
+     */
+    fn map(&mut self, size: usize) -> Result<*mut u8, AllocError> {
+        self.grow(size)
+    }
+
+    /**
+     * Gives back a block of memory taken with map.
+     *
+     * @param addr The start of the block.
+     * @param size The size of the block.
+     * @return Nothing if the memory was given back, or the reason why it wasn't, then the block is still
+     * mapped.
+     *
+     * @note By default only the block at the end of the break can be given back, other blocks
+     * stay in the break and they fail with EINVAL.
+     */
+    fn unmap(&mut self, addr: *mut u8, size: usize) -> Result<(), AllocError> {
+        match self.grow(0) {
+            Ok(current_break) if addr as usize + size == current_break as usize => {
+                self.shrink(size)
+            }
+            _ => Err(AllocError::invalid_argument(OsCall::Munmap)),
+        }
+    }
+
+    /**
+     * Gets the size of a page of this source, blocks given by map are multiples of this size
+     */
+    fn page_size(&self) -> usize;
+}
@@ -0,0 +1,53 @@
+use libc::sbrk;
+
+use crate::{
+    error::{AllocError, OsCall},
+    mmap::utils::get_page_size,
+};
+
+use super::MemorySource;
+
+/*
+ * Value returned by sbrk when it fails, it's (void *)-1 and not NULL
+ */
+const SBRK_FAILED: *mut u8 = usize::MAX as *mut u8;
+
+/**
+ * Memory source that moves the program break of the process with sbrk
+ *
+ * @warning The program break is shared with any other code of the process that calls sbrk or brk,
+ * including the malloc of the C library.
+ */
+pub struct SbrkSource {}
+
+impl MemorySource for SbrkSource {
+    fn grow(&mut self, increment: usize) -> Result<*mut u8, AllocError> {
+        let Ok(increment) = isize::try_from(increment) else {
+            return Err(AllocError::out_of_memory(OsCall::Sbrk));
+        };
+
+        let old_break = unsafe { sbrk(increment) } as *mut u8;
+
+        if old_break == SBRK_FAILED {
+            return Err(AllocError::last_os_error(OsCall::Sbrk));
+        }
+
+        Ok(old_break)
+    }
+
+    fn shrink(&mut self, decrement: usize) -> Result<(), AllocError> {
+        let Ok(decrement) = isize::try_from(decrement) else {
+            return Err(AllocError::invalid_argument(OsCall::Sbrk));
+        };
+
+        if unsafe { sbrk(-decrement) } as *mut u8 == SBRK_FAILED {
+            return Err(AllocError::last_os_error(OsCall::Sbrk));
+        }
+
+        Ok(())
+    }
+
+    fn page_size(&self) -> usize {
+        get_page_size()
+    }
+}
@@ -0,0 +1,305 @@
+use crate::error::{AllocError, OsCall};
+
+use super::MemorySource;
+
+/*
+ * Page size used when no page size is given, the same as most x86_64 systems
+ */
+pub const SIMULATED_DEFAULT_PAGE_SIZE: usize = 4096;
+
+/**
+ * Operations of a memory source, used for injecting failures into a SimulatedSource
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulatedOperation {
+    Grow,
+    Shrink,
+    Map,
+    Unmap,
+}
+
+/**
+ * A failure that will be returned by a SimulatedSource
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulatedFailure {
+    // The nth call of the operation from now fails, starting from 1
+    Nth(SimulatedOperation, usize),
+    // Every call of the operation fails
+    Always(SimulatedOperation),
+    // Every call of any operation fails after the given number of successful calls
+    After(usize),
+}
+
+/**
+ * Memory source that simulates sbrk and mmap over a private buffer, so the allocators can be tested
+ * without touching the memory of the process, the tests are deterministic and they don't affect other tests
+ * running at the same time
+ *
+ * The buffer is split in two areas, the first one works as the program break and the second one is split into
+ * pages that are given by map
+ *
+ * ______________________________________________________________________
+ * |      break area  ->             |  page | page | page | page | ... |
+ * ______________________________________________________________________
+ *
+ * Like the real OS, mappings are page aligned and only mapped pages can be unmapped, and the break can't
+ * go below the start of the break area. This source doesn't call the OS, so it can run under Miri
+ */
+pub struct SimulatedSource {
+    buffer: *mut [u8],
+    start: *mut u8,
+    break_size: usize,
+    current_break: usize,
+    page_size: usize,
+    mapped_pages: Vec<bool>,
+    failures: Vec<SimulatedFailure>,
+    calls: usize,
+}
+
+/*
+ * The buffer is owned by the source, so it can be moved to other threads
+ */
+unsafe impl Send for SimulatedSource {}
+
+impl SimulatedSource {
+    /**
+     * Creates a simulated source with the default page size.
+     *
+     * @param break_size The size of the break area.
+     * @param map_size The size of the area used by map, it's rounded down to the page size.
+     */
+    pub fn new(break_size: usize, map_size: usize) -> Self {
+        Self::with_page_size(break_size, map_size, SIMULATED_DEFAULT_PAGE_SIZE)
+    }
+
+    /**
+     * Creates a simulated source with the given page size, it must be a power of two.
+     */
+    pub fn with_page_size(break_size: usize, map_size: usize, page_size: usize) -> Self {
+        assert!(
+            page_size.is_power_of_two(),
+            "Page size must be a power of two"
+        );
+
+        let break_size = break_size.next_multiple_of(page_size);
+        let pages = map_size / page_size;
+
+        /*
+         * The buffer is bigger than needed for aligning its start to the page size
+         */
+        let buffer =
+            Box::into_raw(vec![0u8; break_size + pages * page_size + page_size].into_boxed_slice());
+        let padding = (buffer as *mut u8).align_offset(page_size);
+        let start = unsafe { (buffer as *mut u8).add(padding) };
+
+        Self {
+            buffer,
+            start,
+            break_size,
+            current_break: 0,
+            page_size,
+            mapped_pages: vec![false; pages],
+            failures: Vec::new(),
+            calls: 0,
+        }
+    }
+
+    /**
+     * Adds a failure, it will be returned by the next calls of the source
+     */
+    pub fn inject_failure(&mut self, failure: SimulatedFailure) {
+        self.failures.push(failure);
+    }
+
+    /**
+     * Removes all the failures that weren't returned yet
+     */
+    pub fn clear_failures(&mut self) {
+        self.failures.clear();
+    }
+
+    /**
+     * Gets the number of bytes that are inside the break
+     */
+    pub fn break_used(&self) -> usize {
+        self.current_break
+    }
+
+    /**
+     * Gets the number of pages that are mapped
+     */
+    pub fn mapped_pages(&self) -> usize {
+        self.mapped_pages.iter().filter(|mapped| **mapped).count()
+    }
+
+    /**
+     * Gets the number of calls done to the source, including the ones that failed
+     */
+    pub fn calls(&self) -> usize {
+        self.calls
+    }
+
+    /**
+     * Checks if a pointer is inside the memory of this source
+     */
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        let end = self.start as usize + self.break_size + self.mapped_pages.len() * self.page_size;
+
+        ptr as usize >= self.start as usize && (ptr as usize) < end
+    }
+
+    /**
+     * Checks if the current call of the operation must fail and updates the injected failures
+     */
+    fn should_fail(&mut self, operation: SimulatedOperation) -> bool {
+        self.calls += 1;
+
+        let mut fail = false;
+
+        self.failures.retain_mut(|failure| match failure {
+            SimulatedFailure::Nth(failure_operation, nth) if *failure_operation == operation => {
+                *nth -= 1;
+
+                if *nth == 0 {
+                    fail = true;
+                    return false;
+                }
+
+                true
+            }
+            SimulatedFailure::Always(failure_operation) if *failure_operation == operation => {
+                fail = true;
+                true
+            }
+            SimulatedFailure::After(calls) => {
+                if *calls == 0 {
+                    fail = true;
+                } else {
+                    *calls -= 1;
+                }
+
+                true
+            }
+            _ => true,
+        });
+
+        fail
+    }
+
+    /**
+     * Gets the start of the area used by map
+     */
+    fn map_start(&self) -> usize {
+        self.start as usize + self.break_size
+    }
+}
+
+/*
+ * The buffer is kept as a raw pointer because moving a Box invalidates the pointers taken from it
+ * under the Miri aliasing model
+ */
+impl Drop for SimulatedSource {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(self.buffer)) };
+    }
+}
+
+impl MemorySource for SimulatedSource {
+    fn grow(&mut self, increment: usize) -> Result<*mut u8, AllocError> {
+        if self.should_fail(SimulatedOperation::Grow) {
+            return Err(AllocError::out_of_memory(OsCall::Sbrk));
+        }
+
+        if increment > self.break_size - self.current_break {
+            return Err(AllocError::out_of_memory(OsCall::Sbrk));
+        }
+
+        let old_break = unsafe { self.start.add(self.current_break) };
+        self.current_break += increment;
+
+        Ok(old_break)
+    }
+
+    fn shrink(&mut self, decrement: usize) -> Result<(), AllocError> {
+        if self.should_fail(SimulatedOperation::Shrink) {
+            return Err(AllocError::out_of_memory(OsCall::Sbrk));
+        }
+
+        if decrement > self.current_break {
+            return Err(AllocError::invalid_argument(OsCall::Sbrk));
+        }
+
+        self.current_break -= decrement;
+
+        Ok(())
+    }
+
+    /**
+     * Looks for the first range of unmapped pages that can store the given size
+     */
+    fn map(&mut self, size: usize) -> Result<*mut u8, AllocError> {
+        if self.should_fail(SimulatedOperation::Map) {
+            return Err(AllocError::out_of_memory(OsCall::Mmap));
+        }
+
+        let pages = size.div_ceil(self.page_size);
+
+        if pages == 0 || pages > self.mapped_pages.len() {
+            return Err(AllocError::out_of_memory(OsCall::Mmap));
+        }
+
+        let first_page = (0..=self.mapped_pages.len() - pages)
+            .find(|first_page| {
+                self.mapped_pages[*first_page..*first_page + pages]
+                    .iter()
+                    .all(|mapped| !mapped)
+            })
+            .ok_or(AllocError::out_of_memory(OsCall::Mmap))?;
+
+        self.mapped_pages[first_page..first_page + pages].fill(true);
+
+        unsafe {
+            Ok(self
+                .start
+                .add(self.break_size + first_page * self.page_size))
+        }
+    }
+
+    /**
+     * Unmaps the pages of the given range, like munmap it fails if the address isn't page aligned,
+     * but unlike munmap it also fails if any of the pages isn't mapped, that helps to find bugs
+     */
+    fn unmap(&mut self, addr: *mut u8, size: usize) -> Result<(), AllocError> {
+        if self.should_fail(SimulatedOperation::Unmap) {
+            return Err(AllocError::out_of_memory(OsCall::Munmap));
+        }
+
+        let addr = addr as usize;
+
+        if addr < self.map_start() || !(addr - self.map_start()).is_multiple_of(self.page_size) {
+            return Err(AllocError::invalid_argument(OsCall::Munmap));
+        }
+
+        let first_page = (addr - self.map_start()) / self.page_size;
+        let pages = size.div_ceil(self.page_size);
+
+        if first_page + pages > self.mapped_pages.len() {
+            return Err(AllocError::invalid_argument(OsCall::Munmap));
+        }
+
+        let range = &mut self.mapped_pages[first_page..first_page + pages];
+
+        if range.iter().any(|mapped| !mapped) {
+            return Err(AllocError::invalid_argument(OsCall::Munmap));
+        }
+
+        range.fill(false);
+
+        Ok(())
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+}
@@ -0,0 +1,41 @@
+use crate::error::AllocError;
+
+use super::{MemorySource, sbrk::SbrkSource, virtual_break::VirtualBreakSource};
+
+/**
+ * Break used by the global bump heap, by default it's the program break of the process, but it can be
+ * changed to a virtual break for processes where the malloc of the C library is also moving the program break
+ */
+pub enum SystemBreakSource {
+    Sbrk(SbrkSource),
+    Virtual(VirtualBreakSource),
+}
+
+impl Default for SystemBreakSource {
+    fn default() -> Self {
+        Self::Sbrk(SbrkSource {})
+    }
+}
+
+impl MemorySource for SystemBreakSource {
+    fn grow(&mut self, increment: usize) -> Result<*mut u8, AllocError> {
+        match self {
+            Self::Sbrk(source) => source.grow(increment),
+            Self::Virtual(source) => source.grow(increment),
+        }
+    }
+
+    fn shrink(&mut self, decrement: usize) -> Result<(), AllocError> {
+        match self {
+            Self::Sbrk(source) => source.shrink(decrement),
+            Self::Virtual(source) => source.shrink(decrement),
+        }
+    }
+
+    fn page_size(&self) -> usize {
+        match self {
+            Self::Sbrk(source) => source.page_size(),
+            Self::Virtual(source) => source.page_size(),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use libc::{
+    MADV_DONTNEED, MAP_ANONYMOUS, MAP_FAILED, MAP_NORESERVE, MAP_PRIVATE, PROT_NONE, PROT_READ,
+    PROT_WRITE, madvise, mmap, mprotect, munmap,
+};
+use std::ptr;
+
+use crate::{
+    error::{AllocError, OsCall},
+    mmap::utils::{get_page_size, round_up_to_page_size},
+};
+
+use super::MemorySource;
+
+/*
+ * Size of the virtual range reserved when no size is given
+ */
+pub const VIRTUAL_BREAK_DEFAULT_RESERVE: usize = 1024 * 1024 * 1024;
+
+/**
+ * Memory source that works like a private program break, so it doesn't share the break with the malloc
+ * of the C library
+ *
+ * At the first grow, a big range of virtual memory is reserved with mmap and PROT_NONE, reserving doesn't
+ * use physical memory. The break starts at the start of the range and the pages under the break are
+ * committed with mprotect, when the break decreases the pages over it are given back to the OS with
+ * madvise and protected again
+ *
+ * __________________________________________________________
+ * |  committed (read/write)  |   reserved (PROT_NONE)      |
+ * __________________________________________________________
+ * ^ start                    ^ break                       ^ end
+ */
+pub struct VirtualBreakSource {
+    reserve_size: usize,
+    start: *mut u8,
+    current_break: usize,
+    committed: usize,
+}
+
+/*
+ * The reserved range is owned by the source, so it can be moved to other threads
+ */
+unsafe impl Send for VirtualBreakSource {}
+
+impl VirtualBreakSource {
+    /**
+     * Creates a virtual break, the range isn't reserved until the first grow.
+     *
+     * @param reserve_size The size of the virtual range, the break can't grow over it.
+     */
+    pub fn new(reserve_size: usize) -> Self {
+        Self {
+            reserve_size: round_up_to_page_size(reserve_size, get_page_size()),
+            start: ptr::null_mut(),
+            current_break: 0,
+            committed: 0,
+        }
+    }
+
+    /**
+     * Gets the number of bytes that are committed, that are the pages under the break
+     */
+    pub fn committed(&self) -> usize {
+        self.committed
+    }
+
+    /**
+     * Reserves the virtual range if it isn't reserved yet
+     */
+    fn reserve(&mut self) -> Result<*mut u8, AllocError> {
+        if !self.start.is_null() {
+            return Ok(self.start);
+        }
+
+        let addr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                self.reserve_size,
+                PROT_NONE,
+                MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+
+        if addr == MAP_FAILED {
+            return Err(AllocError::last_os_error(OsCall::Mmap));
+        }
+
+        self.start = addr as *mut u8;
+
+        Ok(self.start)
+    }
+}
+
+impl Default for VirtualBreakSource {
+    fn default() -> Self {
+        Self::new(VIRTUAL_BREAK_DEFAULT_RESERVE)
+    }
+}
+
+impl Drop for VirtualBreakSource {
+    fn drop(&mut self) {
+        if !self.start.is_null() {
+            unsafe { munmap(self.start as *mut _, self.reserve_size) };
+        }
+    }
+}
+
+impl MemorySource for VirtualBreakSource {
+    fn grow(&mut self, increment: usize) -> Result<*mut u8, AllocError> {
+        let start = self.reserve()?;
+
+        if increment > self.reserve_size - self.current_break {
+            return Err(AllocError::out_of_memory(OsCall::Sbrk));
+        }
+
+        let new_break = self.current_break + increment;
+
+        /*
+         * Commits the pages between the old committed end and the page where the new break is
+         */
+        if new_break > self.committed {
+            let new_committed = round_up_to_page_size(new_break, get_page_size());
+
+            let result = unsafe {
+                mprotect(
+                    start.add(self.committed) as *mut _,
+                    new_committed - self.committed,
+                    PROT_READ | PROT_WRITE,
+                )
+            };
+
+            if result != 0 {
+                return Err(AllocError::last_os_error(OsCall::Mprotect));
+            }
+
+            self.committed = new_committed;
+        }
+
+        let old_break = unsafe { start.add(self.current_break) };
+        self.current_break = new_break;
+
+        Ok(old_break)
+    }
+
+    fn shrink(&mut self, decrement: usize) -> Result<(), AllocError> {
+        if decrement > self.current_break {
+            return Err(AllocError::invalid_argument(OsCall::Sbrk));
+        }
+
+        /*
+         * The pages that are fully over the new break are given back to the OS and they can't be used
+         * until the break grows again, if the OS refuses it the break doesn't move
+         */
+        let new_break = self.current_break - decrement;
+        let new_committed = round_up_to_page_size(new_break, get_page_size());
+
+        if new_committed < self.committed {
+            unsafe {
+                let released = self.start.add(new_committed) as *mut _;
+                let released_size = self.committed - new_committed;
+
+                if madvise(released, released_size, MADV_DONTNEED) != 0 {
+                    return Err(AllocError::last_os_error(OsCall::Madvise));
+                }
+
+                /*
+                 * The pages were already given back, so they stay committed if they can't be protected
+                 */
+                if mprotect(released, released_size, PROT_NONE) == 0 {
+                    self.committed = new_committed;
+                }
+            }
+        }
+
+        self.current_break = new_break;
+
+        Ok(())
+    }
+
+    fn page_size(&self) -> usize {
+        get_page_size()
+    }
+}
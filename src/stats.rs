@@ -0,0 +1,224 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    error::AllocError,
+    limits::AutoTuning,
+    quota::{Quota, QuotaKind},
+    small::SmallStats,
+    walk::{HeapEntry, HeapEntryKind},
+};
+
+/**
+ * Statistics of a heap, like mallinfo of the C library
+ *
+ * bytes_in_use + bytes_free + header_overhead is the memory that the heap took from its memory source
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    // Bytes given to the user that aren't deallocated
+    pub bytes_in_use: usize,
+    // Bytes that the heap haves but aren't given to the user
+    pub bytes_free: usize,
+    // Bytes used by block, section and region headers
+    pub header_overhead: usize,
+    // Number of blocks (bump) or sections (mmap)
+    pub blocks: usize,
+    // Number of regions, it's always 0 for the bump heap
+    pub regions: usize,
+    // Slabs of the small allocator, it's only counted by the stats of MmapAllocator
+    pub small_slabs: usize,
+    pub heap_size: usize,
+    pub peak_heap_size: usize,
+    pub sbrk_calls: usize,
+    pub mmap_calls: usize,
+    pub munmap_calls: usize,
+    pub allocations: usize,
+    pub frees: usize,
+    // Calls to the memory source that failed, including the ones that gave memory back
+    pub os_failures: usize,
+    pub last_os_error: Option<AllocError>,
+    pub quota_kind: QuotaKind,
+    // None when the heap doesn't have a quota
+    pub quota_limit: Option<usize>,
+    // Bytes counted by the quota, they are counted even if the heap doesn't have a limit
+    pub quota_used: usize,
+    // Settings derived from the memory limits of the process, None when the heap wasn't tuned
+    pub tuning: Option<AutoTuning>,
+    // 0 when all the free bytes are in one block, near to 1 when the free bytes are split in many small blocks
+    pub fragmentation: f64,
+}
+
+impl Stats {
+    /**
+     * Calculates the fragmentation ratio, it's 1 - largest free block / free bytes
+     */
+    pub fn fragmentation_ratio(largest_free: usize, bytes_free: usize) -> f64 {
+        if bytes_free == 0 {
+            return 0.0;
+        }
+
+        1.0 - largest_free as f64 / bytes_free as f64
+    }
+
+    /**
+     * Fills the fields that are calculated by walking the heap
+     *
+     * @param entries The entries of a heap walk, every region must be followed by its sections.
+     *
+     * @note The free space at the end of a region that isn't used by any section is counted as one
+     * free block for the fragmentation ratio.
+     */
+    pub fn add_entries(mut self, entries: impl Iterator<Item = HeapEntry>) -> Self {
+        let mut largest_free = 0;
+        let mut region_tail = 0;
+        let mut region_tails = 0;
+
+        for entry in entries {
+            self.header_overhead += entry.header_size;
+
+            match entry.kind {
+                HeapEntryKind::Region => {
+                    largest_free = largest_free.max(region_tail);
+                    region_tails += region_tail;
+                    region_tail = entry.size;
+                    self.regions += 1;
+                    continue;
+                }
+                HeapEntryKind::Section => region_tail -= entry.size + entry.header_size,
+                HeapEntryKind::Block => {}
+            }
+
+            self.blocks += 1;
+
+            if entry.is_free {
+                self.bytes_free += entry.size;
+                largest_free = largest_free.max(entry.size);
+            } else {
+                self.bytes_in_use += entry.size;
+            }
+        }
+
+        /*
+         * The tail of every region is free, it's the region size minus all its sections
+         */
+        largest_free = largest_free.max(region_tail);
+        self.bytes_free += region_tails + region_tail;
+        self.fragmentation = Self::fragmentation_ratio(largest_free, self.bytes_free);
+
+        self
+    }
+
+    /**
+     * Fills the fields of the quota, the heap size must be already loaded from the counters
+     */
+    pub fn add_quota(mut self, quota: &Quota) -> Self {
+        self.quota_kind = quota.kind();
+        self.quota_limit = quota.limit();
+        self.quota_used = quota.used(self.heap_size);
+
+        self
+    }
+
+    /**
+     * Adds the objects of the small allocator, every object is counted as a block, the objects don't have
+     * headers
+     *
+     * @note The slabs are already counted in the heap size, MmapAllocator counts them when they are taken
+     * from the arena.
+     */
+    pub fn add_small_objects(mut self, small: SmallStats) -> Self {
+        self.small_slabs = small.slabs;
+        self.blocks += small.live_objects + small.free_objects;
+        self.bytes_in_use += small.live_bytes;
+        self.bytes_free += small.free_bytes;
+
+        self
+    }
+
+    /**
+     * Fills the settings that the heap got from the memory limits
+     */
+    pub fn add_tuning(mut self, tuning: Option<AutoTuning>) -> Self {
+        self.tuning = tuning;
+
+        self
+    }
+}
+
+/**
+ * Counters updated on every operation of a heap, they use relaxed atomics because they are only read
+ * for statistics, so the cost for every operation is a few atomic additions
+ */
+#[derive(Default)]
+pub struct HeapCounters {
+    pub heap_size: AtomicUsize,
+    pub peak_heap_size: AtomicUsize,
+    pub sbrk_calls: AtomicUsize,
+    pub mmap_calls: AtomicUsize,
+    pub munmap_calls: AtomicUsize,
+    pub allocations: AtomicUsize,
+    pub frees: AtomicUsize,
+    pub os_failures: AtomicUsize,
+    pub last_os_error: Mutex<Option<AllocError>>,
+}
+
+impl HeapCounters {
+    pub const fn new() -> Self {
+        Self {
+            heap_size: AtomicUsize::new(0),
+            peak_heap_size: AtomicUsize::new(0),
+            sbrk_calls: AtomicUsize::new(0),
+            mmap_calls: AtomicUsize::new(0),
+            munmap_calls: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            os_failures: AtomicUsize::new(0),
+            last_os_error: Mutex::new(None),
+        }
+    }
+
+    /**
+     * Records that the heap took memory from its source
+     */
+    pub fn record_heap_growth(&self, bytes: usize) {
+        let heap_size = self.heap_size.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak_heap_size.fetch_max(heap_size, Ordering::Relaxed);
+    }
+
+    /**
+     * Records that the heap gave memory back to its source
+     */
+    pub fn record_heap_release(&self, bytes: usize) {
+        self.heap_size.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /**
+     * Records that a call to the memory source failed, it's only called on failures so the lock isn't
+     * taken by the operations that succeed
+     */
+    pub fn record_os_failure(&self, error: AllocError) {
+        self.os_failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_os_error.lock().unwrap() = Some(error);
+    }
+
+    /**
+     * Copies the counters into a Stats, the fields that are calculated by walking the heap are left empty
+     */
+    pub fn load(&self) -> Stats {
+        Stats {
+            heap_size: self.heap_size.load(Ordering::Relaxed),
+            peak_heap_size: self.peak_heap_size.load(Ordering::Relaxed),
+            sbrk_calls: self.sbrk_calls.load(Ordering::Relaxed),
+            mmap_calls: self.mmap_calls.load(Ordering::Relaxed),
+            munmap_calls: self.munmap_calls.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+            os_failures: self.os_failures.load(Ordering::Relaxed),
+            last_os_error: *self.last_os_error.lock().unwrap(),
+            ..Stats::default()
+        }
+    }
+}
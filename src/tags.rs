@@ -0,0 +1,217 @@
+use std::{
+    cell::Cell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::error::AllocError;
+
+/**
+ * Category of an allocation, it's stored in the header of the block or section, so the per-tag counters are
+ * exact for every live allocation
+ */
+pub type AllocTag = u8;
+
+/*
+ * Tag of the allocations done outside of a TagScope
+ */
+pub const UNTAGGED: AllocTag = 0;
+
+pub const MAX_TAGS: usize = AllocTag::MAX as usize + 1;
+
+/*
+ * Limit stored when a tag doesn't have a limit
+ */
+const NO_LIMIT: usize = usize::MAX;
+
+thread_local! {
+    static CURRENT_TAG: Cell<AllocTag> = const { Cell::new(UNTAGGED) };
+}
+
+/**
+ * Gets the tag that the allocations of this thread get
+ */
+pub fn current_tag() -> AllocTag {
+    CURRENT_TAG.get()
+}
+
+/**
+ * Sets the tag of the allocations of this thread until it's dropped, then the previous tag is restored, so
+ * the scopes can be nested
+ *
+ * For example:
+ *
+ * let _scope = TagScope::enter(QUERY_PLANNER_TAG);
+ * let plan = BumpAllocator::qualloc::<u8>(128); <- Counted in QUERY_PLANNER_TAG
+ */
+pub struct TagScope {
+    previous: AllocTag,
+}
+
+impl TagScope {
+    pub fn enter(tag: AllocTag) -> Self {
+        Self {
+            previous: CURRENT_TAG.replace(tag),
+        }
+    }
+}
+
+impl Drop for TagScope {
+    fn drop(&mut self) {
+        CURRENT_TAG.set(self.previous);
+    }
+}
+
+/**
+ * Live allocations of a tag
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TagStats {
+    pub tag: AllocTag,
+    // Bytes asked by the user that aren't deallocated, headers and canaries aren't counted
+    pub live_bytes: usize,
+    pub live_count: usize,
+    // Highest live bytes since the heap was created or torn down
+    pub peak_bytes: usize,
+    pub limit: Option<usize>,
+}
+
+struct TagCounters {
+    live_bytes: AtomicUsize,
+    live_count: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    limit: AtomicUsize,
+}
+
+impl TagCounters {
+    const fn new() -> Self {
+        Self {
+            live_bytes: AtomicUsize::new(0),
+            live_count: AtomicUsize::new(0),
+            peak_bytes: AtomicUsize::new(0),
+            limit: AtomicUsize::new(NO_LIMIT),
+        }
+    }
+}
+
+/**
+ * Counters of every tag of a heap, they are fixed arrays of atomics, so recording an allocation doesn't
+ * allocate and reading the counters doesn't take the heap lock
+ *
+ * @note An allocation that would take a tag over its limit fails with AllocError::TagLimitExceeded, the
+ * other tags and the quota of the heap aren't affected.
+ */
+pub struct Tags {
+    counters: [TagCounters; MAX_TAGS],
+}
+
+impl Default for Tags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tags {
+    pub const fn new() -> Self {
+        Self {
+            counters: [const { TagCounters::new() }; MAX_TAGS],
+        }
+    }
+
+    /**
+     * Sets the limit of a tag.
+     *
+     * @param limit The maximum number of live bytes of the tag, None removes the limit.
+     *
+     * @note Lowering the limit under the live bytes of the tag doesn't free anything, only the next
+     * allocations of the tag fail.
+     */
+    pub fn set_limit(&self, tag: AllocTag, limit: Option<usize>) {
+        self.counters[tag as usize]
+            .limit
+            .store(limit.unwrap_or(NO_LIMIT), Ordering::Relaxed);
+    }
+
+    pub fn limit(&self, tag: AllocTag) -> Option<usize> {
+        match self.counters[tag as usize].limit.load(Ordering::Relaxed) {
+            NO_LIMIT => None,
+            limit => Some(limit),
+        }
+    }
+
+    /**
+     * Checks that a new allocation fits in the limit of its tag, it must be called with the heap lock taken,
+     * so no other allocation is counted between the check and record_allocation
+     */
+    pub fn check(&self, tag: AllocTag, requested: usize) -> Result<(), AllocError> {
+        let live_bytes = self.counters[tag as usize]
+            .live_bytes
+            .load(Ordering::Relaxed);
+
+        match self.limit(tag) {
+            Some(limit) if live_bytes.saturating_add(requested) > limit => {
+                Err(AllocError::TagLimitExceeded {
+                    tag,
+                    limit,
+                    requested,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_allocation(&self, tag: AllocTag, usr_size: usize) {
+        let counters = &self.counters[tag as usize];
+        let live_bytes = counters.live_bytes.fetch_add(usr_size, Ordering::Relaxed) + usr_size;
+
+        counters.live_count.fetch_add(1, Ordering::Relaxed);
+        counters.peak_bytes.fetch_max(live_bytes, Ordering::Relaxed);
+    }
+
+    /**
+     * Counts a deallocation, usr_size must be the same size counted by record_allocation, so a counter that
+     * goes under 0 is a bug of the caller
+     */
+    pub fn record_free(&self, tag: AllocTag, usr_size: usize) {
+        let counters = &self.counters[tag as usize];
+        let live_bytes = counters.live_bytes.fetch_sub(usr_size, Ordering::Relaxed);
+        let live_count = counters.live_count.fetch_sub(1, Ordering::Relaxed);
+
+        debug_assert!(
+            live_bytes >= usr_size && live_count >= 1,
+            "Tag {tag} freed more than it allocated"
+        );
+    }
+
+    pub fn stats(&self, tag: AllocTag) -> TagStats {
+        let counters = &self.counters[tag as usize];
+
+        TagStats {
+            tag,
+            live_bytes: counters.live_bytes.load(Ordering::Relaxed),
+            live_count: counters.live_count.load(Ordering::Relaxed),
+            peak_bytes: counters.peak_bytes.load(Ordering::Relaxed),
+            limit: self.limit(tag),
+        }
+    }
+
+    /**
+     * Gets the stats of the tags that were used or have a limit, sorted by tag
+     */
+    pub fn all_stats(&self) -> Vec<TagStats> {
+        (0..MAX_TAGS)
+            .map(|tag| self.stats(tag as AllocTag))
+            .filter(|stats| stats.peak_bytes > 0 || stats.live_count > 0 || stats.limit.is_some())
+            .collect()
+    }
+
+    /**
+     * Forgets the live allocations and the peaks, it's used when the heap is torn down, the limits are kept
+     */
+    pub fn clear(&self) {
+        for counters in &self.counters {
+            counters.live_bytes.store(0, Ordering::Relaxed);
+            counters.live_count.store(0, Ordering::Relaxed);
+            counters.peak_bytes.store(0, Ordering::Relaxed);
+        }
+    }
+}
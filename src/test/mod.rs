@@ -1 +1,17 @@
 pub mod unit_tests;
+
+use std::sync::Mutex;
+
+/*
+ * Tests that use the global allocators share the same heap, so the ones that make assertions about
+ * the heap layout must take this lock for not being affected by other tests allocating at the same time
+ */
+pub static GLOBAL_HEAP_LOCK: Mutex<()> = Mutex::new(());
+
+/**
+ * Gets a buffer that lives during the whole test program, used by the tests that run the allocators
+ * over a BufferSource
+ */
+pub fn leak_buffer(size: usize) -> &'static mut [u8] {
+    Box::leak(vec![0u8; size].into_boxed_slice())
+}
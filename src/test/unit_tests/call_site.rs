@@ -0,0 +1,167 @@
+use crate::{
+    bump::BumpHeap,
+    call_site::{AllocatorScope, CALL_SITE_DEPTH, CompactBacktrace},
+    mmap::{MmapHeap, allocator::MmapAllocator},
+    small::allocator::SmallAllocator,
+    source::simulated::SimulatedSource,
+    test::GLOBAL_HEAP_LOCK,
+};
+
+#[inline(never)]
+fn allocate_many_small(heap: &BumpHeap<SimulatedSource>) -> Vec<*mut u8> {
+    (0..4).map(|_| heap.qualloc::<u8>(16).unwrap()).collect()
+}
+
+#[inline(never)]
+fn allocate_one_big(heap: &BumpHeap<SimulatedSource>) -> *mut u8 {
+    heap.qualloc::<u8>(1024).unwrap()
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_compact_backtrace() {
+    let first = CompactBacktrace::capture();
+    let second = CompactBacktrace::capture();
+
+    assert!(!first.frames().is_empty());
+    assert_ne!(first.hash(), second.hash());
+    assert_eq!(first.to_string().lines().count(), first.frames().len());
+}
+
+/*
+ * Stands for an allocator function called by the program, the depth is the number of its internal frames
+ */
+#[inline(never)]
+fn capture_in_allocator(depth: usize) -> CompactBacktrace {
+    let _allocator_scope = AllocatorScope::enter();
+
+    /*
+     * Called through a pointer, so the first internal frame isn't inlined for some depths only
+     */
+    let capture_nested: fn(usize) -> CompactBacktrace = std::hint::black_box(capture_nested);
+
+    capture_nested(depth)
+}
+
+#[inline(never)]
+fn capture_nested(depth: usize) -> CompactBacktrace {
+    match depth {
+        0 => CompactBacktrace::capture(),
+        _ => std::hint::black_box(capture_nested(depth - 1)),
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_call_sites_skip_the_allocator_frames() {
+    /*
+     * The frames under the allocator function aren't captured, so the same call gives the same backtrace
+     * whatever the number of internal frames is
+     */
+    let backtraces: Vec<_> = [0, 8, 32].into_iter().map(capture_in_allocator).collect();
+
+    assert!(!backtraces[0].frames().is_empty());
+    assert!(
+        backtraces
+            .iter()
+            .all(|backtrace| backtrace == &backtraces[0])
+    );
+
+    /*
+     * Without the scope the internal frames take the place of the frames of the program
+     */
+    let unscoped: Vec<_> = [0, 32].into_iter().map(capture_nested).collect();
+
+    assert_ne!(unscoped[0], unscoped[1]);
+    assert_eq!(unscoped[1].frames().len(), CALL_SITE_DEPTH);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_bump_heap_top_call_sites() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let untracked = heap.qualloc::<u8>(32).unwrap();
+
+    heap.call_sites.set_enabled(true);
+
+    let small_blocks = allocate_many_small(&heap);
+    let big_block = allocate_one_big(&heap);
+
+    let by_bytes = heap.call_sites.top_by_bytes(1);
+    let by_count = heap.call_sites.top_by_count(2);
+
+    assert_eq!(by_bytes.len(), 1);
+    assert_eq!((by_bytes[0].live_bytes, by_bytes[0].live_count), (1024, 1));
+    assert_eq!(
+        by_count
+            .iter()
+            .map(|site| (site.live_bytes, site.live_count))
+            .collect::<Vec<_>>(),
+        vec![(64, 4), (1024, 1)]
+    );
+    assert_eq!(
+        heap.call_sites.call_site(big_block as usize),
+        Some(by_bytes[0].backtrace)
+    );
+    assert_eq!(heap.call_sites.call_site(untracked as usize), None);
+
+    /*
+     * A call site is forgotten when all its allocations are freed
+     */
+    heap.qudelloc(big_block);
+    heap.qudelloc(small_blocks[0]);
+
+    let sites = heap.call_sites.top_by_bytes(10);
+
+    assert_eq!(sites.len(), 1);
+    assert_eq!((sites[0].live_bytes, sites[0].live_count), (48, 3));
+
+    for block in &small_blocks[1..] {
+        heap.qudelloc(*block);
+    }
+
+    assert!(heap.call_sites.top_by_count(10).is_empty());
+    heap.qudelloc(untracked);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_leak_report_uses_call_sites() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+
+    heap.call_sites.set_enabled(true);
+
+    let leak = heap.allocate::<u8>(100).unwrap();
+    let report = heap.leaks();
+
+    assert_eq!(report.groups.len(), 1);
+    assert_eq!(
+        report.groups[0].call_site,
+        heap.call_sites
+            .call_site(leak as usize)
+            .map(|backtrace| backtrace.to_string())
+    );
+    assert!(report.groups[0].call_site.is_some());
+
+    heap.deallocate(leak);
+}
+
+#[test]
+fn test_small_allocations_record_call_sites() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    MmapAllocator::set_call_sites(true);
+
+    let section = MmapAllocator::allocate::<u8>(32).unwrap();
+    let recorded_bytes: usize = MmapAllocator::top_call_sites_by_bytes(usize::MAX)
+        .iter()
+        .map(|site| site.live_bytes)
+        .sum();
+
+    assert!(SmallAllocator::object_entry(section).is_none());
+    assert!(recorded_bytes >= 32);
+
+    MmapAllocator::deallocate(section);
+    MmapAllocator::set_call_sites(false);
+}
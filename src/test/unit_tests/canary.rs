@@ -0,0 +1,250 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+};
+
+use crate::{
+    bump::BumpHeap,
+    canary::OverflowReport,
+    mmap::{MmapHeap, allocator::MmapAllocator},
+    small::allocator::SmallAllocator,
+    source::simulated::SimulatedSource,
+    test::GLOBAL_HEAP_LOCK,
+};
+
+#[test]
+fn test_bump_heap_canaries() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let handler_reports = reports.clone();
+
+    heap.canaries
+        .set_handler(Some(Arc::new(move |report: &OverflowReport| {
+            handler_reports.lock().unwrap().push(*report)
+        })));
+    heap.canaries.set_enabled(true);
+
+    let first_block = heap.qualloc::<u8>(13).unwrap();
+    let second_block = heap.qualloc::<u8>(13).unwrap();
+    let third_block = heap.qualloc::<u8>(13).unwrap();
+
+    unsafe { first_block.write_bytes(0, 13) };
+    assert!(heap.check_all().is_empty());
+
+    /*
+     * A one byte overflow changes the rear canary instead of the next header
+     */
+    unsafe { first_block.add(13).write(0) };
+    heap.qudelloc(first_block);
+
+    assert_eq!(
+        reports
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|report| (
+                report.block.usr_address,
+                report.usr_size,
+                report.front_clobbered,
+                report.rear_clobbered
+            ))
+            .collect::<Vec<_>>(),
+        vec![(first_block as usize, 13, 0, 1)]
+    );
+
+    unsafe { second_block.sub(2).write_bytes(0, 2) };
+
+    let overflows = heap.check_all();
+
+    assert_eq!(overflows.len(), 1);
+    assert_eq!(overflows[0].block.usr_address, second_block as usize);
+    assert_eq!(overflows[0].front_clobbered, 2);
+    assert_eq!(heap.verify(), Ok(()));
+
+    heap.qudelloc(third_block);
+}
+
+#[test]
+fn test_handler_can_change_the_handler() {
+    let heap = Arc::new(BumpHeap::new(SimulatedSource::new(64 * 1024, 0)));
+    let clobbered = Arc::new(Mutex::new(Vec::new()));
+    let handler_heap = heap.clone();
+    let handler_clobbered = clobbered.clone();
+
+    /*
+     * The handler is called without its lock, so it can replace itself
+     */
+    heap.canaries
+        .set_handler(Some(Arc::new(move |report: &OverflowReport| {
+            let next_clobbered = handler_clobbered.clone();
+
+            handler_clobbered
+                .lock()
+                .unwrap()
+                .push(("first", report.rear_clobbered));
+            handler_heap
+                .canaries
+                .set_handler(Some(Arc::new(move |report: &OverflowReport| {
+                    next_clobbered
+                        .lock()
+                        .unwrap()
+                        .push(("second", report.rear_clobbered))
+                })));
+        })));
+    heap.canaries.set_enabled(true);
+
+    let blocks: Vec<_> = (0..3).map(|_| heap.qualloc::<u8>(16).unwrap()).collect();
+
+    for (index, block) in blocks[..2].iter().enumerate() {
+        unsafe { block.add(16).write_bytes(0, index + 1) };
+        heap.qudelloc(*block);
+    }
+
+    assert_eq!(
+        *clobbered.lock().unwrap(),
+        vec![("first", 1), ("second", 2)]
+    );
+
+    heap.qudelloc(blocks[2]);
+    heap.canaries.set_handler(None);
+}
+
+#[test]
+fn test_mmap_heap_canaries() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+    let section_without_canaries = heap.allocate::<u8>(100).unwrap();
+
+    heap.canaries.set_enabled(true);
+
+    let section = heap.allocate::<u8>(1000).unwrap();
+
+    unsafe {
+        section_without_canaries.add(100).write(0);
+        section.add(1000).write_bytes(0, 3);
+    }
+
+    let overflows = heap.check_all();
+
+    assert_eq!(overflows.len(), 1);
+    assert_eq!(overflows[0].block.usr_address, section as usize);
+    assert_eq!(overflows[0].usr_size, 1000);
+    assert_eq!(overflows[0].rear_clobbered, 3);
+    assert!(overflows[0].to_string().starts_with("buffer overflow"));
+}
+
+/**
+ * Allocates and fills blocks of the given heap until done is set, the blocks are freed in the same order so the
+ * next allocations reuse them
+ */
+fn fill_blocks_until_done(
+    done: &AtomicBool,
+    allocate: impl Fn(usize) -> *mut u8,
+    deallocate: impl Fn(*mut u8),
+) {
+    let mut blocks = Vec::new();
+    let mut round = 0;
+
+    while !done.load(Ordering::Relaxed) {
+        let size = 8 + round % 57;
+        let block = allocate(size);
+
+        unsafe { block.write_bytes(0xab, size) };
+        blocks.push(block);
+
+        if blocks.len() == 8 {
+            blocks.drain(..).for_each(&deallocate);
+        }
+
+        round += 1;
+    }
+
+    blocks.into_iter().for_each(deallocate);
+}
+
+#[test]
+fn test_canaries_enabled_while_allocating() {
+    let bump_heap = Arc::new(BumpHeap::new(SimulatedSource::new(1024 * 1024, 0)));
+    let mmap_heap = Arc::new(MmapHeap::new(SimulatedSource::new(0, 1024 * 1024)));
+    let done = Arc::new(AtomicBool::new(false));
+    let reports = Arc::new(Mutex::new(Vec::new()));
+
+    for canaries in [&bump_heap.canaries, &mmap_heap.canaries] {
+        let handler_reports = reports.clone();
+
+        canaries.set_handler(Some(Arc::new(move |report: &OverflowReport| {
+            handler_reports.lock().unwrap().push(*report)
+        })));
+    }
+
+    let threads: Vec<_> = (0..4)
+        .map(|thread_index| {
+            let bump_heap = bump_heap.clone();
+            let mmap_heap = mmap_heap.clone();
+            let done = done.clone();
+
+            thread::spawn(move || match thread_index % 2 {
+                0 => fill_blocks_until_done(
+                    &done,
+                    |size| bump_heap.qualloc::<u8>(size as i32).unwrap(),
+                    |block| bump_heap.qudelloc(block),
+                ),
+                _ => fill_blocks_until_done(
+                    &done,
+                    |size| mmap_heap.allocate::<u8>(size).unwrap(),
+                    |section| mmap_heap.deallocate(section),
+                ),
+            })
+        })
+        .collect();
+
+    /*
+     * A block sized without the canaries must not get them, the rear canary would overwrite the next header
+     */
+    for round in 0..1000 {
+        bump_heap.canaries.set_enabled(round % 2 == 0);
+        mmap_heap.canaries.set_enabled(round % 2 == 0);
+        thread::yield_now();
+    }
+
+    done.store(true, Ordering::Relaxed);
+    threads
+        .into_iter()
+        .for_each(|thread| thread.join().unwrap());
+
+    assert_eq!(bump_heap.verify(), Ok(()));
+    assert_eq!(mmap_heap.verify(), Ok(()));
+    assert!(reports.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_small_allocations_get_canaries() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let handler_reports = reports.clone();
+
+    MmapAllocator::set_overflow_handler(Some(Arc::new(move |report: &OverflowReport| {
+        handler_reports.lock().unwrap().push(*report)
+    })));
+    MmapAllocator::set_canaries(true);
+
+    /*
+     * Small objects don't have room for the canaries, so the allocation goes to the heap
+     */
+    let section = MmapAllocator::allocate::<u8>(13).unwrap();
+    assert!(SmallAllocator::object_entry(section).is_none());
+
+    unsafe { section.add(13).write(0) };
+    MmapAllocator::deallocate(section);
+    MmapAllocator::set_canaries(false);
+    MmapAllocator::set_overflow_handler(None);
+
+    let reports = reports.lock().unwrap();
+
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].block.usr_address, section as usize);
+}
@@ -0,0 +1,114 @@
+use crate::{
+    bump::{BumpHeap, BumpMemoryBlockHeader},
+    error::{AllocError, OsCall},
+    mmap::MmapHeap,
+    source::simulated::{SimulatedFailure, SimulatedOperation, SimulatedSource},
+    verify::HeapError,
+};
+
+#[repr(align(64))]
+struct CacheLine {
+    _bytes: [u8; 64],
+}
+
+#[test]
+fn test_os_refused_keeps_the_call_and_errno() {
+    let bump_heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let mmap_heap = MmapHeap::new(SimulatedSource::new(0, 64 * 1024));
+
+    bump_heap
+        .memory
+        .lock()
+        .unwrap()
+        .source
+        .inject_failure(SimulatedFailure::Always(SimulatedOperation::Grow));
+    mmap_heap
+        .memory
+        .lock()
+        .unwrap()
+        .source
+        .inject_failure(SimulatedFailure::Always(SimulatedOperation::Map));
+
+    let bump_error = bump_heap.qualloc::<u64>(64).unwrap_err();
+
+    assert_eq!(
+        bump_error,
+        AllocError::OsRefused {
+            call: OsCall::Sbrk,
+            errno: libc::ENOMEM,
+        }
+    );
+    assert!(bump_error.to_string().starts_with("sbrk failed"));
+    assert_eq!(
+        mmap_heap.allocate::<u64>(64),
+        Err(AllocError::OsRefused {
+            call: OsCall::Mmap,
+            errno: libc::ENOMEM,
+        })
+    );
+    assert_eq!(bump_heap.stats().allocations, 0);
+    assert_eq!(mmap_heap.stats().allocations, 0);
+}
+
+#[test]
+fn test_size_overflow() {
+    let bump_heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let mmap_heap = MmapHeap::new(SimulatedSource::new(0, 64 * 1024));
+
+    assert_eq!(
+        bump_heap.qualloc::<u8>(i32::MAX),
+        Err(AllocError::SizeOverflow {
+            size: i32::MAX as usize,
+        })
+    );
+    assert!(matches!(
+        bump_heap.qualloc::<u8>(-1),
+        Err(AllocError::SizeOverflow { .. })
+    ));
+    assert_eq!(
+        mmap_heap.allocate::<u8>(usize::MAX),
+        Err(AllocError::SizeOverflow { size: usize::MAX })
+    );
+
+    /*
+     * Nothing was taken from the sources
+     */
+    assert_eq!(bump_heap.memory.lock().unwrap().source.break_used(), 0);
+    assert_eq!(mmap_heap.memory.lock().unwrap().source.mapped_pages(), 0);
+}
+
+#[test]
+fn test_invalid_alignment() {
+    let bump_heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let mmap_heap = MmapHeap::new(SimulatedSource::new(0, 64 * 1024));
+
+    assert_eq!(
+        bump_heap.qualloc::<CacheLine>(64),
+        Err(AllocError::InvalidAlignment { align: 64 })
+    );
+    assert_eq!(
+        mmap_heap.allocate::<CacheLine>(64),
+        Err(AllocError::InvalidAlignment { align: 64 })
+    );
+    assert!(bump_heap.qualloc::<u64>(64).is_ok());
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn test_corrupted_heap_isnt_used() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    heap.set_verify_after_operations(true);
+
+    let block = heap.qualloc::<u8>(16).unwrap();
+    let header = unsafe { (block as *mut BumpMemoryBlockHeader).sub(1) };
+    unsafe { (*header).size = 13 };
+
+    assert_eq!(
+        heap.qualloc::<u8>(16),
+        Err(AllocError::CorruptedHeap(HeapError::UnalignedSize {
+            address: header as usize,
+            size: 13,
+        }))
+    );
+    assert_eq!(heap.stats().allocations, 1);
+}
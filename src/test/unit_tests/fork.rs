@@ -0,0 +1,208 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use libc::{_exit, SIGKILL, WEXITSTATUS, WIFEXITED, WNOHANG, fork, kill, waitpid};
+
+use crate::{
+    bump::allocator::BumpAllocator,
+    error::{AllocError, OsCall},
+    fork::register_fork_handlers,
+    mmap::allocator::MmapAllocator,
+    test::GLOBAL_HEAP_LOCK,
+};
+
+/**
+ * Waits for the child process, if it doesn't exit before the timeout then it's killed because it's
+ * probably deadlocked on an allocator lock
+ *
+ * @return The exit status of the child or None if it was killed
+ */
+fn wait_child(pid: i32, timeout: Duration) -> Option<i32> {
+    let deadline = Instant::now() + timeout;
+    let mut status = 0;
+
+    loop {
+        if unsafe { waitpid(pid, &mut status, WNOHANG) } == pid {
+            return WIFEXITED(status).then(|| WEXITSTATUS(status));
+        }
+
+        if Instant::now() > deadline {
+            unsafe {
+                kill(pid, SIGKILL);
+                waitpid(pid, &mut status, 0);
+            }
+            return None;
+        }
+
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_fork_under_allocation_load() {
+    /*
+     * In this test many threads are allocating and deallocating memory while the main thread forks, the
+     * child process must be able to allocate with both allocators, if a lock was held by other thread at
+     * the moment of fork, then the child deadlocks and it will be killed by the timeout
+     */
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    let stop = Arc::new(AtomicBool::new(false));
+
+    /*
+     * Only one worker uses the bump allocator, because blocks freed in the middle of the list are kept
+     * as free blocks and other tests expect an empty bump heap, with a single thread every block is
+     * the last one when it's freed so the heap is given back to the OS
+     */
+    let workers = (0..4)
+        .map(|worker| {
+            let stop = stop.clone();
+
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if worker == 0 {
+                        let block = BumpAllocator::qualloc::<u64>(64).unwrap();
+                        unsafe { *block = 42 };
+                        BumpAllocator::qudelloc(block);
+                        continue;
+                    }
+
+                    /*
+                     * Small sizes don't take the mmap_memory lock, so big sections are used too
+                     */
+                    let size = if worker % 2 == 0 { 64 } else { 4096 };
+                    let section = MmapAllocator::allocate::<u64>(size).unwrap();
+                    unsafe { *section = 42 };
+                    MmapAllocator::deallocate(section);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for _ in 0..20 {
+        let pid = unsafe { fork() };
+        assert!(pid >= 0, "Fork must succeed");
+
+        if pid == 0 {
+            let block = BumpAllocator::qualloc::<u64>(64);
+            let section = MmapAllocator::allocate::<u64>(4096);
+
+            let code = match (block, section) {
+                (Ok(block), Ok(section)) => unsafe {
+                    *block = 7;
+                    *section = 7;
+                    BumpAllocator::qudelloc(block);
+                    MmapAllocator::deallocate(section);
+                    0
+                },
+                _ => 1,
+            };
+
+            unsafe { _exit(code) };
+        }
+
+        assert_eq!(
+            wait_child(pid, Duration::from_secs(10)),
+            Some(0),
+            "Child process must allocate after fork without deadlocking"
+        );
+    }
+
+    stop.store(true, Ordering::Relaxed);
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_fork_while_other_threads_use_malloc() {
+    /*
+     * The fork handlers keep their guards without allocating, so forking while other threads are inside
+     * malloc doesn't deadlock the parent in prepare_fork and the child can use both heaps and malloc
+     */
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    assert_eq!(register_fork_handlers(), Ok(()));
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let workers = (0..4)
+        .map(|_| {
+            let stop = stop.clone();
+
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let buffer = vec![42u8; 4096];
+                    assert_eq!(
+                        buffer.iter().map(|byte| *byte as usize).sum::<usize>(),
+                        42 * 4096
+                    );
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for _ in 0..20 {
+        let pid = unsafe { fork() };
+        assert!(pid >= 0, "Fork must succeed");
+
+        if pid == 0 {
+            let buffer = vec![7u8; 4096];
+            let section = MmapAllocator::allocate::<u64>(4096);
+            let block = BumpAllocator::qualloc::<u64>(64);
+
+            let code = match (block, section) {
+                (Ok(block), Ok(section)) if buffer[4095] == 7 => {
+                    BumpAllocator::qudelloc(block);
+                    MmapAllocator::deallocate(section);
+                    0
+                }
+                _ => 1,
+            };
+
+            unsafe { _exit(code) };
+        }
+
+        assert_eq!(
+            wait_child(pid, Duration::from_secs(10)),
+            Some(0),
+            "Child process must allocate after fork without deadlocking"
+        );
+    }
+
+    stop.store(true, Ordering::Relaxed);
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_fork_handlers_are_registered_once() {
+    assert_eq!(register_fork_handlers(), Ok(()));
+    assert_eq!(register_fork_handlers(), Ok(()));
+
+    /*
+     * pthread_atfork only fails without memory, so the report of the failure is checked apart
+     */
+    let error = AllocError::OsRefused {
+        call: OsCall::PthreadAtfork,
+        errno: libc::ENOMEM,
+    };
+
+    assert!(error.to_string().starts_with("pthread_atfork failed"));
+    assert!(error.is_out_of_memory());
+}
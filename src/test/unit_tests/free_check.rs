@@ -0,0 +1,262 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    bump::BumpHeap,
+    free_check::{FreeChecks, InvalidFreeAction, InvalidFreeKind, InvalidFreeReport},
+    mmap::{MmapHeap, allocator::MmapAllocator, globals::mmap_memory},
+    small::allocator::SmallAllocator,
+    source::simulated::SimulatedSource,
+    test::GLOBAL_HEAP_LOCK,
+    walk::HeapEntryKind,
+};
+
+/*
+ * Summary of a report, the backtraces are checked apart because they can't be compared
+ */
+type ReportSummary = (InvalidFreeKind, usize, Option<(usize, bool)>);
+
+fn collect_reports(free_checks: &FreeChecks) -> Arc<Mutex<Vec<InvalidFreeReport>>> {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let handler_reports = reports.clone();
+
+    free_checks.set_handler(Some(Arc::new(move |report: &InvalidFreeReport| {
+        handler_reports.lock().unwrap().push(InvalidFreeReport {
+            kind: report.kind,
+            ptr: report.ptr,
+            block: report.block,
+            allocation_backtrace: report.allocation_backtrace.clone(),
+            first_free_backtrace: report.first_free_backtrace.clone(),
+        })
+    })));
+
+    reports
+}
+
+fn summaries(reports: &Mutex<Vec<InvalidFreeReport>>) -> Vec<ReportSummary> {
+    reports
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|report| {
+            (
+                report.kind,
+                report.ptr,
+                report.block.map(|block| (block.usr_address, block.is_free)),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_bump_heap_invalid_frees() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let reports = collect_reports(&heap.free_checks);
+
+    let first_block = heap.qualloc::<u8>(16).unwrap();
+    let second_block = heap.qualloc::<u8>(16).unwrap();
+
+    heap.qudelloc(first_block);
+    heap.qudelloc(first_block);
+    heap.qudelloc(unsafe { second_block.add(4) });
+    heap.qudelloc(0x10 as *const u8);
+
+    assert_eq!(
+        summaries(&reports),
+        vec![
+            (
+                InvalidFreeKind::DoubleFree,
+                first_block as usize,
+                Some((first_block as usize, true))
+            ),
+            (
+                InvalidFreeKind::InteriorPointer,
+                second_block as usize + 4,
+                Some((second_block as usize, false))
+            ),
+            (InvalidFreeKind::ForeignPointer, 0x10, None),
+        ]
+    );
+
+    /*
+     * Invalid frees don't change the heap
+     */
+    assert_eq!(heap.stats().frees, 1);
+    assert_eq!(heap.verify(), Ok(()));
+
+    heap.teardown();
+    heap.qudelloc(second_block);
+
+    assert_eq!(
+        summaries(&reports).last(),
+        Some(&(InvalidFreeKind::AfterTeardown, second_block as usize, None))
+    );
+    assert_eq!(heap.stats().heap_size, 0);
+}
+
+#[test]
+fn test_mmap_heap_invalid_frees() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+    let reports = collect_reports(&heap.free_checks);
+
+    let first_section = heap.allocate::<u8>(1000).unwrap();
+    let second_section = heap.allocate::<u8>(5000).unwrap();
+
+    heap.deallocate(unsafe { second_section.add(100) });
+    heap.deallocate(0x10 as *const u8);
+
+    assert_eq!(
+        summaries(&reports),
+        vec![
+            (
+                InvalidFreeKind::InteriorPointer,
+                second_section as usize + 100,
+                Some((second_section as usize, false))
+            ),
+            (InvalidFreeKind::ForeignPointer, 0x10, None),
+        ]
+    );
+
+    /*
+     * The tail of a region isn't inside any section, so the report gives the region
+     */
+    heap.deallocate(unsafe { first_section.add(2000) });
+
+    let report = reports.lock().unwrap().pop().unwrap();
+
+    assert_eq!(report.kind, InvalidFreeKind::InteriorPointer);
+    assert_eq!(report.block.unwrap().kind, HeapEntryKind::Region);
+
+    heap.teardown();
+    heap.deallocate(first_section);
+
+    assert_eq!(
+        summaries(&reports).last(),
+        Some(&(InvalidFreeKind::AfterTeardown, first_section as usize, None))
+    );
+    assert_eq!(heap.stats().heap_size, 0);
+
+    /*
+     * The heap can be used again after teardown
+     */
+    let section = heap.allocate::<u8>(1000).unwrap();
+    heap.deallocate(section);
+    assert_eq!(reports.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn test_double_free_report_backtraces() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+    let reports = collect_reports(&heap.free_checks);
+    heap.free_checks.set_backtraces(true);
+
+    /*
+     * The region of the section is unmapped on the first free, so the double free is found with the
+     * backtraces of the deallocated pointers
+     */
+    let section = heap.allocate::<u8>(1000).unwrap();
+    heap.deallocate(section);
+    heap.deallocate(section);
+
+    let reports = reports.lock().unwrap();
+
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].kind, InvalidFreeKind::DoubleFree);
+    assert!(reports[0].allocation_backtrace.is_some());
+    assert!(reports[0].first_free_backtrace.is_some());
+    assert!(reports[0].to_string().starts_with("double free"));
+}
+
+#[test]
+fn test_handler_can_change_the_handler() {
+    let heap = Arc::new(BumpHeap::new(SimulatedSource::new(64 * 1024, 0)));
+    let kinds = Arc::new(Mutex::new(Vec::new()));
+    let handler_heap = heap.clone();
+    let handler_kinds = kinds.clone();
+
+    /*
+     * The handler is called without its lock, so it can replace itself and report again
+     */
+    heap.free_checks
+        .set_handler(Some(Arc::new(move |report: &InvalidFreeReport| {
+            let next_kinds = handler_kinds.clone();
+
+            handler_kinds.lock().unwrap().push(report.kind);
+            handler_heap.free_checks.set_handler(Some(Arc::new(
+                move |report: &InvalidFreeReport| next_kinds.lock().unwrap().push(report.kind),
+            )));
+            handler_heap.qudelloc(report.ptr as *const u8);
+        })));
+
+    let first_block = heap.qualloc::<u8>(16).unwrap();
+    let second_block = heap.qualloc::<u8>(16).unwrap();
+
+    heap.qudelloc(first_block);
+    heap.qudelloc(first_block);
+
+    assert_eq!(
+        *kinds.lock().unwrap(),
+        vec![InvalidFreeKind::DoubleFree, InvalidFreeKind::DoubleFree]
+    );
+
+    heap.qudelloc(second_block);
+    heap.free_checks.set_handler(None);
+}
+
+#[test]
+fn test_ignored_invalid_frees() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let reports = collect_reports(&heap.free_checks);
+    heap.free_checks.set_action(InvalidFreeAction::Ignore);
+
+    let block = heap.qualloc::<u8>(16).unwrap();
+    heap.qudelloc(unsafe { block.add(1) });
+
+    assert!(reports.lock().unwrap().is_empty());
+    assert_eq!(heap.free_checks.action(), InvalidFreeAction::Ignore);
+}
+
+#[test]
+fn test_small_object_invalid_frees() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let reports = collect_reports(&mmap_memory.free_checks);
+
+    let first_object = MmapAllocator::allocate::<u8>(32).unwrap();
+    let second_object = MmapAllocator::allocate::<u8>(32).unwrap();
+
+    assert!(SmallAllocator::object_entry(first_object).is_some());
+
+    MmapAllocator::deallocate(first_object);
+    MmapAllocator::deallocate(first_object);
+    MmapAllocator::deallocate(unsafe { second_object.add(8) });
+
+    assert_eq!(
+        summaries(&reports),
+        vec![
+            (
+                InvalidFreeKind::DoubleFree,
+                first_object as usize,
+                Some((first_object as usize, true))
+            ),
+            (
+                InvalidFreeKind::InteriorPointer,
+                second_object as usize + 8,
+                Some((second_object as usize, false))
+            ),
+        ]
+    );
+
+    /*
+     * The backtraces need the header of the section, so the small allocator isn't used while they are enabled
+     */
+    MmapAllocator::set_free_backtraces(true);
+    let section = MmapAllocator::allocate::<u8>(32).unwrap();
+    MmapAllocator::set_free_backtraces(false);
+
+    assert!(SmallAllocator::object_entry(section).is_none());
+
+    MmapAllocator::deallocate(section);
+    MmapAllocator::deallocate(second_object);
+    MmapAllocator::set_invalid_free_handler(None);
+}
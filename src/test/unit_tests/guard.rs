@@ -0,0 +1,161 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use libc::{_exit, SIGBUS, SIGSEGV, WIFSIGNALED, WTERMSIG, fork, waitpid};
+
+use crate::{
+    free_check::{InvalidFreeKind, InvalidFreeReport},
+    guard::{GuardOptions, allocator::GuardAllocator},
+    hooks::{EventKind, HeapEvent},
+    mmap::{allocator::MmapAllocator, globals::mmap_memory, utils::get_page_size},
+    test::GLOBAL_HEAP_LOCK,
+};
+
+/**
+ * Runs the access in a child process
+ *
+ * @return True if the child was killed by a memory fault.
+ */
+fn access_faults(access: impl FnOnce()) -> bool {
+    unsafe {
+        let pid = fork();
+
+        if pid == 0 {
+            access();
+            _exit(0);
+        }
+
+        let mut status = 0;
+        waitpid(pid, &mut status, 0);
+
+        WIFSIGNALED(status) && (WTERMSIG(status) == SIGSEGV || WTERMSIG(status) == SIGBUS)
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_guard_pages() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let page_size = get_page_size();
+
+    MmapAllocator::enable_guard_pages(GuardOptions {
+        leading_guard: true,
+        free_delay: Duration::from_secs(60),
+    });
+
+    let small = MmapAllocator::allocate::<u8>(100).unwrap();
+    let page = MmapAllocator::allocate::<u8>(page_size).unwrap();
+
+    MmapAllocator::disable_guard_pages();
+
+    /*
+     * The user data ends just before the trailing guard page
+     */
+    assert_eq!((small as usize + 100) % page_size, 0);
+    unsafe { small.write_bytes(1, 100) };
+    assert!(!access_faults(|| unsafe { small.add(99).write(0) }));
+    assert!(access_faults(|| unsafe { small.add(100).write(0) }));
+
+    /*
+     * When the user data fills its pages, the byte before it is in the leading guard page
+     */
+    assert!(access_faults(|| unsafe { page.sub(1).write(0) }));
+
+    MmapAllocator::deallocate(small);
+    assert!(access_faults(|| unsafe {
+        small.read_volatile();
+    }));
+
+    MmapAllocator::deallocate(page);
+    GuardAllocator::release_freed();
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_guard_pages_invalid_frees() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let handler_reports = reports.clone();
+
+    mmap_memory
+        .free_checks
+        .set_handler(Some(Arc::new(move |report: &InvalidFreeReport| {
+            handler_reports.lock().unwrap().push(report.kind)
+        })));
+    MmapAllocator::enable_guard_pages(GuardOptions::default());
+
+    let data = MmapAllocator::allocate::<u64>(64).unwrap();
+
+    MmapAllocator::disable_guard_pages();
+
+    MmapAllocator::deallocate(unsafe { data.add(1) });
+    MmapAllocator::deallocate(data);
+    MmapAllocator::deallocate(data);
+
+    GuardAllocator::release_freed();
+    mmap_memory.free_checks.set_handler(None);
+
+    assert_eq!(
+        *reports.lock().unwrap(),
+        vec![
+            InvalidFreeKind::InteriorPointer,
+            InvalidFreeKind::DoubleFree
+        ]
+    );
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_guard_pages_send_events() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let events = Arc::new(Mutex::new(Vec::<HeapEvent>::new()));
+    let hook_events = events.clone();
+
+    MmapAllocator::set_event_hook(Some(Arc::new(move |event| {
+        hook_events.lock().unwrap().push(*event)
+    })));
+    MmapAllocator::enable_guard_pages(GuardOptions {
+        leading_guard: false,
+        free_delay: Duration::ZERO,
+    });
+
+    let data = MmapAllocator::allocate::<u8>(100).unwrap();
+
+    MmapAllocator::disable_guard_pages();
+
+    /*
+     * Without a free delay the mapping is unmapped by the free
+     */
+    MmapAllocator::deallocate(data);
+    MmapAllocator::set_event_hook(None);
+
+    let events = events.lock().unwrap();
+    let kinds: Vec<EventKind> = events.iter().map(|event| event.kind).collect();
+
+    assert_eq!(
+        kinds,
+        vec![
+            EventKind::RegionMap,
+            EventKind::Alloc,
+            EventKind::Free,
+            EventKind::RegionUnmap
+        ]
+    );
+    assert_eq!((events[1].address, events[1].size), (data as usize, 100));
+    assert_eq!((events[2].address, events[2].size), (data as usize, 100));
+    assert_eq!(
+        events[3],
+        HeapEvent {
+            kind: EventKind::RegionUnmap,
+            ..events[0]
+        }
+    );
+}
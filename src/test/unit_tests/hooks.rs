@@ -0,0 +1,109 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    bump::BumpHeap,
+    hooks::{EventKind, HeapEvent},
+    mmap::MmapHeap,
+    source::simulated::SimulatedSource,
+};
+
+#[test]
+fn test_bump_heap_sends_events_in_order() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let events = Arc::new(Mutex::new(Vec::<HeapEvent>::new()));
+    let hook_events = events.clone();
+
+    heap.hooks.set_hook(Some(Arc::new(move |event| {
+        hook_events.lock().unwrap().push(*event)
+    })));
+
+    let block = heap.qualloc::<u8>(64).unwrap();
+    heap.qudelloc(block);
+
+    let kinds = events
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|event| event.kind)
+        .collect::<Vec<_>>();
+
+    /*
+     * The break events are kept under the heap lock and sent before the event of the operation
+     */
+    assert_eq!(
+        kinds,
+        vec![
+            EventKind::BreakGrow,
+            EventKind::Alloc,
+            EventKind::Free,
+            EventKind::BreakShrink,
+        ]
+    );
+
+    let events = events.lock().unwrap();
+    assert_eq!(events[1].address, block as usize);
+    assert_eq!(events[1].size, 64);
+    assert_eq!(events[2].address, block as usize);
+    assert_eq!(events[2].size, 64);
+}
+
+#[test]
+fn test_mmap_heap_sends_region_events() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 64 * 1024));
+    let events = Arc::new(Mutex::new(Vec::<HeapEvent>::new()));
+    let hook_events = events.clone();
+
+    heap.hooks.set_hook(Some(Arc::new(move |event| {
+        hook_events.lock().unwrap().push(*event)
+    })));
+
+    let section = heap.allocate::<u8>(1000).unwrap();
+    heap.deallocate(section);
+
+    let events = events.lock().unwrap();
+    let region_map = events
+        .iter()
+        .find(|event| event.kind == EventKind::RegionMap)
+        .unwrap();
+    let region_unmap = events
+        .iter()
+        .find(|event| event.kind == EventKind::RegionUnmap)
+        .unwrap();
+
+    assert_eq!(region_map.address, region_unmap.address);
+    assert_eq!(region_map.size, region_unmap.size);
+    assert_eq!(heap.stats().heap_size, 0);
+}
+
+#[test]
+fn test_hook_can_use_the_heap() {
+    let heap = Arc::new(MmapHeap::new(SimulatedSource::new(0, 64 * 1024)));
+    let events = Arc::new(Mutex::new(Vec::<HeapEvent>::new()));
+    let hook_heap = heap.clone();
+    let hook_events = events.clone();
+
+    heap.hooks.set_hook(Some(Arc::new(move |event| {
+        /*
+         * The allocations of the hook don't send events, so the hook doesn't call itself
+         */
+        let scratch = hook_heap.allocate::<u8>(32).unwrap();
+        hook_heap.deallocate(scratch);
+
+        hook_events.lock().unwrap().push(*event);
+    })));
+
+    let section = heap.allocate::<u8>(100).unwrap();
+    heap.deallocate(section);
+
+    let user_events = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|event| matches!(event.kind, EventKind::Alloc | EventKind::Free))
+        .count();
+
+    assert_eq!(user_events, 2);
+
+    heap.hooks.set_hook(None);
+    assert!(!heap.hooks.is_enabled());
+}
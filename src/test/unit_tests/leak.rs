@@ -0,0 +1,161 @@
+use crate::{
+    bump::{BumpHeap, allocator::BumpAllocator},
+    leak::LeakChecker,
+    mmap::{MmapHeap, allocator::MmapAllocator},
+    small::allocator::SmallAllocator,
+    source::simulated::SimulatedSource,
+    test::GLOBAL_HEAP_LOCK,
+};
+
+#[inline(never)]
+fn allocate_intentional_leak(heap: &MmapHeap<SimulatedSource>) -> *mut u8 {
+    heap.allocate::<u8>(64).unwrap()
+}
+
+#[test]
+fn test_bump_heap_leaks() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let first_block = heap.qualloc::<u8>(16).unwrap();
+    let second_block = heap.qualloc::<u8>(32).unwrap();
+    let third_block = heap.qualloc::<u8>(48).unwrap();
+
+    heap.qudelloc(second_block);
+
+    let report = heap.leaks();
+
+    assert_eq!((report.count, report.bytes), (2, 64));
+    assert_eq!(report.groups.len(), 1);
+    assert_eq!(report.groups[0].call_site, None);
+    assert!(
+        report
+            .to_string()
+            .starts_with("64 bytes leaked in 2 blocks")
+    );
+
+    /*
+     * A suppressed pointer is checked again when its block is reused by other allocation
+     */
+    heap.leak_suppressions.suppress(first_block as usize);
+    assert_eq!(heap.leaks().count, 1);
+
+    heap.qudelloc(first_block);
+    assert_eq!(heap.qualloc::<u8>(16).unwrap(), first_block);
+    assert_eq!(heap.leaks().count, 2);
+
+    heap.qudelloc(first_block);
+    heap.qudelloc(third_block);
+    assert!(heap.leaks().is_empty());
+}
+
+#[test]
+fn test_mmap_heap_leak_call_sites() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 64 * 4096));
+
+    heap.free_checks.set_backtraces(true);
+
+    /*
+     * Blocks are grouped by their whole backtrace, so the intentional leaks are done from the same line
+     */
+    let intentional_leaks: Vec<*mut u8> =
+        (0..2).map(|_| allocate_intentional_leak(&heap)).collect();
+    let leak = heap.allocate::<u8>(200).unwrap();
+    let report = heap.leaks();
+
+    assert_eq!((report.count, report.bytes), (3, 328));
+    assert_eq!(report.groups.len(), 2);
+    assert_eq!(report.groups[0].blocks, vec![leak as usize]);
+    assert_eq!(report.groups[1].count, 2);
+    assert!(
+        report.groups[1]
+            .call_site
+            .as_ref()
+            .unwrap()
+            .contains("allocate_intentional_leak")
+    );
+
+    heap.leak_suppressions
+        .suppress_call_site("allocate_intentional_leak");
+
+    let report = heap.leaks();
+
+    assert_eq!(report.count, 1);
+    assert_eq!(report.groups[0].blocks, vec![leak as usize]);
+
+    heap.leak_suppressions.clear();
+    heap.deallocate(leak);
+
+    for intentional_leak in intentional_leaks {
+        heap.deallocate(intentional_leak);
+    }
+
+    assert!(heap.leaks().is_empty());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_leak_scope() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    let allocated_before = BumpAllocator::qualloc::<u8>(64).unwrap();
+    let scope = LeakChecker::scope();
+    let leak = BumpAllocator::qualloc::<u8>(64).unwrap();
+    let report = scope.check();
+
+    assert!(
+        report
+            .groups
+            .iter()
+            .any(|group| group.blocks.contains(&(leak as usize)))
+    );
+    assert!(
+        !report
+            .groups
+            .iter()
+            .any(|group| group.blocks.contains(&(allocated_before as usize)))
+    );
+
+    LeakChecker::suppress(leak);
+    assert!(
+        !LeakChecker::check()
+            .groups
+            .iter()
+            .any(|group| group.blocks.contains(&(leak as usize)))
+    );
+    LeakChecker::clear_suppressions();
+
+    /*
+     * Dropping the scope while the leak is alive fails
+     */
+    assert!(std::panic::catch_unwind(move || drop(scope)).is_err());
+
+    BumpAllocator::qudelloc(leak);
+    BumpAllocator::qudelloc(allocated_before);
+}
+
+#[test]
+fn test_small_object_leaks() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    let leak = MmapAllocator::allocate::<u8>(24).unwrap();
+    let report = LeakChecker::check();
+
+    assert!(SmallAllocator::object_entry(leak).is_some());
+    assert!(
+        report
+            .groups
+            .iter()
+            .any(|group| group.blocks.contains(&(leak as usize)))
+    );
+
+    MmapAllocator::deallocate(leak);
+    assert!(
+        !LeakChecker::check()
+            .groups
+            .iter()
+            .any(|group| group.blocks.contains(&(leak as usize)))
+    );
+}
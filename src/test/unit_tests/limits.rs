@@ -0,0 +1,229 @@
+use std::{fs, path::PathBuf, sync::atomic::Ordering};
+
+use crate::{
+    bump::BumpHeap,
+    limits::{AutoTuning, LimitsProbe, MemoryLimits},
+    mmap::{MmapHeap, MmapMemorySectionHeader},
+    source::simulated::SimulatedSource,
+};
+
+const MIB: usize = 1024 * 1024;
+
+/**
+ * Creates a fake filesystem root with the given files, the paths are relative to the root
+ */
+fn fake_root(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("qualloc-{name}-{}", std::process::id()));
+
+    for (path, content) in files {
+        let path = root.join(path);
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    root
+}
+
+fn probe(root: PathBuf) -> MemoryLimits {
+    let limits = LimitsProbe {
+        root: root.clone(),
+        read_rlimits: false,
+    }
+    .probe();
+
+    fs::remove_dir_all(root).ok();
+    limits
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_cgroup_v2_limit() {
+    let root = fake_root(
+        "cgroup-v2",
+        &[
+            ("proc/self/cgroup", "0::/app/worker\n"),
+            ("sys/fs/cgroup/memory.max", "max\n"),
+            ("sys/fs/cgroup/app/memory.max", "536870912\n"),
+            ("sys/fs/cgroup/app/worker/memory.max", "max\n"),
+        ],
+    );
+
+    /*
+     * The worker doesn't have a limit, but its parent does
+     */
+    assert_eq!(
+        probe(root),
+        MemoryLimits {
+            cgroup: Some(512 * MIB),
+            rlimit_as: None,
+            rlimit_data: None,
+        }
+    );
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_cgroup_v1_limit() {
+    let root = fake_root(
+        "cgroup-v1",
+        &[
+            (
+                "proc/self/cgroup",
+                "5:cpu,cpuacct:/docker/abc\n4:memory:/docker/abc\n",
+            ),
+            (
+                "sys/fs/cgroup/memory/memory.limit_in_bytes",
+                "9223372036854771712\n",
+            ),
+            (
+                "sys/fs/cgroup/memory/docker/abc/memory.limit_in_bytes",
+                "268435456\n",
+            ),
+        ],
+    );
+
+    assert_eq!(probe(root).cgroup, Some(256 * MIB));
+    assert_eq!(
+        probe(fake_root("cgroup-none", &[])),
+        MemoryLimits::default()
+    );
+}
+
+#[test]
+fn test_tuning_follows_the_limit() {
+    let small = AutoTuning::from_limits(MemoryLimits {
+        cgroup: Some(256 * MIB),
+        rlimit_as: None,
+        rlimit_data: Some(1024 * MIB),
+    });
+
+    assert_eq!(small.soft_limit, Some(192 * MIB));
+    assert_eq!(small.low_watermark, 128 * MIB);
+    assert_eq!(small.min_region_size, 256 * 1024);
+    assert_eq!(small.quarantine_size, 256 * 1024);
+    assert_eq!(small.trim_threshold, 512 * 1024);
+
+    let big = AutoTuning::from_limits(MemoryLimits {
+        cgroup: Some(64 * 1024 * MIB),
+        rlimit_as: None,
+        rlimit_data: None,
+    });
+
+    assert_eq!(big.min_region_size, 4 * MIB);
+    assert_eq!(big.quarantine_size, 16 * MIB);
+    assert_eq!(big.trim_threshold, 8 * MIB);
+
+    let unlimited = AutoTuning::from_limits(MemoryLimits::default());
+
+    assert_eq!(unlimited.soft_limit, None);
+    assert_eq!(unlimited.trim_threshold, 0);
+}
+
+#[test]
+fn test_tuned_heap_shares_regions() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 1024 * 1024));
+    let tuning = AutoTuning::from_limits(MemoryLimits {
+        cgroup: Some(64 * MIB),
+        rlimit_as: None,
+        rlimit_data: None,
+    });
+
+    heap.apply_tuning(tuning);
+
+    /*
+     * The first region is mapped with the minimum region size, so the second section fits in it
+     */
+    let first_section = heap.allocate::<u64>(1000).unwrap();
+    let second_section = heap.allocate::<u64>(1000).unwrap();
+    let stats = heap.stats();
+
+    assert_eq!(stats.regions, 1);
+    assert_eq!(stats.heap_size, 64 * 1024);
+    assert_eq!(stats.tuning, Some(tuning));
+    assert_eq!(heap.watermarks.high(), Some(48 * MIB));
+
+    /*
+     * The empty region is smaller than the trim threshold, so it stays mapped
+     */
+    heap.deallocate(second_section);
+    heap.deallocate(first_section);
+    assert_eq!(heap.stats().regions, 1);
+}
+
+#[test]
+fn test_sections_are_appended_to_the_region() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 1024 * 1024));
+
+    heap.min_region_size.store(64 * 1024, Ordering::Relaxed);
+
+    /*
+     * The sections that don't fit in a free section are placed after the last one, with aligned headers
+     */
+    let sections: Vec<_> = [13, 100, 1000]
+        .into_iter()
+        .map(|size| heap.allocate::<u8>(size).unwrap())
+        .collect();
+    let section_header_size = MmapMemorySectionHeader::size();
+
+    assert_eq!(heap.stats().regions, 1);
+    assert_eq!(
+        sections[1] as usize,
+        sections[0] as usize + 16 + section_header_size
+    );
+    assert!(
+        sections
+            .iter()
+            .all(|section| (*section as usize - section_header_size)
+                % align_of::<MmapMemorySectionHeader>()
+                == 0)
+    );
+
+    /*
+     * A section bigger than the space left after the last section takes a new region
+     */
+    let big_section = heap.allocate::<u8>(64 * 1024).unwrap();
+
+    assert_eq!(heap.stats().regions, 2);
+    assert_eq!(heap.verify(), Ok(()));
+
+    heap.deallocate(big_section);
+    sections
+        .into_iter()
+        .for_each(|section| heap.deallocate(section));
+}
+
+#[test]
+fn test_tuned_heaps_keep_free_memory_under_the_trim_threshold() {
+    let bump_heap = BumpHeap::new(SimulatedSource::new(1024 * 1024, 0));
+    let mmap_heap = MmapHeap::new(SimulatedSource::new(0, 1024 * 1024));
+    let tuning = AutoTuning::from_limits(MemoryLimits {
+        cgroup: Some(64 * MIB),
+        rlimit_as: None,
+        rlimit_data: None,
+    });
+
+    bump_heap.apply_tuning(tuning);
+    mmap_heap.apply_tuning(tuning);
+
+    assert_eq!(bump_heap.trim_threshold.load(Ordering::Relaxed), 128 * 1024);
+    assert_eq!(mmap_heap.trim_threshold.load(Ordering::Relaxed), 128 * 1024);
+
+    /*
+     * The free memory stays in the heaps for the next allocations until the heaps are trimmed
+     */
+    let block = bump_heap.qualloc::<u64>(1000).unwrap();
+    let section = mmap_heap.allocate::<u64>(1000).unwrap();
+
+    bump_heap.qudelloc(block);
+    mmap_heap.deallocate(section);
+
+    assert_ne!(bump_heap.stats().heap_size, 0);
+    assert_eq!(mmap_heap.stats().regions, 1);
+
+    bump_heap.trim();
+    mmap_heap.trim();
+
+    assert_eq!(bump_heap.stats().heap_size, 0);
+    assert_eq!(mmap_heap.stats().regions, 0);
+}
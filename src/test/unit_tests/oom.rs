@@ -0,0 +1,110 @@
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    bump::BumpHeap,
+    error::{AllocError, OsCall},
+    mmap::MmapHeap,
+    oom::{MAX_OOM_RETRIES, OomAction, OomReport},
+    quota::QuotaKind,
+    source::simulated::SimulatedSource,
+};
+
+#[test]
+fn test_emergency_reserve_is_released_on_first_oom() {
+    let heap = BumpHeap::new(SimulatedSource::new(4096, 0));
+    let reports = Arc::new(Mutex::new(Vec::<OomReport>::new()));
+    let handler_reports = reports.clone();
+
+    heap.oom.set_handler(Some(Arc::new(move |report| {
+        handler_reports.lock().unwrap().push(*report);
+        OomAction::Fail
+    })));
+    heap.set_emergency_reserve(1024).unwrap();
+
+    assert_eq!(heap.oom.reserve_size(), 1024);
+    assert!(heap.leaks().is_empty());
+
+    heap.qualloc::<u64>(2048).unwrap();
+
+    /*
+     * The reserve is a free block after the first failure, so the retry reuses it
+     */
+    assert!(heap.qualloc::<u64>(1024).is_ok());
+    assert_eq!(heap.oom.reserve_size(), 0);
+    assert_eq!(
+        heap.qualloc::<u64>(1024),
+        Err(AllocError::OsRefused {
+            call: OsCall::Sbrk,
+            errno: libc::ENOMEM,
+        })
+    );
+
+    let reports = reports.lock().unwrap();
+
+    assert_eq!(reports.len(), 2);
+    assert!(reports[0].reserve_released);
+    assert_eq!(reports[0].requested, 1024);
+    assert!(!reports[1].reserve_released);
+    assert_eq!(heap.oom.events(), 2);
+}
+
+#[test]
+fn test_oom_handler_can_free_memory_and_retry() {
+    let heap = Arc::new(MmapHeap::new(SimulatedSource::new(0, 64 * 1024)));
+    let cache = Arc::new(Mutex::new(Vec::<usize>::new()));
+    let handler_heap = heap.clone();
+    let handler_cache = cache.clone();
+
+    heap.quota.set_limit(Some(8192), QuotaKind::OsBytes);
+    heap.oom.set_handler(Some(Arc::new(move |report| {
+        match handler_cache.lock().unwrap().pop() {
+            Some(section) if report.attempt == 1 => {
+                handler_heap.deallocate(section as *const u8);
+                OomAction::Retry
+            }
+            _ => OomAction::Fail,
+        }
+    })));
+
+    let section = heap.allocate::<u64>(5000).unwrap();
+    cache.lock().unwrap().push(section as usize);
+
+    /*
+     * The handler runs without the heap lock, so it can deallocate the cached section before the retry
+     */
+    let other_section = heap.allocate::<u64>(5000).unwrap();
+
+    assert!(cache.lock().unwrap().is_empty());
+    assert_eq!(heap.stats().regions, 1);
+    assert_eq!(heap.oom.events(), 1);
+
+    heap.deallocate(other_section);
+}
+
+#[test]
+fn test_oom_retries_are_bounded() {
+    let heap = BumpHeap::new(SimulatedSource::new(4096, 0));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler_calls = calls.clone();
+
+    heap.oom.set_handler(Some(Arc::new(move |_| {
+        handler_calls.fetch_add(1, Ordering::Relaxed);
+        OomAction::Retry
+    })));
+
+    assert!(heap.qualloc::<u64>(8192).is_err());
+    assert_eq!(calls.load(Ordering::Relaxed), MAX_OOM_RETRIES);
+    assert_eq!(heap.oom.events(), MAX_OOM_RETRIES + 1);
+
+    /*
+     * Errors that freeing memory can't fix don't reach the handler
+     */
+    assert!(matches!(
+        heap.qualloc::<u64>(-1),
+        Err(AllocError::SizeOverflow { .. })
+    ));
+    assert_eq!(heap.oom.events(), MAX_OOM_RETRIES + 1);
+}
@@ -0,0 +1,158 @@
+use crate::{
+    bump::BumpHeap,
+    error::{AllocError, OsCall},
+    mmap::MmapHeap,
+    source::{
+        MemorySource,
+        mmap::MmapSource,
+        sbrk::SbrkSource,
+        simulated::{SimulatedFailure, SimulatedOperation, SimulatedSource},
+    },
+};
+
+#[test]
+fn test_system_sources_report_errno() {
+    /*
+     * sbrk returns (void *)-1 when the break can't grow, so the failure must not be taken as a new break
+     */
+    assert_eq!(
+        SbrkSource {}.grow(1 << 62),
+        Err(AllocError::OsRefused {
+            call: OsCall::Sbrk,
+            errno: libc::ENOMEM,
+        })
+    );
+    assert_eq!(
+        MmapSource {}.unmap(1 as *mut u8, 4096),
+        Err(AllocError::OsRefused {
+            call: OsCall::Munmap,
+            errno: libc::EINVAL,
+        })
+    );
+}
+
+#[test]
+fn test_bump_shrink_failure_keeps_the_block() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+    let first_block = heap.qualloc::<u64>(64).unwrap();
+    let last_block = heap.qualloc::<u64>(64).unwrap();
+    let break_used = heap.memory.lock().unwrap().source.break_used();
+
+    heap.memory
+        .lock()
+        .unwrap()
+        .source
+        .inject_failure(SimulatedFailure::Nth(SimulatedOperation::Shrink, 1));
+    heap.qudelloc(last_block);
+
+    let stats = heap.stats();
+
+    assert_eq!(heap.memory.lock().unwrap().source.break_used(), break_used);
+    assert_eq!(heap.verify(), Ok(()));
+    assert_eq!(stats.blocks, 2);
+    assert_eq!(stats.heap_size, break_used);
+    assert_eq!(stats.os_failures, 1);
+    assert_eq!(
+        stats.last_os_error,
+        Some(AllocError::OsRefused {
+            call: OsCall::Sbrk,
+            errno: libc::ENOMEM,
+        })
+    );
+
+    /*
+     * The block stays at the end of the heap, so it's reused and given back on the next free
+     */
+    assert_eq!(heap.qualloc::<u64>(64), Ok(last_block));
+
+    heap.qudelloc(last_block);
+    heap.qudelloc(first_block);
+
+    assert_eq!(heap.memory.lock().unwrap().source.break_used(), 0);
+    assert_eq!(heap.stats().heap_size, 0);
+}
+
+#[test]
+fn test_mmap_unmap_failure_keeps_the_region() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 64 * 1024));
+
+    let section = heap.allocate::<u64>(5000).unwrap();
+    let mapped_pages = heap.memory.lock().unwrap().source.mapped_pages();
+
+    heap.memory
+        .lock()
+        .unwrap()
+        .source
+        .inject_failure(SimulatedFailure::Always(SimulatedOperation::Unmap));
+    heap.deallocate(section);
+
+    let stats = heap.stats();
+
+    assert_eq!(
+        heap.memory.lock().unwrap().source.mapped_pages(),
+        mapped_pages
+    );
+    assert_eq!(heap.verify(), Ok(()));
+    assert_eq!(stats.regions, 1);
+    assert_eq!(stats.bytes_in_use, 0);
+    assert_eq!(stats.os_failures, 1);
+    assert_eq!(
+        stats.last_os_error,
+        Some(AllocError::OsRefused {
+            call: OsCall::Munmap,
+            errno: libc::ENOMEM,
+        })
+    );
+    assert_eq!(heap.allocate::<u64>(5000), Ok(section));
+
+    heap.memory.lock().unwrap().source.clear_failures();
+    heap.deallocate(section);
+
+    assert_eq!(heap.memory.lock().unwrap().source.mapped_pages(), 0);
+    assert_eq!(heap.stats().regions, 0);
+}
+
+#[test]
+fn test_teardown_keeps_what_cant_be_given_back() {
+    let bump_heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let mmap_heap = MmapHeap::new(SimulatedSource::new(0, 64 * 1024));
+
+    for _ in 0..3 {
+        bump_heap.qualloc::<u64>(64).unwrap();
+        mmap_heap.allocate::<u64>(5000).unwrap();
+    }
+
+    bump_heap
+        .memory
+        .lock()
+        .unwrap()
+        .source
+        .inject_failure(SimulatedFailure::Nth(SimulatedOperation::Shrink, 2));
+    mmap_heap
+        .memory
+        .lock()
+        .unwrap()
+        .source
+        .inject_failure(SimulatedFailure::Nth(SimulatedOperation::Unmap, 2));
+    bump_heap.teardown();
+    mmap_heap.teardown();
+
+    let bump_stats = bump_heap.stats();
+    let mmap_stats = mmap_heap.stats();
+
+    /*
+     * The bump heap gives back its last block and stops at the one that failed, the mmap heap only
+     * keeps the region that failed
+     */
+    assert_eq!(bump_heap.verify(), Ok(()));
+    assert_eq!(bump_stats.blocks, 2);
+    assert_eq!(bump_stats.bytes_in_use, 0);
+    assert_eq!(mmap_heap.verify(), Ok(()));
+    assert_eq!(mmap_stats.regions, 1);
+    assert_eq!(mmap_stats.bytes_in_use, 0);
+    assert_eq!(
+        mmap_heap.memory.lock().unwrap().source.mapped_pages() * 4096,
+        mmap_stats.heap_size
+    );
+}
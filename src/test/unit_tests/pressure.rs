@@ -0,0 +1,221 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    bump::BumpHeap,
+    mmap::{MmapHeap, allocator::MmapAllocator},
+    pressure::{PressureLevel, PressureReport},
+    quota::QuotaKind,
+    small::{SMALL_SLAB_SIZE, allocator::SmallAllocator},
+    source::simulated::{SimulatedFailure, SimulatedOperation, SimulatedSource},
+    test::GLOBAL_HEAP_LOCK,
+};
+
+#[test]
+fn test_watermarks_are_reported_once() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let reports = Arc::new(Mutex::new(Vec::<PressureReport>::new()));
+    let handler_reports = reports.clone();
+
+    heap.watermarks.set(Some(100), 40, QuotaKind::UserBytes);
+    heap.watermarks.set_handler(Some(Arc::new(move |report| {
+        handler_reports.lock().unwrap().push(*report)
+    })));
+
+    let first_block = heap.qualloc::<u8>(64).unwrap();
+    let second_block = heap.qualloc::<u8>(64).unwrap();
+    let third_block = heap.qualloc::<u8>(64).unwrap();
+
+    assert!(heap.watermarks.is_under_pressure());
+
+    heap.qudelloc(third_block);
+    heap.qudelloc(second_block);
+    heap.qudelloc(first_block);
+
+    /*
+     * The third allocation doesn't report the high watermark again
+     */
+    assert_eq!(
+        *reports.lock().unwrap(),
+        vec![
+            PressureReport {
+                level: PressureLevel::High,
+                used: 128,
+                watermark: 100,
+            },
+            PressureReport {
+                level: PressureLevel::Low,
+                used: 0,
+                watermark: 40,
+            },
+        ]
+    );
+    assert!(!heap.watermarks.is_under_pressure());
+}
+
+#[test]
+fn test_pressure_handler_can_free_memory() {
+    let heap = Arc::new(MmapHeap::new(SimulatedSource::new(0, 64 * 1024)));
+    let cache = Arc::new(Mutex::new(Vec::<usize>::new()));
+    let handler_heap = heap.clone();
+    let handler_cache = cache.clone();
+
+    heap.watermarks.set(Some(4096), 1024, QuotaKind::UserBytes);
+    heap.watermarks.set_handler(Some(Arc::new(move |report| {
+        if report.level == PressureLevel::High {
+            for section in handler_cache.lock().unwrap().drain(..) {
+                handler_heap.deallocate(section as *const u8);
+            }
+        }
+    })));
+
+    for _ in 0..8 {
+        let section = heap.allocate::<u8>(1000).unwrap();
+        cache.lock().unwrap().push(section as usize);
+    }
+
+    /*
+     * The handler runs without the heap lock, so it empties the cache without deadlocking
+     */
+    assert!(cache.lock().unwrap().len() < 8);
+    assert!(heap.stats().bytes_in_use <= 4096);
+
+    for section in cache.lock().unwrap().drain(..) {
+        heap.deallocate(section as *const u8);
+    }
+
+    assert_eq!(heap.stats().regions, 0);
+}
+
+#[test]
+fn test_auto_trim_gives_back_free_memory() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 64 * 1024));
+
+    heap.watermarks.set(Some(8192), 0, QuotaKind::OsBytes);
+    heap.watermarks.set_handler(Some(Arc::new(|_| {})));
+    heap.quarantine.set_enabled(true, 64 * 1024);
+
+    let first_section = heap.allocate::<u64>(5000).unwrap();
+    heap.deallocate(first_section);
+
+    assert_eq!(heap.memory.lock().unwrap().source.mapped_pages(), 2);
+
+    /*
+     * The quarantined region is unmapped by the trim that follows the high watermark
+     */
+    heap.watermarks.set_auto_trim(true);
+    let second_section = heap.allocate::<u64>(5000).unwrap();
+
+    assert_eq!(heap.memory.lock().unwrap().source.mapped_pages(), 2);
+    assert_eq!(heap.stats().regions, 1);
+
+    heap.deallocate(second_section);
+    heap.quarantine.set_enabled(false, 0);
+    heap.flush_quarantine();
+}
+
+#[test]
+fn test_trim_gives_back_blocks_kept_after_failures() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+    let first_block = heap.qualloc::<u64>(64).unwrap();
+    let last_block = heap.qualloc::<u64>(64).unwrap();
+
+    heap.memory
+        .lock()
+        .unwrap()
+        .source
+        .inject_failure(SimulatedFailure::Nth(SimulatedOperation::Shrink, 1));
+    heap.qudelloc(last_block);
+
+    let heap_size = heap.stats().heap_size;
+    let released = heap.trim();
+
+    assert!(released > 0);
+    assert_eq!(heap.stats().heap_size, heap_size - released);
+    assert_eq!(heap.stats().blocks, 1);
+
+    heap.qudelloc(first_block);
+    assert_eq!(heap.trim(), 0);
+    assert_eq!(heap.stats().heap_size, 0);
+}
+
+#[test]
+fn test_small_allocations_cross_watermarks() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let levels = Arc::new(Mutex::new(Vec::new()));
+    let handler_levels = levels.clone();
+
+    MmapAllocator::set_pressure_handler(Some(Arc::new(move |report: &PressureReport| {
+        handler_levels.lock().unwrap().push(report.level)
+    })));
+
+    /*
+     * Other tests can leave small objects of the global allocator, the watermarks start from them
+     */
+    MmapAllocator::set_quota(None, QuotaKind::UserBytes);
+
+    let used = MmapAllocator::stats().quota_used;
+
+    MmapAllocator::set_watermarks(Some(used + 16), used, QuotaKind::UserBytes);
+
+    /*
+     * Small objects are counted with the size of their class, so the watermarks don't turn the small
+     * allocator off
+     */
+    let object = MmapAllocator::allocate::<u8>(32).unwrap();
+    assert!(SmallAllocator::object_entry(object).is_some());
+
+    MmapAllocator::deallocate(object);
+    MmapAllocator::set_watermarks(None, 0, QuotaKind::UserBytes);
+    MmapAllocator::set_pressure_handler(None);
+
+    assert_eq!(
+        *levels.lock().unwrap(),
+        vec![PressureLevel::High, PressureLevel::Low]
+    );
+}
+
+#[test]
+fn test_small_slabs_cross_os_watermarks() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let levels = Arc::new(Mutex::new(Vec::new()));
+    let handler_levels = levels.clone();
+
+    MmapAllocator::set_pressure_handler(Some(Arc::new(move |report: &PressureReport| {
+        handler_levels.lock().unwrap().push(report.level)
+    })));
+    MmapAllocator::set_watermarks(
+        Some(MmapAllocator::stats().heap_size),
+        0,
+        QuotaKind::OsBytes,
+    );
+
+    /*
+     * Objects freed by other tests are reused first, once they are taken the next object takes a new slab
+     * from the arena
+     */
+    let objects: Vec<_> = (0..64 * SMALL_SLAB_SIZE / 256)
+        .map_while(|_| {
+            let crossed = !levels.lock().unwrap().is_empty();
+
+            (!crossed).then(|| MmapAllocator::allocate::<u8>(256).unwrap())
+        })
+        .collect();
+
+    MmapAllocator::set_watermarks(None, 0, QuotaKind::OsBytes);
+    MmapAllocator::set_pressure_handler(None);
+    objects
+        .iter()
+        .for_each(|object| MmapAllocator::deallocate(*object));
+
+    assert!(
+        objects
+            .iter()
+            .all(|object| SmallAllocator::object_entry(*object).is_some())
+    );
+    assert_eq!(*levels.lock().unwrap(), vec![PressureLevel::High]);
+}
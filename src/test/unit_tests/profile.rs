@@ -0,0 +1,186 @@
+use std::{
+    sync::{Arc, Barrier},
+    thread,
+};
+
+use crate::{
+    bump::BumpHeap,
+    mmap::allocator::MmapAllocator,
+    profile::{HeapProfile, HeapProfiler, ProfileFormat},
+    small::allocator::SmallAllocator,
+    source::simulated::SimulatedSource,
+    test::GLOBAL_HEAP_LOCK,
+};
+
+/*
+ * Reads the top level fields of a protobuf message as field number and value, the value of length
+ * delimited fields is their length
+ */
+fn read_proto_fields(mut buffer: &[u8]) -> Vec<(u64, u64)> {
+    fn read_varint(buffer: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = buffer[0];
+            *buffer = &buffer[1..];
+            value |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+
+            if byte < 0x80 {
+                return value;
+            }
+        }
+    }
+
+    let mut fields = Vec::new();
+
+    while !buffer.is_empty() {
+        let key = read_varint(&mut buffer);
+        let value = read_varint(&mut buffer);
+
+        if key & 7 == 2 {
+            buffer = &buffer[value as usize..];
+        }
+
+        fields.push((key >> 3, value));
+    }
+
+    fields
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_heap_profile_samples() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+    heap.profiler.set_enabled(true, 1);
+
+    let blocks: Vec<*mut u8> = (0..3).map(|_| heap.qualloc::<u8>(100).unwrap()).collect();
+
+    heap.qudelloc(blocks[1]);
+
+    let profile = heap.profiler.profile();
+
+    assert_eq!(profile.samples.len(), 2);
+    assert_eq!(profile.estimated_bytes().round(), 200.0);
+    assert!(profile.samples.iter().all(|sample| sample.count() == 1.0));
+
+    let mut text = Vec::new();
+
+    profile.write(&mut text, ProfileFormat::Text).unwrap();
+
+    let text = String::from_utf8(text).unwrap();
+    let mut lines = text.lines();
+
+    assert_eq!(
+        lines.next(),
+        Some("heap profile: 2: 200 [2: 200] @ heap_v2/1")
+    );
+    assert!(lines.next().unwrap().starts_with("2: 200 [2: 200] @ 0x"));
+    assert!(text.contains("MAPPED_LIBRARIES:"));
+
+    let mut pprof = Vec::new();
+
+    profile.write(&mut pprof, ProfileFormat::Pprof).unwrap();
+
+    let fields = read_proto_fields(&pprof);
+    let count_fields = |field| fields.iter().filter(|(number, _)| *number == field).count();
+
+    assert_eq!(count_fields(1), 2);
+    assert_eq!(count_fields(2), 1);
+    assert!(count_fields(4) >= 1);
+    assert!(fields.contains(&(12, 1)));
+    assert!(pprof.windows(11).any(|window| window == b"inuse_space"));
+
+    heap.profiler.set_enabled(false, 0);
+    assert_eq!(
+        heap.profiler.profile(),
+        HeapProfile {
+            sample_interval: 1,
+            samples: Vec::new()
+        }
+    );
+
+    heap.qudelloc(blocks[0]);
+    heap.qudelloc(blocks[2]);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_heap_profile_estimate() {
+    let heap = BumpHeap::new(SimulatedSource::new(2 * 1024 * 1024, 0));
+
+    heap.profiler.set_enabled(true, 4096);
+
+    let blocks: Vec<*mut u8> = (0..8000).map(|_| heap.qualloc::<u8>(64).unwrap()).collect();
+    let profile = heap.profiler.profile();
+    let estimated_bytes = profile.estimated_bytes();
+
+    /*
+     * Around 125 of the 8000 allocations are sampled, the estimate must be close to the 512000 live bytes
+     */
+    assert!(profile.samples.len() < 400);
+    assert!((384_000.0..640_000.0).contains(&estimated_bytes));
+
+    for block in blocks {
+        heap.qudelloc(block);
+    }
+
+    assert!(heap.profiler.profile().samples.is_empty());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_racing_threads_take_one_sample_per_interval() {
+    let profiler = Arc::new(HeapProfiler::new());
+
+    profiler.set_enabled(true, 4096);
+
+    /*
+     * The threads that cross the interval at the same time don't all sample, 8 threads allocating 8 MiB
+     * take around 2048 samples
+     */
+    let barrier = Arc::new(Barrier::new(8));
+    let threads = (0..8)
+        .map(|thread| {
+            let profiler = profiler.clone();
+            let barrier = barrier.clone();
+
+            thread::spawn(move || {
+                barrier.wait();
+
+                for allocation in 0..64 * 1024 {
+                    profiler.record_allocation((thread << 32) | (allocation << 4), 16);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert!(profiler.profile().samples.len() < 4096);
+    profiler.set_enabled(false, 0);
+}
+
+#[test]
+fn test_small_objects_are_sampled() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    MmapAllocator::set_heap_profiling(true, 1);
+
+    let object = MmapAllocator::allocate::<u8>(32).unwrap();
+    let profile = MmapAllocator::heap_profile();
+
+    assert!(SmallAllocator::object_entry(object).is_some());
+    assert_eq!(profile.samples.len(), 1);
+    assert_eq!(profile.samples[0].size, 32);
+
+    MmapAllocator::deallocate(object);
+    assert!(MmapAllocator::heap_profile().samples.is_empty());
+
+    MmapAllocator::set_heap_profiling(false, 0);
+}
@@ -0,0 +1,207 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    bump::BumpHeap,
+    free_check::{InvalidFreeKind, InvalidFreeReport},
+    mmap::{MmapHeap, allocator::MmapAllocator},
+    quarantine::{POISON_BYTE, Quarantine, UseAfterFreeReport},
+    small::allocator::SmallAllocator,
+    source::simulated::SimulatedSource,
+    test::GLOBAL_HEAP_LOCK,
+};
+
+fn collect_reports(quarantine: &Quarantine) -> Arc<Mutex<Vec<UseAfterFreeReport>>> {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let handler_reports = reports.clone();
+
+    quarantine.set_handler(Some(Arc::new(move |report: &UseAfterFreeReport| {
+        handler_reports.lock().unwrap().push(*report)
+    })));
+
+    reports
+}
+
+#[test]
+fn test_bump_heap_quarantine() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let reports = collect_reports(&heap.quarantine);
+    heap.quarantine.set_enabled(true, 64);
+
+    let first_block = heap.qualloc::<u8>(32).unwrap();
+    let second_block = heap.qualloc::<u8>(32).unwrap();
+    let third_block = heap.qualloc::<u8>(32).unwrap();
+    let _last_block = heap.qualloc::<u8>(16).unwrap();
+
+    heap.qudelloc(first_block);
+
+    /*
+     * The block is poisoned and it isn't reused while it's in the quarantine
+     */
+    let first_data = unsafe { std::slice::from_raw_parts(first_block, 32) };
+    assert!(first_data.iter().all(|byte| *byte == POISON_BYTE));
+
+    let new_block = heap.qualloc::<u8>(32).unwrap();
+    assert_ne!(new_block, first_block);
+
+    unsafe { first_block.add(5).write(0x2a) };
+
+    heap.qudelloc(second_block);
+    assert!(reports.lock().unwrap().is_empty());
+
+    /*
+     * The quarantine is bigger than 64 bytes, so the first block leaves it
+     */
+    heap.qudelloc(third_block);
+
+    let reports = reports.lock().unwrap();
+
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].block.usr_address, first_block as usize);
+    assert_eq!(reports[0].offset, 5);
+    assert_eq!(reports[0].value, 0x2a);
+    assert_eq!(heap.verify(), Ok(()));
+}
+
+#[test]
+fn test_handler_can_change_the_handler() {
+    let heap = Arc::new(MmapHeap::new(SimulatedSource::new(0, 16 * 4096)));
+    let offsets = Arc::new(Mutex::new(Vec::new()));
+    let handler_heap = heap.clone();
+    let handler_offsets = offsets.clone();
+
+    /*
+     * The handler is called without its lock, so it can replace itself
+     */
+    heap.quarantine
+        .set_handler(Some(Arc::new(move |report: &UseAfterFreeReport| {
+            let next_offsets = handler_offsets.clone();
+
+            handler_offsets
+                .lock()
+                .unwrap()
+                .push(("first", report.offset));
+            handler_heap.quarantine.set_handler(Some(Arc::new(
+                move |report: &UseAfterFreeReport| {
+                    next_offsets.lock().unwrap().push(("second", report.offset))
+                },
+            )));
+        })));
+    heap.quarantine.set_enabled(true, 4096);
+
+    for offset in [3, 7] {
+        let section = heap.allocate::<u8>(100).unwrap();
+
+        heap.deallocate(section);
+        unsafe { section.add(offset).write(0) };
+        heap.flush_quarantine();
+    }
+
+    assert_eq!(*offsets.lock().unwrap(), vec![("first", 3), ("second", 7)]);
+    heap.quarantine.set_handler(None);
+}
+
+#[test]
+fn test_mmap_heap_quarantine() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+    let reports = collect_reports(&heap.quarantine);
+    heap.quarantine.set_enabled(true, 4096);
+
+    let section = heap.allocate::<u8>(1000).unwrap();
+    heap.deallocate(section);
+
+    /*
+     * The region isn't unmapped while its section is in the quarantine
+     */
+    assert_eq!(heap.stats().regions, 1);
+    assert_eq!(heap.verify(), Ok(()));
+
+    let new_section = heap.allocate::<u8>(1000).unwrap();
+    assert_ne!(new_section, section);
+
+    unsafe { section.add(999).write(0) };
+    heap.flush_quarantine();
+
+    assert_eq!(
+        reports
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|report| (report.block.usr_address, report.offset))
+            .collect::<Vec<_>>(),
+        vec![(section as usize, 999)]
+    );
+
+    /*
+     * After the flush the section can be reused
+     */
+    assert_eq!(heap.allocate::<u8>(1000).unwrap(), section);
+    assert_eq!(heap.verify(), Ok(()));
+}
+
+#[test]
+fn test_quarantined_double_free() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+    let invalid_frees = Arc::new(Mutex::new(Vec::new()));
+    let handler_invalid_frees = invalid_frees.clone();
+
+    heap.free_checks
+        .set_handler(Some(Arc::new(move |report: &InvalidFreeReport| {
+            handler_invalid_frees.lock().unwrap().push(report.kind)
+        })));
+    heap.quarantine.set_enabled(true, 4096);
+
+    let section = heap.allocate::<u8>(100).unwrap();
+    heap.deallocate(section);
+    heap.deallocate(section);
+
+    assert_eq!(
+        *invalid_frees.lock().unwrap(),
+        vec![InvalidFreeKind::DoubleFree]
+    );
+}
+
+#[test]
+fn test_disabled_quarantine_releases_blocks() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    heap.quarantine.set_enabled(true, 1024);
+
+    let first_block = heap.qualloc::<u8>(32).unwrap();
+    let second_block = heap.qualloc::<u8>(32).unwrap();
+    heap.qudelloc(second_block);
+    heap.qudelloc(first_block);
+
+    assert_eq!(heap.stats().blocks, 2);
+
+    /*
+     * The blocks leave the quarantine with the next deallocation after disabling it
+     */
+    heap.quarantine.set_enabled(false, 0);
+    let block = heap.qualloc::<u8>(8).unwrap();
+    heap.qudelloc(block);
+
+    assert_eq!(heap.stats().heap_size, 0);
+}
+
+#[test]
+fn test_small_allocations_are_quarantined() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    MmapAllocator::set_quarantine(true, 4096);
+
+    /*
+     * The small allocator doesn't have a quarantine, so the allocation goes to the heap and it's poisoned
+     */
+    let section = MmapAllocator::allocate::<u8>(32).unwrap();
+    assert!(SmallAllocator::object_entry(section).is_none());
+
+    MmapAllocator::deallocate(section);
+    assert!(
+        unsafe { std::slice::from_raw_parts(section, 32) }
+            .iter()
+            .all(|byte| *byte == POISON_BYTE)
+    );
+
+    MmapAllocator::set_quarantine(false, 0);
+    MmapAllocator::flush_quarantine();
+}
@@ -0,0 +1,98 @@
+use crate::{
+    bump::BumpHeap, error::AllocError, mmap::MmapHeap, quota::QuotaKind,
+    source::simulated::SimulatedSource,
+};
+
+#[test]
+fn test_user_bytes_quota() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    heap.quota.set_limit(Some(100), QuotaKind::UserBytes);
+
+    let first_block = heap.qualloc::<u8>(64).unwrap();
+
+    assert_eq!(
+        heap.qualloc::<u8>(64),
+        Err(AllocError::QuotaExceeded {
+            limit: 100,
+            requested: 64,
+        })
+    );
+
+    let stats = heap.stats();
+
+    assert_eq!(stats.quota_kind, QuotaKind::UserBytes);
+    assert_eq!(stats.quota_limit, Some(100));
+    assert_eq!(stats.quota_used, 64);
+    assert_eq!(stats.allocations, 1);
+
+    /*
+     * Freed bytes go back to the budget, and the limit can be raised while the heap is used
+     */
+    heap.qudelloc(first_block);
+    let first_block = heap.qualloc::<u8>(64).unwrap();
+
+    heap.quota.set_limit(Some(128), QuotaKind::UserBytes);
+    let second_block = heap.qualloc::<u8>(64).unwrap();
+
+    assert_eq!(heap.stats().quota_used, 128);
+
+    heap.qudelloc(second_block);
+    heap.qudelloc(first_block);
+    assert_eq!(heap.stats().quota_used, 0);
+}
+
+#[test]
+fn test_os_bytes_quota() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 64 * 1024));
+    heap.quota.set_limit(Some(8192), QuotaKind::OsBytes);
+
+    let section = heap.allocate::<u64>(5000).unwrap();
+
+    /*
+     * The second section needs a new region of two pages
+     */
+    assert_eq!(
+        heap.allocate::<u64>(5000),
+        Err(AllocError::QuotaExceeded {
+            limit: 8192,
+            requested: 8192,
+        })
+    );
+    assert_eq!(heap.memory.lock().unwrap().source.mapped_pages(), 2);
+    assert_eq!(heap.stats().quota_used, 8192);
+
+    heap.quota.set_limit(None, QuotaKind::OsBytes);
+    let other_section = heap.allocate::<u64>(5000).unwrap();
+
+    assert_eq!(heap.stats().quota_limit, None);
+    assert_eq!(heap.stats().quota_used, 16384);
+
+    heap.deallocate(other_section);
+    heap.deallocate(section);
+}
+
+#[test]
+fn test_quota_kind_can_change() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+    heap.qualloc::<u8>(32).unwrap();
+
+    /*
+     * User bytes are counted without a limit, so switching the kind keeps the usage
+     */
+    assert_eq!(heap.stats().quota_used, 32);
+
+    heap.quota
+        .set_limit(Some(heap.stats().heap_size), QuotaKind::OsBytes);
+    assert!(matches!(
+        heap.qualloc::<u8>(8),
+        Err(AllocError::QuotaExceeded { .. })
+    ));
+
+    heap.quota.set_limit(Some(40), QuotaKind::UserBytes);
+    assert!(heap.qualloc::<u8>(8).is_ok());
+    assert_eq!(heap.stats().quota_used, 40);
+
+    heap.teardown();
+    assert_eq!(heap.stats().quota_used, 0);
+}
@@ -0,0 +1,129 @@
+use crate::{
+    bump::{BumpHeap, BumpMemoryBlockHeader},
+    mmap::{MmapHeap, MmapMemoryRegion},
+    source::{
+        MemorySource,
+        simulated::{SimulatedFailure, SimulatedOperation, SimulatedSource},
+    },
+    utils::align_up,
+};
+
+#[test]
+fn test_qualloc_simulated() {
+    /*
+     * In this test we check that blocks are reused and merged, the heap is over a simulated source, so
+     * nothing else can move the break between the allocations
+     */
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let initial_heap_address = heap.memory.lock().unwrap().source.grow(0).unwrap() as usize;
+    let aligned_size = align_up(52);
+
+    let first_block = heap.qualloc::<char>(aligned_size).unwrap();
+    let second_block = heap.qualloc::<char>(aligned_size).unwrap();
+
+    heap.qudelloc(first_block);
+    let first_block_again = heap.qualloc::<char>(aligned_size).unwrap();
+
+    assert_eq!(first_block, first_block_again);
+    assert_eq!(
+        second_block as usize,
+        initial_heap_address + (BumpMemoryBlockHeader::size() * 2 + aligned_size) as usize
+    );
+
+    heap.qualloc::<char>(aligned_size).unwrap();
+    heap.qudelloc(first_block_again);
+    heap.qudelloc(second_block);
+    let merged_two_blocks = heap.qualloc::<char>(aligned_size * 2).unwrap();
+
+    assert_eq!(
+        merged_two_blocks as usize - BumpMemoryBlockHeader::size() as usize,
+        initial_heap_address
+    );
+}
+
+#[test]
+fn test_simulated_break_moves() {
+    /*
+     * The break of the simulated source moves by the exact increment and goes back on shrink, like sbrk
+     * does, but without moving the break of the process
+     */
+    let mut source = SimulatedSource::new(64 * 1024, 0);
+    let heap_address = source.grow(0).unwrap();
+
+    source.grow(32).unwrap();
+    let new_heap_address = source.grow(0).unwrap();
+    assert_eq!(heap_address as usize + 32, new_heap_address as usize);
+
+    source.shrink(32).unwrap();
+    assert_eq!(source.grow(0).unwrap(), heap_address);
+}
+
+#[test]
+fn test_simulated_grow_failure() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+    heap.memory
+        .lock()
+        .unwrap()
+        .source
+        .inject_failure(SimulatedFailure::Nth(SimulatedOperation::Grow, 2));
+
+    assert!(heap.qualloc::<u64>(64).is_ok());
+    assert!(heap.qualloc::<u64>(64).is_err(), "Second grow must fail");
+    assert!(heap.qualloc::<u64>(64).is_ok());
+}
+
+#[test]
+fn test_simulated_fail_after() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 64 * 1024));
+
+    heap.memory
+        .lock()
+        .unwrap()
+        .source
+        .inject_failure(SimulatedFailure::After(1));
+
+    assert!(heap.allocate::<u64>(5000).is_ok());
+    assert!(heap.allocate::<u64>(5000).is_err());
+
+    heap.memory.lock().unwrap().source.clear_failures();
+    assert!(heap.allocate::<u64>(5000).is_ok());
+}
+
+#[test]
+fn test_simulated_page_size_override() {
+    let page_size = 64 * 1024;
+    let heap = MmapHeap::new(SimulatedSource::with_page_size(0, 4 * page_size, page_size));
+
+    heap.allocate::<u64>(100).unwrap();
+
+    let memory_guard = heap.memory.lock().unwrap();
+    let region = memory_guard
+        .head
+        .as_ref()
+        .unwrap()
+        .load(std::sync::atomic::Ordering::SeqCst);
+
+    assert_eq!(memory_guard.source.mapped_pages(), 1);
+    assert_eq!(region as usize % page_size, 0);
+    assert_eq!(
+        unsafe { (*region).total_space },
+        page_size - MmapMemoryRegion::size()
+    );
+}
+
+#[test]
+fn test_simulated_unmapped_pages_are_reused() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 8 * 4096));
+
+    let first_section = heap.allocate::<u8>(5000).unwrap();
+    let second_section = heap.allocate::<u8>(5000).unwrap();
+    assert_eq!(heap.memory.lock().unwrap().source.mapped_pages(), 4);
+
+    heap.deallocate(first_section);
+    assert_eq!(heap.memory.lock().unwrap().source.mapped_pages(), 2);
+
+    let third_section = heap.allocate::<u8>(5000).unwrap();
+    assert_eq!(first_section, third_section);
+    assert!(heap.memory.lock().unwrap().source.contains(second_section));
+}
@@ -0,0 +1,140 @@
+use std::thread;
+
+use crate::{
+    free_check::InvalidFreeKind,
+    mmap::allocator::MmapAllocator,
+    small::{SMALL_SIZE_CLASSES, allocator::SmallAllocator, utils::get_size_class},
+    test::GLOBAL_HEAP_LOCK,
+};
+
+#[test]
+fn test_get_size_class() {
+    assert_eq!(get_size_class(1), Some(0));
+    assert_eq!(get_size_class(16), Some(0));
+    assert_eq!(get_size_class(17), Some(1));
+    assert_eq!(get_size_class(256), Some(SMALL_SIZE_CLASSES.len() - 1));
+    assert_eq!(get_size_class(257), None);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_small_allocator_concurrent_alloc_free() {
+    /*
+     * In this test many threads are allocating and deallocating small objects at the same time, every
+     * thread fills its objects with its own id and checks that the content is the same before deallocating
+     * them, if the free lists give the same object to two threads then the content is overwritten
+     */
+    let workers = (0..8u8)
+        .map(|worker| {
+            thread::spawn(move || {
+                let mut live_objects: Vec<(*mut u8, usize)> = Vec::new();
+
+                for iteration in 0..20_000usize {
+                    let size = SMALL_SIZE_CLASSES
+                        [(iteration + worker as usize) % SMALL_SIZE_CLASSES.len()];
+                    let object = SmallAllocator::allocate::<u8>(size).unwrap();
+
+                    assert_eq!(
+                        object as usize % size,
+                        0,
+                        "Objects must be aligned to their class size"
+                    );
+
+                    unsafe { object.write_bytes(worker, size) };
+                    live_objects.push((object, size));
+
+                    if live_objects.len() < 64 {
+                        continue;
+                    }
+
+                    for (object, size) in live_objects.drain(..) {
+                        let content = unsafe { std::slice::from_raw_parts(object, size) };
+                        assert!(
+                            content.iter().all(|byte| *byte == worker),
+                            "Object was given to other thread while it was alive"
+                        );
+                        assert_eq!(SmallAllocator::deallocate(object), Ok(true));
+                    }
+                }
+
+                for (object, _) in live_objects {
+                    SmallAllocator::deallocate(object).unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_mmap_allocator_mixed_sizes_concurrent() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    /*
+     * Small sizes go through the lock-free path and big sizes through the locked path, both paths must
+     * work at the same time and deallocate must find the right one for every pointer
+     */
+    let workers = (0..4usize)
+        .map(|worker| {
+            thread::spawn(move || {
+                for iteration in 0..2_000usize {
+                    let size = if (iteration + worker) % 4 == 0 {
+                        2048
+                    } else {
+                        48
+                    };
+                    let section = MmapAllocator::allocate::<u64>(size).unwrap();
+
+                    unsafe {
+                        *section = iteration as u64;
+                        assert_eq!(*section, iteration as u64);
+                    }
+
+                    MmapAllocator::deallocate(section);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_small_allocator_rejects_invalid_frees() {
+    /*
+     * A double free and a free of an interior pointer must not reach the free list, if they do, then the
+     * same object is given twice or an object overlaps other one
+     */
+    let object = SmallAllocator::allocate::<u8>(32).unwrap();
+
+    assert_eq!(SmallAllocator::deallocate(object), Ok(true));
+    assert_eq!(
+        SmallAllocator::deallocate(object),
+        Err(InvalidFreeKind::DoubleFree)
+    );
+
+    let first_object = SmallAllocator::allocate::<u8>(32).unwrap();
+    let second_object = SmallAllocator::allocate::<u8>(32).unwrap();
+    assert_ne!(first_object, second_object);
+
+    let big_object = SmallAllocator::allocate::<u8>(64).unwrap();
+    assert_eq!(
+        SmallAllocator::deallocate(unsafe { big_object.add(8) }),
+        Err(InvalidFreeKind::InteriorPointer)
+    );
+
+    for object in [first_object, second_object, big_object] {
+        assert_eq!(SmallAllocator::deallocate(object), Ok(true));
+    }
+
+    assert_eq!(SmallAllocator::deallocate(&0u8 as *const u8), Ok(false));
+}
@@ -0,0 +1,82 @@
+use crate::{
+    bump::{BumpHeap, BumpMemoryBlockHeader},
+    mmap::{MmapHeap, MmapMemoryRegion, MmapMemorySectionHeader},
+    source::buffer::{BUFFER_PAGE_SIZE, BufferSource},
+    test::leak_buffer,
+    utils::align_up,
+};
+
+#[test]
+fn test_bump_heap_over_buffer() {
+    /*
+     * The same steps of test_qualloc but over a buffer, so the heap layout doesn't depend on other
+     * code moving the program break
+     */
+    let buffer = leak_buffer(64 * 1024);
+    let buffer_range = buffer.as_ptr_range();
+    let heap = BumpHeap::new(BufferSource::new(buffer));
+    let aligned_size = align_up(52);
+
+    let first_block = heap.qualloc::<u8>(aligned_size).unwrap();
+    let second_block = heap.qualloc::<u8>(aligned_size).unwrap();
+
+    assert!(buffer_range.contains(&(first_block as *const u8)));
+    assert!(buffer_range.contains(&(second_block as *const u8)));
+    assert_eq!(
+        second_block as usize,
+        first_block as usize + (aligned_size + BumpMemoryBlockHeader::size()) as usize
+    );
+
+    heap.qudelloc(first_block);
+    let first_block_again = heap.qualloc::<u8>(aligned_size).unwrap();
+    assert_eq!(first_block, first_block_again);
+
+    /*
+     * Deallocating the last block gives its memory back to the buffer
+     */
+    let used = heap.memory.lock().unwrap().source.used();
+    heap.qudelloc(second_block);
+    assert_eq!(
+        heap.memory.lock().unwrap().source.used(),
+        used - (aligned_size + BumpMemoryBlockHeader::size()) as usize
+    );
+}
+
+#[test]
+fn test_bump_heap_buffer_exhausted() {
+    let heap = BumpHeap::new(BufferSource::new(leak_buffer(256)));
+
+    assert!(heap.qualloc::<u8>(128).is_ok());
+    assert!(heap.qualloc::<u8>(512).is_err());
+}
+
+#[test]
+fn test_mmap_heap_over_buffer() {
+    let buffer = leak_buffer(16 * BUFFER_PAGE_SIZE);
+    let buffer_range = buffer.as_ptr_range();
+    let heap = MmapHeap::new(BufferSource::new(buffer));
+
+    let first_section = heap.allocate::<u8>(1000).unwrap();
+    let second_section = heap.allocate::<u8>(5000).unwrap();
+
+    assert!(buffer_range.contains(&(first_section as *const u8)));
+    assert!(buffer_range.contains(&(second_section as *const u8)));
+    assert_eq!(
+        first_section as usize,
+        buffer_range.start as usize + MmapMemoryRegion::size() + MmapMemorySectionHeader::size()
+    );
+
+    /*
+     * The second region is at the end of the break, so it can be given back
+     */
+    assert_eq!(
+        heap.memory.lock().unwrap().source.used(),
+        3 * BUFFER_PAGE_SIZE
+    );
+    heap.deallocate(second_section);
+    assert_eq!(heap.memory.lock().unwrap().source.used(), BUFFER_PAGE_SIZE);
+
+    heap.deallocate(first_section);
+    assert_eq!(heap.memory.lock().unwrap().source.used(), 0);
+    assert!(heap.memory.lock().unwrap().head.is_none());
+}
@@ -0,0 +1,146 @@
+use crate::{
+    bump::{BumpHeap, BumpMemoryBlockHeader},
+    mmap::{MmapHeap, MmapMemoryRegion, MmapMemorySectionHeader, allocator::MmapAllocator},
+    small::SMALL_SLAB_SIZE,
+    source::simulated::SimulatedSource,
+    stats::Stats,
+    test::GLOBAL_HEAP_LOCK,
+};
+
+#[test]
+fn test_bump_heap_stats() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let header_size = BumpMemoryBlockHeader::size() as usize;
+
+    let first_block = heap.qualloc::<u8>(16).unwrap();
+    let second_block = heap.qualloc::<u8>(32).unwrap();
+    let third_block = heap.qualloc::<u8>(64).unwrap();
+    heap.qudelloc(second_block);
+
+    let stats = heap.stats();
+
+    assert_eq!(stats.bytes_in_use, 16 + 64);
+    assert_eq!(stats.bytes_free, 32);
+    assert_eq!(stats.header_overhead, 3 * header_size);
+    assert_eq!(stats.blocks, 3);
+    assert_eq!(stats.regions, 0);
+    assert_eq!(stats.heap_size, 16 + 32 + 64 + 3 * header_size);
+    assert_eq!(
+        stats.bytes_in_use + stats.bytes_free + stats.header_overhead,
+        stats.heap_size
+    );
+    assert_eq!(stats.sbrk_calls, 3);
+    assert_eq!(stats.allocations, 3);
+    assert_eq!(stats.frees, 1);
+    assert_eq!(stats.fragmentation, 0.0);
+
+    /*
+     * Giving the last block back to the source reduces the heap size but not the peak
+     */
+    heap.qudelloc(third_block);
+    heap.qudelloc(first_block);
+
+    let stats = heap.stats();
+
+    assert_eq!(stats.blocks, 2);
+    assert_eq!(stats.bytes_in_use, 0);
+    assert_eq!(stats.heap_size, 16 + 32 + 2 * header_size);
+    assert_eq!(stats.peak_heap_size, 16 + 32 + 64 + 3 * header_size);
+    assert_eq!(stats.frees, 3);
+}
+
+#[test]
+fn test_mmap_heap_stats() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+    let section_header_size = MmapMemorySectionHeader::size();
+
+    let first_section = heap.allocate::<u8>(1000).unwrap();
+    let second_section = heap.allocate::<u8>(5000).unwrap();
+
+    let stats = heap.stats();
+
+    assert_eq!(stats.regions, 2);
+    assert_eq!(stats.blocks, 2);
+    assert_eq!(stats.bytes_in_use, 6000);
+    assert_eq!(stats.heap_size, 3 * 4096);
+    assert_eq!(
+        stats.header_overhead,
+        2 * MmapMemoryRegion::size() + 2 * section_header_size
+    );
+    assert_eq!(
+        stats.bytes_in_use + stats.bytes_free + stats.header_overhead,
+        stats.heap_size
+    );
+    assert_eq!(stats.mmap_calls, 2);
+    assert_eq!(stats.allocations, 2);
+
+    heap.deallocate(first_section);
+    heap.deallocate(second_section);
+
+    let stats = heap.stats();
+
+    assert_eq!(stats.regions, 0);
+    assert_eq!(stats.heap_size, 0);
+    assert_eq!(stats.peak_heap_size, 3 * 4096);
+    assert_eq!(stats.munmap_calls, 2);
+    assert_eq!(stats.frees, 2);
+}
+
+#[test]
+fn test_deallocate_unknown_pointer_is_not_counted() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let block = heap.qualloc::<u8>(16).unwrap();
+
+    heap.qudelloc(0x10 as *const u8);
+
+    assert_eq!(heap.stats().frees, 0);
+    heap.qudelloc(block);
+}
+
+#[test]
+fn test_fragmentation_ratio() {
+    assert_eq!(Stats::fragmentation_ratio(0, 0), 0.0);
+    assert_eq!(Stats::fragmentation_ratio(100, 100), 0.0);
+    assert_eq!(Stats::fragmentation_ratio(25, 100), 0.75);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_mmap_allocator_stats_count_small_objects() {
+    /*
+     * Small objects don't have headers, but they are allocations of the mmap allocator, so they must be
+     * counted in its stats. Other tests use the global allocator too, so only the growth is checked
+     */
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    let before = MmapAllocator::stats();
+    let first_object = MmapAllocator::allocate::<u8>(32).unwrap();
+    let second_object = MmapAllocator::allocate::<u8>(64).unwrap();
+    let stats = MmapAllocator::stats();
+
+    assert!(stats.allocations >= before.allocations + 2);
+    assert!(stats.small_slabs >= 2);
+    assert!(stats.bytes_in_use >= 32 + 64);
+
+    /*
+     * Objects freed by other tests are reused first, once they are taken the next object takes a new slab,
+     * and it's counted in the heap size
+     */
+    let objects: Vec<_> = (0..64 * SMALL_SLAB_SIZE / 256)
+        .map_while(|_| {
+            (MmapAllocator::stats().heap_size < stats.heap_size + SMALL_SLAB_SIZE)
+                .then(|| MmapAllocator::allocate::<u8>(256).unwrap())
+        })
+        .collect();
+
+    assert!(MmapAllocator::stats().heap_size >= stats.heap_size + SMALL_SLAB_SIZE);
+
+    objects
+        .into_iter()
+        .for_each(|object| MmapAllocator::deallocate(object));
+    MmapAllocator::deallocate(first_object);
+    MmapAllocator::deallocate(second_object);
+    assert!(MmapAllocator::stats().frees >= before.frees + 2);
+}
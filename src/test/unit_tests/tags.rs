@@ -0,0 +1,199 @@
+use crate::{
+    bump::BumpHeap,
+    error::AllocError,
+    mmap::{MmapHeap, allocator::MmapAllocator},
+    small::allocator::SmallAllocator,
+    source::simulated::SimulatedSource,
+    tags::{TagScope, TagStats, Tags, UNTAGGED, current_tag},
+    test::GLOBAL_HEAP_LOCK,
+    walk::HeapEntryKind,
+};
+
+const PLANNER_TAG: u8 = 7;
+const CACHE_TAG: u8 = 9;
+
+#[test]
+fn test_tag_scopes_are_nested() {
+    assert_eq!(current_tag(), UNTAGGED);
+
+    {
+        let _planner_scope = TagScope::enter(PLANNER_TAG);
+
+        {
+            let _cache_scope = TagScope::enter(CACHE_TAG);
+            assert_eq!(current_tag(), CACHE_TAG);
+        }
+
+        assert_eq!(current_tag(), PLANNER_TAG);
+    }
+
+    assert_eq!(current_tag(), UNTAGGED);
+}
+
+#[test]
+fn test_bump_heap_counts_live_bytes_by_tag() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+    let untagged_block = heap.qualloc::<u8>(16).unwrap();
+    let (first_plan, second_plan) = {
+        let _scope = TagScope::enter(PLANNER_TAG);
+
+        (
+            heap.qualloc::<u8>(64).unwrap(),
+            heap.qualloc::<u8>(100).unwrap(),
+        )
+    };
+    let cache_block = heap.qualloc_tagged::<u8>(CACHE_TAG, 32).unwrap();
+
+    assert_eq!(
+        heap.tags.stats(PLANNER_TAG),
+        TagStats {
+            tag: PLANNER_TAG,
+            live_bytes: 164,
+            live_count: 2,
+            peak_bytes: 164,
+            limit: None,
+        }
+    );
+    assert_eq!(heap.tags.stats(CACHE_TAG).live_bytes, 32);
+    assert_eq!(heap.tags.stats(UNTAGGED).live_bytes, 16);
+
+    /*
+     * The tag is stored in the header, so the walk sees it
+     */
+    let tagged_blocks = heap
+        .snapshot()
+        .iter()
+        .filter(|entry| entry.kind == HeapEntryKind::Block && entry.tag == PLANNER_TAG)
+        .count();
+
+    assert_eq!(tagged_blocks, 2);
+
+    /*
+     * The free is counted in the tag of the header, not in the tag of the thread
+     */
+    heap.qudelloc(first_plan);
+
+    let planner_stats = heap.tags.stats(PLANNER_TAG);
+    assert_eq!(planner_stats.live_bytes, 100);
+    assert_eq!(planner_stats.live_count, 1);
+    assert_eq!(planner_stats.peak_bytes, 164);
+
+    heap.qudelloc(second_plan);
+    heap.qudelloc(cache_block);
+    heap.qudelloc(untagged_block);
+
+    assert_eq!(
+        heap.tags
+            .all_stats()
+            .iter()
+            .map(|stats| (stats.tag, stats.live_bytes))
+            .collect::<Vec<_>>(),
+        vec![(UNTAGGED, 0), (PLANNER_TAG, 0), (CACHE_TAG, 0)]
+    );
+}
+
+#[test]
+fn test_tag_limit() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 64 * 1024));
+    heap.tags.set_limit(PLANNER_TAG, Some(1000));
+
+    let plan = heap.allocate_tagged::<u8>(PLANNER_TAG, 800).unwrap();
+
+    assert_eq!(
+        heap.allocate_tagged::<u8>(PLANNER_TAG, 400),
+        Err(AllocError::TagLimitExceeded {
+            tag: PLANNER_TAG,
+            limit: 1000,
+            requested: 400,
+        })
+    );
+
+    /*
+     * The other tags aren't limited
+     */
+    let cache_section = heap.allocate_tagged::<u8>(CACHE_TAG, 400).unwrap();
+
+    heap.deallocate(plan);
+    let plan = heap.allocate_tagged::<u8>(PLANNER_TAG, 400).unwrap();
+
+    assert_eq!(heap.tags.stats(PLANNER_TAG).live_bytes, 400);
+    assert_eq!(heap.tags.stats(PLANNER_TAG).limit, Some(1000));
+
+    heap.deallocate(plan);
+    heap.deallocate(cache_section);
+}
+
+#[test]
+fn test_small_objects_are_untagged() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let before = MmapAllocator::tag_stats(UNTAGGED);
+
+    /*
+     * The object is counted with the size of its class
+     */
+    let object = MmapAllocator::allocate::<u8>(20).unwrap();
+    let stats = MmapAllocator::tag_stats(UNTAGGED);
+
+    assert!(SmallAllocator::object_entry(object).is_some());
+    assert_eq!(stats.live_bytes, before.live_bytes + 32);
+    assert_eq!(stats.live_count, before.live_count + 1);
+
+    MmapAllocator::deallocate(object);
+    assert_eq!(
+        MmapAllocator::tag_stats(UNTAGGED).live_bytes,
+        before.live_bytes
+    );
+
+    /*
+     * While UNTAGGED haves a limit the allocations go to the heap, so the limit is checked
+     */
+    MmapAllocator::set_tag_limit(UNTAGGED, Some(before.live_bytes + 16));
+    let result = MmapAllocator::allocate::<u8>(20);
+    MmapAllocator::set_tag_limit(UNTAGGED, None);
+
+    assert!(matches!(result, Err(AllocError::TagLimitExceeded { .. })));
+}
+
+#[test]
+fn test_small_objects_stay_counted_after_teardown() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    let object = MmapAllocator::allocate::<u8>(20).unwrap();
+    assert!(SmallAllocator::object_entry(object).is_some());
+
+    /*
+     * The object survives the teardown, so it's still counted with the size of its class, and freeing it
+     * doesn't take the counters under 0
+     */
+    MmapAllocator::teardown();
+
+    let stats = MmapAllocator::tag_stats(UNTAGGED);
+
+    assert!(stats.live_bytes >= 32);
+    assert!(stats.live_count >= 1);
+
+    MmapAllocator::deallocate(object);
+
+    let after_free = MmapAllocator::tag_stats(UNTAGGED);
+
+    assert_eq!(after_free.live_bytes, stats.live_bytes - 32);
+    assert_eq!(after_free.live_count, stats.live_count - 1);
+}
+
+#[test]
+fn test_tag_counters_count_the_same_size_on_free() {
+    let tags = Tags::new();
+
+    tags.record_allocation(UNTAGGED, 16);
+    tags.record_allocation(UNTAGGED, 32);
+    tags.record_free(UNTAGGED, 16);
+
+    assert_eq!(tags.stats(UNTAGGED).live_bytes, 32);
+    assert_eq!(tags.stats(UNTAGGED).live_count, 1);
+    assert_eq!(tags.stats(UNTAGGED).peak_bytes, 48);
+}
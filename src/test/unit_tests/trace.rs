@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use crate::{
+    bump::{BumpHeap, allocator::BumpAllocator},
+    mmap::{MmapHeap, allocator::MmapAllocator},
+    placement::PlacementPolicy,
+    source::simulated::SimulatedSource,
+    test::GLOBAL_HEAP_LOCK,
+    trace::{
+        TRACE_MAGIC, TraceEvent, TraceOp, read_trace, recorder::TraceRecorder, replay::replay,
+    },
+};
+
+fn event(op: TraceOp, size: u64, ptr_id: u32) -> TraceEvent {
+    TraceEvent {
+        op,
+        size,
+        align: 8,
+        ptr_id,
+        thread_id: 1,
+        timestamp: Duration::ZERO,
+    }
+}
+
+#[test]
+fn test_trace_encoding() {
+    let allocation = TraceEvent {
+        op: TraceOp::MmapAlloc,
+        size: 1 << 40,
+        align: 16,
+        ptr_id: 7,
+        thread_id: 3,
+        timestamp: Duration::from_nanos(123_456),
+    };
+    let free = event(TraceOp::BumpFree, 0, 7);
+    let mut trace = TRACE_MAGIC.to_vec();
+
+    trace.extend(allocation.encode());
+    trace.extend(free.encode());
+
+    assert_eq!(
+        read_trace(trace.as_slice()).unwrap(),
+        vec![allocation, free]
+    );
+
+    /*
+     * The event that was being written when the recording process died is dropped
+     */
+    trace.extend(&allocation.encode()[..10]);
+    assert_eq!(read_trace(trace.as_slice()).unwrap().len(), 2);
+
+    assert!(read_trace(&b"NOTATRACE"[..]).is_err());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_trace_recorder() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let path = std::env::temp_dir().join(format!("qualloc-trace-{}", std::process::id()));
+
+    let untraced = BumpAllocator::qualloc::<u8>(16).unwrap();
+
+    TraceRecorder::start(&path).unwrap();
+
+    let block = BumpAllocator::qualloc::<u64>(40).unwrap();
+    let section = MmapAllocator::allocate::<u16>(1000).unwrap();
+
+    /*
+     * The blocks are freed from the last one, so the bump heap is left empty for the other tests
+     */
+    BumpAllocator::qudelloc(block);
+    MmapAllocator::deallocate(section);
+    BumpAllocator::qudelloc(untraced);
+    TraceRecorder::stop().unwrap();
+
+    let events = read_trace(std::fs::File::open(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    /*
+     * Other tests can use the mmap allocator at the same time, their events have other thread ids
+     */
+    let thread_id = events[0].thread_id;
+    let events: Vec<_> = events
+        .into_iter()
+        .filter(|event| event.thread_id == thread_id)
+        .map(|event| (event.op, event.size, event.align, event.ptr_id))
+        .collect();
+
+    assert_eq!(
+        events,
+        vec![
+            (TraceOp::BumpAlloc, 40, 8, 1),
+            (TraceOp::MmapAlloc, 1000, 2, 2),
+            (TraceOp::BumpFree, 0, 1, 1),
+            (TraceOp::MmapFree, 0, 1, 2),
+        ]
+    );
+    assert!(!TraceRecorder::is_recording());
+}
+
+#[test]
+fn test_best_fit_placement() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let big_block = heap.qualloc::<u8>(64).unwrap();
+    let first_separator = heap.qualloc::<u8>(16).unwrap();
+    let small_block = heap.qualloc::<u8>(32).unwrap();
+    let second_separator = heap.qualloc::<u8>(16).unwrap();
+
+    heap.qudelloc(big_block);
+    heap.qudelloc(small_block);
+
+    heap.placement.set_policy(PlacementPolicy::BestFit);
+    let best_fit = heap.qualloc::<u8>(32).unwrap();
+    assert_eq!(best_fit, small_block);
+    heap.qudelloc(best_fit);
+
+    heap.placement.set_policy(PlacementPolicy::FirstFit);
+    let first_fit = heap.qualloc::<u8>(32).unwrap();
+    assert_eq!(first_fit, big_block);
+
+    heap.qudelloc(first_fit);
+    heap.qudelloc(first_separator);
+    heap.qudelloc(second_separator);
+
+    /*
+     * Sections of the mmap heap are only reused when their region haves other sections that are alive
+     */
+    let heap = MmapHeap::new(SimulatedSource::new(0, 64 * 4096));
+
+    heap.placement.set_policy(PlacementPolicy::BestFit);
+
+    let sections: Vec<*mut u8> = [3000, 200, 3000]
+        .iter()
+        .map(|size| heap.allocate::<u8>(*size).unwrap())
+        .collect();
+
+    assert_eq!(heap.verify(), Ok(()));
+
+    for section in sections {
+        heap.deallocate(section);
+    }
+
+    assert!(heap.snapshot().is_empty());
+}
+
+#[test]
+fn test_replay() {
+    let events = vec![
+        event(TraceOp::BumpAlloc, 64, 1),
+        event(TraceOp::BumpAlloc, 16, 2),
+        event(TraceOp::MmapAlloc, 32, 3),
+        event(TraceOp::BumpAlloc, 16, 4),
+        event(TraceOp::BumpFree, 0, 1),
+        event(TraceOp::MmapFree, 0, 3),
+        // Deallocation of a pointer that isn't in the trace
+        event(TraceOp::BumpFree, 0, 9),
+        event(TraceOp::BumpAlloc, 32, 5),
+        event(TraceOp::BumpAlloc, 1 << 20, 6),
+    ];
+
+    for policy in [PlacementPolicy::FirstFit, PlacementPolicy::BestFit] {
+        let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+        heap.placement.set_policy(policy);
+
+        let report = replay(&events, &heap, 1);
+
+        assert_eq!(
+            (report.allocations, report.frees, report.failed_allocations),
+            (5, 2, 1)
+        );
+        /*
+         * First fit reuses the block of the first allocation for the 32 bytes, best fit the block of
+         * the third one
+         */
+        let reused_block = match policy {
+            PlacementPolicy::FirstFit => 64,
+            PlacementPolicy::BestFit => 32,
+        };
+
+        assert_eq!(report.final_stats.bytes_in_use, 16 + 16 + reused_block);
+        assert!(report.max_fragmentation >= report.final_stats.fragmentation);
+        assert!(heap.snapshot().iter().all(|block| block.is_free));
+    }
+}
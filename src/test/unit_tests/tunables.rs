@@ -0,0 +1,161 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    bump::BumpHeap,
+    mmap::{MmapHeap, allocator::MmapAllocator},
+    placement::PlacementPolicy,
+    quarantine::{POISON_BYTE, UseAfterFreeReport},
+    small::{SMALL_MAX_SIZE, allocator::SmallAllocator},
+    source::simulated::SimulatedSource,
+    test::GLOBAL_HEAP_LOCK,
+    tunables::{OptionTarget, Tunable, parse_options},
+};
+
+#[test]
+fn test_parse_options() {
+    let options: Vec<_> = parse_options(
+        "placement=best-fit, bump.trim_threshold=64k,mmap.min_region_size=1M,canaries=on,,\
+         quarantine=maybe,heap.verify=1,mmap_threshold=2g",
+    )
+    .collect();
+
+    assert_eq!(
+        options,
+        vec![
+            Ok((
+                OptionTarget::Both,
+                Tunable::Placement(PlacementPolicy::BestFit)
+            )),
+            Ok((OptionTarget::Bump, Tunable::TrimThreshold(64 * 1024))),
+            Ok((OptionTarget::Mmap, Tunable::MinRegionSize(1024 * 1024))),
+            Ok((OptionTarget::Both, Tunable::Canaries(true))),
+            Err("quarantine=maybe"),
+            Err("heap.verify=1"),
+            Ok((OptionTarget::Both, Tunable::MmapThreshold(2 << 30))),
+        ]
+    );
+    assert_eq!(
+        parse_options("poisoning=off,mmap.small_allocator=on,small_max_size=64")
+            .collect::<Vec<_>>(),
+        vec![
+            Ok((OptionTarget::Both, Tunable::Poisoning(false))),
+            Ok((OptionTarget::Mmap, Tunable::SmallAllocator(true))),
+            Ok((OptionTarget::Both, Tunable::SmallMaxSize(64))),
+        ]
+    );
+    assert_eq!(
+        parse_options("trim_threshold=99999999999999999999g").next(),
+        Some(Err("trim_threshold=99999999999999999999g"))
+    );
+}
+
+#[test]
+fn test_bump_trim_threshold() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+    assert!(heap.set_tunable(Tunable::TrimThreshold(4096)));
+    assert!(!heap.set_tunable(Tunable::MinRegionSize(4096)));
+
+    /*
+     * The last block is smaller than the threshold, so it's kept free for the next allocation
+     */
+    let block = heap.qualloc::<u64>(64).unwrap();
+    heap.qudelloc(block);
+
+    assert_eq!(heap.stats().blocks, 1);
+    assert_eq!(heap.qualloc::<u64>(64), Ok(block));
+
+    heap.qudelloc(block);
+    assert!(heap.trim() > 0);
+    assert_eq!(heap.stats().heap_size, 0);
+}
+
+#[test]
+fn test_mmap_region_tunables() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 1024 * 1024));
+
+    heap.set_tunable(Tunable::MinRegionSize(64 * 1024));
+    heap.set_tunable(Tunable::MmapThreshold(16 * 1024));
+    heap.set_tunable(Tunable::TrimThreshold(128 * 1024));
+
+    /*
+     * The small sections share the first region and the big one gets a region of its own size
+     */
+    let first_section = heap.allocate::<u64>(1000).unwrap();
+    let second_section = heap.allocate::<u64>(1000).unwrap();
+    let big_section = heap.allocate::<u64>(20 * 1024).unwrap();
+
+    assert_eq!(heap.stats().regions, 2);
+    assert_eq!(heap.stats().heap_size, 64 * 1024 + 24 * 1024);
+
+    /*
+     * Both regions are under the trim threshold, so they stay mapped until the heap is trimmed
+     */
+    heap.deallocate(big_section);
+    heap.deallocate(second_section);
+    heap.deallocate(first_section);
+
+    assert_eq!(heap.stats().regions, 2);
+    assert_eq!(heap.trim(), 64 * 1024 + 24 * 1024);
+    assert_eq!(heap.stats().regions, 0);
+}
+
+#[test]
+fn test_quarantine_without_poisoning() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    let reports = Arc::new(AtomicUsize::new(0));
+    let handler_reports = reports.clone();
+
+    heap.quarantine
+        .set_handler(Some(Arc::new(move |_: &UseAfterFreeReport| {
+            handler_reports.fetch_add(1, Ordering::Relaxed);
+        })));
+    assert!(heap.set_tunable(Tunable::Quarantine(true)));
+    assert!(heap.set_tunable(Tunable::Poisoning(false)));
+
+    /*
+     * The block is kept in the quarantine but its data isn't poisoned, so the write isn't reported
+     */
+    let block = heap.qualloc::<u8>(32).unwrap();
+    unsafe { block.write_bytes(1, 32) };
+    heap.qudelloc(block);
+
+    assert_eq!(unsafe { block.read() }, 1);
+    assert_eq!(heap.stats().blocks, 1);
+
+    unsafe { block.write(POISON_BYTE) };
+    heap.flush_quarantine();
+
+    assert_eq!(reports.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn test_small_allocator_tunables() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    assert!(!BumpHeap::new(SimulatedSource::new(0, 0)).set_tunable(Tunable::SmallMaxSize(64)));
+
+    MmapAllocator::set_option(Tunable::SmallMaxSize(64));
+    let object = MmapAllocator::allocate::<u8>(64).unwrap();
+    let section = MmapAllocator::allocate::<u8>(65).unwrap();
+
+    assert!(SmallAllocator::object_entry(object).is_some());
+    assert!(SmallAllocator::object_entry(section).is_none());
+
+    MmapAllocator::set_option(Tunable::SmallAllocator(false));
+    let disabled_section = MmapAllocator::allocate::<u8>(16).unwrap();
+
+    assert!(SmallAllocator::object_entry(disabled_section).is_none());
+
+    MmapAllocator::set_option(Tunable::SmallAllocator(true));
+    MmapAllocator::set_option(Tunable::SmallMaxSize(SMALL_MAX_SIZE));
+
+    for ptr in [object, section, disabled_section] {
+        MmapAllocator::deallocate(ptr);
+    }
+}
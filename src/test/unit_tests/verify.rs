@@ -0,0 +1,189 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::{
+    bump::{BumpHeap, BumpMemoryBlockHeader},
+    error::AllocError,
+    mmap::{MmapHeap, MmapMemorySectionHeader},
+    source::simulated::SimulatedSource,
+    verify::HeapError,
+};
+
+#[test]
+fn test_bump_heap_verify() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    heap.set_verify_after_operations(true);
+
+    let blocks = (1..=16)
+        .map(|size| heap.qualloc::<u8>(size * 8).unwrap())
+        .collect::<Vec<_>>();
+
+    for block in blocks.iter().step_by(2) {
+        heap.qudelloc(*block);
+    }
+
+    heap.qualloc::<u8>(200).unwrap();
+
+    assert_eq!(heap.verify(), Ok(()));
+}
+
+#[test]
+fn test_bump_heap_merge_keeps_blocks_apart() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+    let first_block = heap.qualloc::<u8>(64).unwrap();
+    let second_block = heap.qualloc::<u8>(16).unwrap();
+    let _third_block = heap.qualloc::<u8>(8).unwrap();
+
+    heap.qudelloc(first_block);
+    heap.qudelloc(second_block);
+
+    /*
+     * The merged block must end where the third block starts
+     */
+    let merged_block = heap.qualloc::<u8>(72).unwrap();
+
+    assert_eq!(merged_block, first_block);
+    assert_eq!(
+        heap.snapshot()[0].size,
+        64 + 16 + BumpMemoryBlockHeader::size() as usize
+    );
+    assert_eq!(heap.verify(), Ok(()));
+}
+
+#[test]
+fn test_bump_heap_verify_finds_corruption() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+    let first_block = heap.qualloc::<u8>(16).unwrap();
+    let second_block = heap.qualloc::<u8>(16).unwrap();
+    let first_header = unsafe { (first_block as *mut BumpMemoryBlockHeader).sub(1) };
+    let second_header = unsafe { (second_block as *mut BumpMemoryBlockHeader).sub(1) };
+
+    unsafe { (*first_header).size = 13 };
+    assert_eq!(
+        heap.verify(),
+        Err(HeapError::UnalignedSize {
+            address: first_header as usize,
+            size: 13,
+        })
+    );
+
+    unsafe { (*first_header).size = 64 };
+    assert_eq!(
+        heap.verify(),
+        Err(HeapError::Overlap {
+            address: second_header as usize,
+            previous: first_header as usize,
+        })
+    );
+
+    unsafe {
+        (*first_header).size = 16;
+        (*second_header).prev = None;
+    }
+    assert_eq!(
+        heap.verify(),
+        Err(HeapError::BrokenLink {
+            address: first_header as usize,
+            next: second_header as usize,
+        })
+    );
+}
+
+#[test]
+fn test_mmap_heap_verify() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+    heap.set_verify_after_operations(true);
+
+    let first_section = heap.allocate::<u8>(1000).unwrap();
+    let second_section = heap.allocate::<u8>(5000).unwrap();
+    heap.deallocate(first_section);
+    let third_section = heap.allocate::<u8>(500).unwrap();
+
+    assert_eq!(heap.verify(), Ok(()));
+
+    heap.deallocate(second_section);
+    heap.deallocate(third_section);
+
+    assert_eq!(heap.verify(), Ok(()));
+}
+
+#[test]
+fn test_mmap_heap_verify_finds_corruption() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+
+    let section = heap.allocate::<u8>(1000).unwrap();
+    let header = unsafe { (section as *mut MmapMemorySectionHeader).sub(1) };
+    let region = heap.snapshot()[0];
+
+    unsafe { (*header).size = 8000 };
+    assert_eq!(
+        heap.verify(),
+        Err(HeapError::OutOfRegion {
+            region: region.address,
+            section: header as usize,
+        })
+    );
+
+    unsafe { (*header).is_free = true };
+    assert!(matches!(heap.verify(), Err(HeapError::OutOfRegion { .. })));
+
+    /*
+     * The header of a section placed after it would be misaligned
+     */
+    unsafe { (*header).size = 1001 };
+    assert_eq!(
+        heap.verify(),
+        Err(HeapError::UnalignedSize {
+            address: header as usize,
+            size: 1001,
+        })
+    );
+
+    unsafe { (*header).size = 1000 };
+    assert_eq!(
+        heap.verify(),
+        Err(HeapError::SpaceAvailableMismatch {
+            region: region.address,
+            space_available: region.size - 1000 - MmapMemorySectionHeader::size(),
+            expected: region.size,
+        })
+    );
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "bump heap is corrupted")]
+fn test_verify_after_operations_panics_on_corruption() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+    heap.set_verify_after_operations(true);
+
+    let block = heap.qualloc::<u8>(16).unwrap();
+    let last_block = heap.qualloc::<u8>(16).unwrap();
+    unsafe { (*(block as *mut BumpMemoryBlockHeader).sub(1)).size = 13 };
+
+    heap.qudelloc(last_block);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn test_verify_after_operations_releases_the_lock() {
+    /*
+     * The panic happens after releasing the heap lock, so the heap isn't poisoned for the other callers
+     */
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+    heap.set_verify_after_operations(true);
+
+    let section = heap.allocate::<u8>(1000).unwrap();
+    let last_section = heap.allocate::<u8>(1000).unwrap();
+    unsafe { (*(section as *mut MmapMemorySectionHeader).sub(1)).size = 1001 };
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| heap.deallocate(last_section)));
+
+    assert!(result.is_err());
+    assert!(!heap.memory.is_poisoned());
+    assert!(matches!(
+        heap.allocate::<u8>(16),
+        Err(AllocError::CorruptedHeap(HeapError::UnalignedSize { .. }))
+    ));
+}
@@ -0,0 +1,72 @@
+use crate::{
+    bump::{BumpHeap, BumpMemoryBlockHeader, allocator::BumpAllocator, globals::bump_memory},
+    mmap::utils::get_page_size,
+    source::{system::SystemBreakSource, virtual_break::VirtualBreakSource},
+    test::GLOBAL_HEAP_LOCK,
+};
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_virtual_break_commits_and_releases_pages() {
+    let page_size = get_page_size();
+    let heap = BumpHeap::new(VirtualBreakSource::new(64 * page_size));
+
+    let first_block = heap.qualloc::<u8>(128).unwrap();
+    let big_block = heap.qualloc::<u8>(3 * page_size as i32).unwrap();
+
+    assert_eq!(
+        big_block as usize,
+        first_block as usize + 128 + BumpMemoryBlockHeader::size() as usize
+    );
+
+    /*
+     * The committed pages must be writable
+     */
+    unsafe { big_block.write_bytes(0xab, 3 * page_size) };
+    assert_eq!(
+        heap.memory.lock().unwrap().source.committed(),
+        4 * page_size
+    );
+
+    heap.qudelloc(big_block);
+    assert_eq!(heap.memory.lock().unwrap().source.committed(), page_size);
+
+    heap.qudelloc(first_block);
+    assert_eq!(heap.memory.lock().unwrap().source.committed(), 0);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_virtual_break_reserve_exhausted() {
+    let page_size = get_page_size();
+    let heap = BumpHeap::new(VirtualBreakSource::new(2 * page_size));
+
+    assert!(heap.qualloc::<u8>(page_size as i32).is_ok());
+    assert!(heap.qualloc::<u8>(2 * page_size as i32).is_err());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_bump_allocator_virtual_break_mode() {
+    let _heap_lock = GLOBAL_HEAP_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    assert!(BumpAllocator::use_virtual_break(1024 * 1024));
+
+    let block = BumpAllocator::qualloc::<u64>(64).unwrap();
+    unsafe { *block = 42 };
+
+    match &bump_memory.memory.lock().unwrap().source {
+        SystemBreakSource::Virtual(source) => assert!(source.committed() > 0),
+        SystemBreakSource::Sbrk(_) => panic!("Bump allocator must use the virtual break"),
+    }
+
+    assert!(
+        !BumpAllocator::use_program_break(),
+        "Break can't be changed while the heap has blocks"
+    );
+
+    BumpAllocator::qudelloc(block);
+    assert!(BumpAllocator::use_program_break());
+}
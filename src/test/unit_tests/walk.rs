@@ -0,0 +1,83 @@
+use crate::{
+    bump::{BumpHeap, BumpMemoryBlockHeader},
+    mmap::{MmapHeap, MmapMemoryRegion, MmapMemorySectionHeader},
+    source::simulated::SimulatedSource,
+    walk::HeapEntryKind,
+};
+
+#[test]
+fn test_bump_heap_walk() {
+    let heap = BumpHeap::new(SimulatedSource::new(64 * 1024, 0));
+
+    let first_block = heap.qualloc::<u8>(16).unwrap();
+    let second_block = heap.qualloc::<u8>(32).unwrap();
+    let third_block = heap.qualloc::<u8>(64).unwrap();
+    heap.qudelloc(second_block);
+
+    let blocks = heap.snapshot();
+
+    assert_eq!(blocks.len(), 3);
+    assert!(
+        blocks
+            .iter()
+            .all(|block| block.kind == HeapEntryKind::Block)
+    );
+    assert!(
+        blocks
+            .iter()
+            .all(|block| block.header_size == BumpMemoryBlockHeader::size() as usize)
+    );
+    assert_eq!(
+        blocks
+            .iter()
+            .map(|block| (block.usr_address, block.size, block.is_free))
+            .collect::<Vec<_>>(),
+        vec![
+            (first_block as usize, 16, false),
+            (second_block as usize, 32, true),
+            (third_block as usize, 64, false),
+        ]
+    );
+
+    /*
+     * The walker releases the lock when it's dropped, so the heap can be used again
+     */
+    assert_eq!(heap.walk().filter(|block| block.is_free).count(), 1);
+    heap.qudelloc(third_block);
+    assert_eq!(heap.walk().count(), 2);
+}
+
+#[test]
+fn test_mmap_heap_walk() {
+    let heap = MmapHeap::new(SimulatedSource::new(0, 16 * 4096));
+
+    let first_section = heap.allocate::<u8>(1000).unwrap();
+    let second_section = heap.allocate::<u8>(5000).unwrap();
+
+    let entries = heap.snapshot();
+    let kinds = entries.iter().map(|entry| entry.kind).collect::<Vec<_>>();
+
+    assert_eq!(
+        kinds,
+        vec![
+            HeapEntryKind::Region,
+            HeapEntryKind::Section,
+            HeapEntryKind::Region,
+            HeapEntryKind::Section,
+        ]
+    );
+
+    let (first_region, second_region) = (entries[0], entries[2]);
+
+    assert_eq!(first_region.header_size, MmapMemoryRegion::size());
+    assert_eq!(first_region.size, 4096 - MmapMemoryRegion::size());
+    assert!(!first_region.is_free);
+
+    assert_eq!(entries[1].usr_address, first_section as usize);
+    assert_eq!(entries[1].size, 1000);
+    assert_eq!(entries[1].region, Some(first_region.address));
+    assert_eq!(entries[1].header_size, MmapMemorySectionHeader::size());
+
+    assert_eq!(entries[3].usr_address, second_section as usize);
+    assert_eq!(entries[3].region, Some(second_region.address));
+}
@@ -0,0 +1,24 @@
+#![allow(non_upper_case_globals)]
+
+use std::sync::{
+    Mutex,
+    atomic::{AtomicBool, AtomicU16},
+};
+
+use lazy_static::lazy_static;
+
+use super::recorder::TraceState;
+
+/*
+ * True while a trace is recorded, the allocators don't take the trace lock when it's false
+ */
+pub static trace_enabled: AtomicBool = AtomicBool::new(false);
+
+/*
+ * Last thread id given to a thread that recorded an event
+ */
+pub static last_thread_id: AtomicU16 = AtomicU16::new(0);
+
+lazy_static! {
+    pub static ref trace_state: Mutex<Option<TraceState>> = Mutex::new(None);
+}
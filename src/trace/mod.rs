@@ -0,0 +1,129 @@
+use std::{
+    io::{self, Read},
+    time::Duration,
+};
+
+pub mod globals;
+pub mod recorder;
+pub mod replay;
+
+/*
+ * First bytes of a trace file, the last byte is the version of the format
+ */
+pub const TRACE_MAGIC: [u8; 8] = *b"QUTRACE1";
+
+pub const TRACE_EVENT_SIZE: usize = 24;
+
+/**
+ * Operation of a trace event, it says which allocator API was called
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TraceOp {
+    // BumpAllocator::qualloc
+    BumpAlloc,
+    // BumpAllocator::qudelloc
+    BumpFree,
+    // MmapAllocator::allocate
+    MmapAlloc,
+    // MmapAllocator::deallocate
+    MmapFree,
+}
+
+impl TraceOp {
+    pub fn is_allocation(&self) -> bool {
+        matches!(self, TraceOp::BumpAlloc | TraceOp::MmapAlloc)
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(TraceOp::BumpAlloc),
+            1 => Some(TraceOp::BumpFree),
+            2 => Some(TraceOp::MmapAlloc),
+            3 => Some(TraceOp::MmapFree),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * An allocation or deallocation of a trace
+ *
+ * Pointers are replaced by ids, an id is given on allocation and it's the same for its deallocation, so a
+ * trace can be replayed at other addresses. Thread ids are given in the order threads are seen, from 1
+ *
+ * A trace file is TRACE_MAGIC followed by the events, every event is encoded in TRACE_EVENT_SIZE bytes in
+ * little endian:
+ *
+ * | op: u8 | log2(align): u8 | thread_id: u16 | ptr_id: u32 | size: u64 | timestamp in ns: u64 |
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub op: TraceOp,
+    // Size asked by the user, it's 0 for deallocations
+    pub size: u64,
+    pub align: usize,
+    pub ptr_id: u32,
+    pub thread_id: u16,
+    // Time since the recording started
+    pub timestamp: Duration,
+}
+
+impl TraceEvent {
+    pub fn encode(&self) -> [u8; TRACE_EVENT_SIZE] {
+        let mut bytes = [0u8; TRACE_EVENT_SIZE];
+
+        bytes[0] = self.op as u8;
+        bytes[1] = self.align.max(1).trailing_zeros() as u8;
+        bytes[2..4].copy_from_slice(&self.thread_id.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.ptr_id.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.size.to_le_bytes());
+        bytes[16..24].copy_from_slice(&(self.timestamp.as_nanos() as u64).to_le_bytes());
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; TRACE_EVENT_SIZE]) -> Option<Self> {
+        Some(Self {
+            op: TraceOp::from_u8(bytes[0])?,
+            align: 1usize.checked_shl(bytes[1] as u32)?,
+            thread_id: u16::from_le_bytes(bytes[2..4].try_into().ok()?),
+            ptr_id: u32::from_le_bytes(bytes[4..8].try_into().ok()?),
+            size: u64::from_le_bytes(bytes[8..16].try_into().ok()?),
+            timestamp: Duration::from_nanos(u64::from_le_bytes(bytes[16..24].try_into().ok()?)),
+        })
+    }
+}
+
+fn invalid_trace(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/**
+ * Reads all the events of a trace.
+ *
+ * @return The events, or an InvalidData error if the reader doesn't have a trace.
+ */
+pub fn read_trace(mut reader: impl Read) -> io::Result<Vec<TraceEvent>> {
+    let mut magic = [0u8; TRACE_MAGIC.len()];
+
+    reader.read_exact(&mut magic)?;
+
+    if magic != TRACE_MAGIC {
+        return Err(invalid_trace("the file isn't a qualloc trace"));
+    }
+
+    let mut data = Vec::new();
+
+    reader.read_to_end(&mut data)?;
+
+    /*
+     * A trace of a process that was killed can end in the middle of an event, that event is dropped
+     */
+    data.chunks_exact(TRACE_EVENT_SIZE)
+        .map(|chunk| {
+            TraceEvent::decode(chunk.try_into().unwrap())
+                .ok_or_else(|| invalid_trace("the trace haves an invalid event"))
+        })
+        .collect()
+}
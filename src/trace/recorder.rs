@@ -0,0 +1,165 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::atomic::Ordering,
+    time::Instant,
+};
+
+use super::{
+    TRACE_MAGIC, TraceEvent, TraceOp,
+    globals::{last_thread_id, trace_enabled, trace_state},
+};
+
+thread_local! {
+    static THREAD_ID: Cell<u16> = const { Cell::new(0) };
+}
+
+/**
+ * State of the trace that is being recorded, the ids of the live pointers are kept until they are deallocated
+ */
+pub struct TraceState {
+    writer: BufWriter<File>,
+    started: Instant,
+    ids: HashMap<usize, u32>,
+    next_id: u32,
+    // First error found while writing, it's returned by TraceRecorder::stop
+    error: Option<io::Error>,
+}
+
+impl TraceState {
+    fn write(&mut self, op: TraceOp, size: usize, align: usize, ptr_id: u32) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let event = TraceEvent {
+            op,
+            size: size as u64,
+            align,
+            ptr_id,
+            thread_id: thread_id(),
+            timestamp: self.started.elapsed(),
+        };
+
+        if let Err(error) = self.writer.write_all(&event.encode()) {
+            self.error = Some(error);
+        }
+    }
+}
+
+/*
+ * Gets the id of the current thread in the trace, ids are given the first time a thread records an event
+ */
+fn thread_id() -> u16 {
+    THREAD_ID.with(|id| {
+        if id.get() == 0 {
+            id.set(
+                last_thread_id
+                    .fetch_add(1, Ordering::Relaxed)
+                    .wrapping_add(1),
+            );
+        }
+
+        id.get()
+    })
+}
+
+/**
+ * Recorder of the allocations and deallocations of BumpAllocator and MmapAllocator in a trace file, the
+ * trace can be replayed later with qualloc-replay for tuning the allocators without running the program
+ *
+ * @note Events are written from the allocator facades after the allocation and before the deallocation, so
+ * the order of the events of a pointer is the real one even with many threads.
+ * @warning Recording takes a lock on every allocation and deallocation, so it's slower than the allocators.
+ */
+pub struct TraceRecorder {}
+
+impl TraceRecorder {
+    /**
+     * Starts recording to a new file, the trace that was being recorded is finished.
+     *
+     * @param path The path of the trace file, it's truncated if it exists.
+     */
+    pub fn start(path: impl AsRef<Path>) -> io::Result<()> {
+        Self::stop()?;
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(&TRACE_MAGIC)?;
+
+        *trace_state.lock().unwrap() = Some(TraceState {
+            writer,
+            started: Instant::now(),
+            ids: HashMap::new(),
+            next_id: 1,
+            error: None,
+        });
+        trace_enabled.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /**
+     * Stops recording and flushes the trace file.
+     *
+     * @return The first error found while writing the trace.
+     */
+    pub fn stop() -> io::Result<()> {
+        trace_enabled.store(false, Ordering::SeqCst);
+
+        let Some(mut state) = trace_state.lock().unwrap().take() else {
+            return Ok(());
+        };
+
+        match state.error.take() {
+            Some(error) => Err(error),
+            None => state.writer.flush(),
+        }
+    }
+
+    pub fn is_recording() -> bool {
+        trace_enabled.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Records an allocation that was done, failed allocations aren't recorded.
+     */
+    pub fn record_allocation(op: TraceOp, usr_address: usize, size: usize, align: usize) {
+        if !Self::is_recording() {
+            return;
+        }
+
+        let mut state = trace_state.lock().unwrap();
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+
+        let ptr_id = state.next_id;
+
+        state.next_id = state.next_id.wrapping_add(1).max(1);
+        state.ids.insert(usr_address, ptr_id);
+        state.write(op, size, align, ptr_id);
+    }
+
+    /**
+     * Records a deallocation that is going to be done, pointers allocated before starting the recording
+     * aren't recorded.
+     */
+    pub fn record_free(op: TraceOp, usr_address: usize) {
+        if !Self::is_recording() {
+            return;
+        }
+
+        let mut state = trace_state.lock().unwrap();
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+
+        if let Some(ptr_id) = state.ids.remove(&usr_address) {
+            state.write(op, 0, 1, ptr_id);
+        }
+    }
+}
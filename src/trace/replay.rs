@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bump::BumpHeap, error::AllocError, mmap::MmapHeap, placement::PlacementPolicy,
+    source::MemorySource, stats::Stats,
+};
+
+use super::TraceEvent;
+
+/**
+ * A heap where a trace can be replayed, the alignment of an allocation is given at runtime, so it's
+ * served with the integer type of that alignment
+ */
+pub trait ReplayHeap {
+    fn allocate(&self, size: usize, align: usize) -> Result<usize, AllocError>;
+
+    fn deallocate(&self, usr_address: usize);
+
+    fn stats(&self) -> Stats;
+
+    fn set_placement_policy(&self, policy: PlacementPolicy);
+}
+
+impl<S: MemorySource> ReplayHeap for BumpHeap<S> {
+    fn allocate(&self, size: usize, align: usize) -> Result<usize, AllocError> {
+        let size = i32::try_from(size).map_err(|_| AllocError::SizeOverflow { size })?;
+
+        match align {
+            1 => self.qualloc::<u8>(size).map(|ptr| ptr as usize),
+            2 => self.qualloc::<u16>(size).map(|ptr| ptr as usize),
+            4 => self.qualloc::<u32>(size).map(|ptr| ptr as usize),
+            8 => self.qualloc::<u64>(size).map(|ptr| ptr as usize),
+            _ => self.qualloc::<u128>(size).map(|ptr| ptr as usize),
+        }
+    }
+
+    fn deallocate(&self, usr_address: usize) {
+        self.qudelloc(usr_address as *const u8)
+    }
+
+    fn stats(&self) -> Stats {
+        BumpHeap::stats(self)
+    }
+
+    fn set_placement_policy(&self, policy: PlacementPolicy) {
+        self.placement.set_policy(policy)
+    }
+}
+
+impl<S: MemorySource> ReplayHeap for MmapHeap<S> {
+    fn allocate(&self, size: usize, align: usize) -> Result<usize, AllocError> {
+        match align {
+            1 => MmapHeap::allocate::<u8>(self, size).map(|ptr| ptr as usize),
+            2 => MmapHeap::allocate::<u16>(self, size).map(|ptr| ptr as usize),
+            4 => MmapHeap::allocate::<u32>(self, size).map(|ptr| ptr as usize),
+            8 => MmapHeap::allocate::<u64>(self, size).map(|ptr| ptr as usize),
+            _ => MmapHeap::allocate::<u128>(self, size).map(|ptr| ptr as usize),
+        }
+    }
+
+    fn deallocate(&self, usr_address: usize) {
+        MmapHeap::deallocate(self, usr_address as *const u8)
+    }
+
+    fn stats(&self) -> Stats {
+        MmapHeap::stats(self)
+    }
+
+    fn set_placement_policy(&self, policy: PlacementPolicy) {
+        self.placement.set_policy(policy)
+    }
+}
+
+/**
+ * Result of replaying a trace
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayReport {
+    // Time spent inside the heap operations, the bookkeeping of the replay isn't counted
+    pub elapsed: Duration,
+    pub allocations: usize,
+    pub frees: usize,
+    pub failed_allocations: usize,
+    // Highest fragmentation of the stats taken during the replay
+    pub max_fragmentation: f64,
+    // Stats of the heap after the last event, before freeing the pointers that are still alive
+    pub final_stats: Stats,
+}
+
+/**
+ * Replays the events of a trace over a heap, the pointers that are alive at the end are deallocated.
+ *
+ * @param stats_interval Number of events between two stats used for the fragmentation, 0 only takes them at
+ * the end, taking stats walks the heap so it isn't counted in the elapsed time.
+ */
+pub fn replay(
+    events: &[TraceEvent],
+    heap: &impl ReplayHeap,
+    stats_interval: usize,
+) -> ReplayReport {
+    let mut report = ReplayReport::default();
+    let mut pointers: HashMap<u32, usize> = HashMap::new();
+
+    for (index, event) in events.iter().enumerate() {
+        if event.op.is_allocation() {
+            let started = Instant::now();
+            let usr_address = heap.allocate(event.size as usize, event.align);
+
+            report.elapsed += started.elapsed();
+
+            match usr_address {
+                Ok(usr_address) => {
+                    pointers.insert(event.ptr_id, usr_address);
+                    report.allocations += 1;
+                }
+                Err(_) => report.failed_allocations += 1,
+            }
+        } else if let Some(usr_address) = pointers.remove(&event.ptr_id) {
+            let started = Instant::now();
+
+            heap.deallocate(usr_address);
+            report.elapsed += started.elapsed();
+            report.frees += 1;
+        }
+
+        if stats_interval != 0 && (index + 1) % stats_interval == 0 {
+            report.max_fragmentation = report.max_fragmentation.max(heap.stats().fragmentation);
+        }
+    }
+
+    report.final_stats = heap.stats();
+    report.max_fragmentation = report
+        .max_fragmentation
+        .max(report.final_stats.fragmentation);
+
+    for usr_address in pointers.into_values() {
+        heap.deallocate(usr_address);
+    }
+
+    report
+}
@@ -0,0 +1,170 @@
+use std::{ffi::CStr, sync::Once};
+
+use crate::{bump::globals::bump_memory, mmap::globals::mmap_memory, placement::PlacementPolicy};
+
+/*
+ * Environment variable read at the first allocation, for example
+ * QUALLOC_OPTIONS=placement=best-fit,trim_threshold=128k,mmap.min_region_size=1m,canaries=on
+ */
+const OPTIONS_VARIABLE: &CStr = c"QUALLOC_OPTIONS";
+
+static LOAD_ENV_OPTIONS: Once = Once::new();
+
+/**
+ * Setting of a heap that can be changed at runtime, like the parameters of mallopt
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tunable {
+    // A free block at the end of the bump heap or a free region smaller than this is kept for the next
+    // allocations instead of being given back, 0 gives back everything
+    TrimThreshold(usize),
+    // Sections of at least this size get a region of their own in the mmap heap
+    MmapThreshold(usize),
+    // Minimum size of the regions of the mmap heap, so small sections share the regions
+    MinRegionSize(usize),
+    // Bytes of user data that the quarantine can keep
+    QuarantineSize(usize),
+    Placement(PlacementPolicy),
+    Canaries(bool),
+    // Freed blocks are kept in the quarantine, so they aren't reused until other blocks are freed after them
+    Quarantine(bool),
+    // The blocks in the quarantine are poisoned and their pattern is checked when they leave it, so writes
+    // after free are detected, it's on by default
+    Poisoning(bool),
+    // Allocations of MmapAllocator up to SmallMaxSize are served by the lock-free small allocator, it's on
+    // by default, BumpAllocator never uses it
+    SmallAllocator(bool),
+    // Biggest size served by the small allocator, it's clamped to SMALL_MAX_SIZE
+    SmallMaxSize(usize),
+    // Runs verify after every operation, only in debug builds
+    VerifyAfterOperations(bool),
+}
+
+/**
+ * Heaps that an option of QUALLOC_OPTIONS applies to, options without a bump. or mmap. prefix apply to both
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptionTarget {
+    Both,
+    Bump,
+    Mmap,
+}
+
+impl OptionTarget {
+    pub fn includes_bump(&self) -> bool {
+        *self != OptionTarget::Mmap
+    }
+
+    pub fn includes_mmap(&self) -> bool {
+        *self != OptionTarget::Bump
+    }
+}
+
+/**
+ * Parses a list of options separated by commas, every option is key=value with an optional bump. or mmap.
+ * prefix in the key.
+ *
+ * Sizes accept a k, m or g suffix, switches accept on, off, true, false, 1 and 0, and the placement accepts
+ * first-fit and best-fit.
+ *
+ * @return An iterator over the options, the options that can't be parsed are given back as an error with
+ * their text.
+ *
+ * @note The parser doesn't allocate, so it can run inside the first allocation.
+ */
+pub fn parse_options(options: &str) -> impl Iterator<Item = Result<(OptionTarget, Tunable), &str>> {
+    options
+        .split(',')
+        .map(str::trim)
+        .filter(|option| !option.is_empty())
+        .map(|option| parse_option(option).ok_or(option))
+}
+
+fn parse_option(option: &str) -> Option<(OptionTarget, Tunable)> {
+    let (key, value) = option.split_once('=')?;
+    let (target, key) = match key.trim().split_once('.') {
+        Some(("bump", key)) => (OptionTarget::Bump, key),
+        Some(("mmap", key)) => (OptionTarget::Mmap, key),
+        Some(_) => return None,
+        None => (OptionTarget::Both, key.trim()),
+    };
+    let value = value.trim();
+
+    let tunable = match key {
+        "trim_threshold" => Tunable::TrimThreshold(parse_size(value)?),
+        "mmap_threshold" => Tunable::MmapThreshold(parse_size(value)?),
+        "min_region_size" => Tunable::MinRegionSize(parse_size(value)?),
+        "quarantine_size" => Tunable::QuarantineSize(parse_size(value)?),
+        "placement" => Tunable::Placement(match value {
+            "first-fit" => PlacementPolicy::FirstFit,
+            "best-fit" => PlacementPolicy::BestFit,
+            _ => return None,
+        }),
+        "canaries" => Tunable::Canaries(parse_switch(value)?),
+        "quarantine" => Tunable::Quarantine(parse_switch(value)?),
+        "poisoning" => Tunable::Poisoning(parse_switch(value)?),
+        "small_allocator" => Tunable::SmallAllocator(parse_switch(value)?),
+        "small_max_size" => Tunable::SmallMaxSize(parse_size(value)?),
+        "verify" => Tunable::VerifyAfterOperations(parse_switch(value)?),
+        _ => return None,
+    };
+
+    Some((target, tunable))
+}
+
+fn parse_size(value: &str) -> Option<usize> {
+    let (digits, multiplier) = match value.as_bytes().last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 1024),
+        b'm' | b'M' => (&value[..value.len() - 1], 1024 * 1024),
+        b'g' | b'G' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+    match value {
+        "on" | "true" | "1" => Some(true),
+        "off" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/**
+ * Applies QUALLOC_OPTIONS to the global heaps, only the first call reads the variable.
+ *
+ * @note The variable is read with getenv, so it isn't copied, and the options that can't be parsed or that
+ * the heap doesn't have are printed to stderr.
+ */
+pub fn load_env_options() {
+    LOAD_ENV_OPTIONS.call_once(|| {
+        let variable = unsafe { libc::getenv(OPTIONS_VARIABLE.as_ptr()) };
+
+        if variable.is_null() {
+            return;
+        }
+
+        let Ok(options) = unsafe { CStr::from_ptr(variable) }.to_str() else {
+            eprintln!("QUALLOC_OPTIONS isn't valid UTF-8, it's ignored");
+            return;
+        };
+
+        for option in parse_options(options) {
+            match option {
+                Ok((target, tunable)) => {
+                    /*
+                     * An option for both heaps is valid when one of them haves it
+                     */
+                    let bump = target.includes_bump() && bump_memory.set_tunable(tunable);
+                    let mmap = target.includes_mmap() && mmap_memory.set_tunable(tunable);
+
+                    if !bump && !mmap {
+                        eprintln!("QUALLOC_OPTIONS: {tunable:?} isn't supported by the heap");
+                    }
+                }
+                Err(option) => eprintln!("QUALLOC_OPTIONS: invalid option {option}"),
+            }
+        }
+    });
+}
@@ -1,6 +1,30 @@
+use crate::error::AllocError;
+
 /*
  * Align passed size in 8 bytes multiplier
  */
 pub fn align_up(size: i32) -> i32 {
     (size + (8 - 1)) & !(8 - 1)
 }
+
+/*
+ * Like align_up but returns None if the aligned size doesn't fit in an i32
+ */
+pub fn checked_align_up(size: i32) -> Option<i32> {
+    size.checked_add(8 - 1).map(|size| size & !(8 - 1))
+}
+
+/*
+ * Alignment of the user pointers of the bump and mmap heaps, the headers are multiples of it
+ */
+pub const MAX_ALIGNMENT: usize = 8;
+
+/*
+ * Checks that the heaps can give a pointer aligned for T
+ */
+pub fn check_alignment<T>() -> Result<(), AllocError> {
+    match align_of::<T>() {
+        align if align > MAX_ALIGNMENT => Err(AllocError::InvalidAlignment { align }),
+        _ => Ok(()),
+    }
+}
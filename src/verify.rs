@@ -0,0 +1,76 @@
+use std::fmt;
+
+/**
+ * First violation of the heap invariants found by verify, addresses are the addresses of the headers
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeapError {
+    // The next of a block, section or region doesn't point back to it with its prev
+    BrokenLink {
+        address: usize,
+        next: usize,
+    },
+    // The head of a list has a prev
+    HeadWithPrev {
+        address: usize,
+    },
+    // A block, section or region starts before the end of the previous one
+    Overlap {
+        address: usize,
+        previous: usize,
+    },
+    // The size of a block, section or region isn't aligned
+    UnalignedSize {
+        address: usize,
+        size: usize,
+    },
+    // The space available of a region isn't the space that isn't used by its sections
+    SpaceAvailableMismatch {
+        region: usize,
+        space_available: usize,
+        expected: usize,
+    },
+    // A section is placed outside of the memory of its region
+    OutOfRegion {
+        region: usize,
+        section: usize,
+    },
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeapError::BrokenLink { address, next } => write!(
+                formatter,
+                "{next:#x} is the next of {address:#x} but its prev doesn't point to {address:#x}"
+            ),
+            HeapError::HeadWithPrev { address } => {
+                write!(
+                    formatter,
+                    "{address:#x} is the head of a list but it has a prev"
+                )
+            }
+            HeapError::Overlap { address, previous } => write!(
+                formatter,
+                "{address:#x} starts before the end of the previous entry {previous:#x}"
+            ),
+            HeapError::UnalignedSize { address, size } => {
+                write!(formatter, "{address:#x} has the unaligned size {size}")
+            }
+            HeapError::SpaceAvailableMismatch {
+                region,
+                space_available,
+                expected,
+            } => write!(
+                formatter,
+                "region {region:#x} has {space_available} bytes available but its sections leave {expected} bytes"
+            ),
+            HeapError::OutOfRegion { region, section } => write!(
+                formatter,
+                "section {section:#x} is outside of the region {region:#x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeapError {}
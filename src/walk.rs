@@ -0,0 +1,36 @@
+use crate::tags::AllocTag;
+
+/**
+ * Kind of the structure described by a HeapEntry
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeapEntryKind {
+    // A block of the bump allocator
+    Block,
+    // A region of the mmap allocator
+    Region,
+    // A section inside a region of the mmap allocator
+    Section,
+}
+
+/**
+ * Description of a block, region or section found while walking a heap
+ *
+ * For blocks and sections, size is the space for the user data and usr_address is the pointer given to the
+ * user. For regions, size is the space of the region without its header and usr_address is the first byte
+ * after the header, where the sections are stored
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapEntry {
+    pub kind: HeapEntryKind,
+    pub address: usize,
+    pub usr_address: usize,
+    pub size: usize,
+    pub is_free: bool,
+    // Address of the region that owns a section, it's None for blocks and regions
+    pub region: Option<usize>,
+    // Size of the header stored before the user data
+    pub header_size: usize,
+    // Tag given to a block or section when it was allocated, it's UNTAGGED for regions
+    pub tag: AllocTag,
+}